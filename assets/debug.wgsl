@@ -0,0 +1,81 @@
+// Debug views of the scene, included by shader.wgsl when DEBUG_VIEW is
+// defined along with one of the views below (see `DebugView` in
+// src/debug_view.rs).
+
+// How far away the depth view fades to black, in world units
+#define DEBUG_DEPTH_RANGE 20.0
+
+const WIREFRAME_COLOR: vec3<f32> = vec3<f32>(0.9, 0.9, 0.9);
+
+// (1, 0, 0), (0, 1, 0) or (0, 0, 1) depending on which corner of its
+// triangle a vertex is. That's only true of meshes drawn with every
+// triangle's corners as separate vertices, see `MeshDraw::draw_corners`.
+fn corner_barycentric(vertex_index: u32) -> vec3<f32> {
+    let corner = vertex_index % 3u;
+    return vec3<f32>(
+        select(0.0, 1.0, corner == 0u),
+        select(0.0, 1.0, corner == 1u),
+        select(0.0, 1.0, corner == 2u),
+    );
+}
+
+// A color for every instance, with neighbouring ids far apart.
+fn id_color(id: u32) -> vec3<f32> {
+    var hash = (id + 1u) * 2654435761u;
+    hash = hash ^ (hash >> 16u);
+    let channels = vec3<u32>(hash, hash >> 8u, hash >> 16u) & vec3<u32>(255u);
+    return vec3<f32>(channels) / 255.0 * 0.8 + 0.2;
+}
+
+// What the fragment shader needs for the view, interpolated across each
+// triangle and packed into one vector: the corner's barycentric
+// coordinates for drawn wireframes or its world position otherwise in xyz,
+// and its distance from the camera in w.
+fn debug_varyings(vertex_index: u32, world_position: vec3<f32>, view_depth: f32) -> vec4<f32> {
+#ifdef BARYCENTRIC_WIREFRAME
+    return vec4<f32>(corner_barycentric(vertex_index), view_depth);
+#else
+    return vec4<f32>(world_position, view_depth);
+#endif
+}
+
+fn debug_color(tex_coords: vec2<f32>, debug: vec4<f32>, instance: u32) -> vec4<f32> {
+    // Magenta unless one of the views below is defined
+    var color = vec4<f32>(1.0, 0.0, 1.0, 1.0);
+#ifdef DEBUG_WIREFRAME
+#ifdef BARYCENTRIC_WIREFRAME
+    // Without line polygon mode, only the pixels about a pixel away from
+    // an edge of their triangle are kept
+    let barycentric = debug.xyz;
+    let edges = smoothstep(vec3<f32>(0.0), fwidth(barycentric) * 1.5, barycentric);
+    if min(min(edges.x, edges.y), edges.z) > 0.5 {
+        discard;
+    }
+#endif
+    color = vec4<f32>(WIREFRAME_COLOR, 1.0);
+#endif
+#ifdef DEBUG_NORMALS
+    // Meshes have no normals, so these are the flat normals of the
+    // triangles, from how the position changes across the screen
+    let normal = normalize(cross(dpdy(debug.xyz), dpdx(debug.xyz)));
+    color = vec4<f32>(normal * 0.5 + 0.5, 1.0);
+#endif
+#ifdef DEBUG_UV_CHECKER
+    // 8x8 squares over the texture, more red to the right (+u) and more
+    // green further down (+v), so stretched and flipped UVs stand out
+    let cells = floor(tex_coords * 8.0);
+    let shade = select(1.0, 0.6, (i32(cells.x) + i32(cells.y)) % 2 != 0);
+    color = vec4<f32>(vec3<f32>(fract(tex_coords), 0.3) * shade, 1.0);
+#endif
+#ifdef DEBUG_DEPTH
+    color = vec4<f32>(vec3<f32>(1.0 - clamp(debug.w / {{DEBUG_DEPTH_RANGE}}, 0.0, 1.0)), 1.0);
+#endif
+#ifdef DEBUG_OVERDRAW
+    // Added up by the pipeline, so about ten layers make full orange
+    color = vec4<f32>(0.1, 0.04, 0.0, 1.0);
+#endif
+#ifdef DEBUG_INSTANCE_ID
+    color = vec4<f32>(id_color(instance), 1.0);
+#endif
+    return color;
+}
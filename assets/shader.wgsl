@@ -0,0 +1,82 @@
+#include "common.wgsl"
+#ifdef DEBUG_VIEW
+#include "debug.wgsl"
+#endif
+
+#ifdef SKINNED
+// One matrix per joint: model-space joint transform * inverse bind matrix
+@group(2) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+#endif
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+#ifdef SKINNED
+    @location(2) joints: vec4<u32>,
+    @location(3) weights: vec4<f32>,
+#endif
+};
+
+struct VertexOutput {
+    // Invariant so outline_mask.wgsl and picking.wgsl, which compute it the
+    // same way, get exactly the same depth
+    @invariant @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+#ifdef DEBUG_VIEW
+    // What the debug view needs, see debug.wgsl
+    @location(1) debug: vec4<f32>,
+    @location(2) @interpolate(flat) instance: u32,
+#endif
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+#ifdef SKINNED
+    // Linear blend skinning
+    let skin_matrix =
+        model.weights.x * joint_matrices[model.joints.x] +
+        model.weights.y * joint_matrices[model.joints.y] +
+        model.weights.z * joint_matrices[model.joints.z] +
+        model.weights.w * joint_matrices[model.joints.w];
+#else
+    let skin_matrix = mat4x4<f32>(
+        vec4<f32>(1.0, 0.0, 0.0, 0.0),
+        vec4<f32>(0.0, 1.0, 0.0, 0.0),
+        vec4<f32>(0.0, 0.0, 1.0, 0.0),
+        vec4<f32>(0.0, 0.0, 0.0, 1.0),
+    );
+#endif
+    let world_position = model_matrix(instance) * skin_matrix * vec4<f32>(model.position, 1.0);
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = camera.view_proj * world_position;
+#ifdef DEBUG_VIEW
+    // The view looks down -z, so that's the distance in front of the camera
+    // whatever the projection
+    let view_depth = -(camera.view * world_position).z;
+    out.debug = debug_varyings(vertex_index, world_position.xyz, view_depth);
+    out.instance = instance_index;
+#endif
+    return out;
+}
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+// Fragment shader
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef DEBUG_VIEW
+    return debug_color(in.tex_coords, in.debug, in.instance);
+#else
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+#endif
+}
@@ -0,0 +1,1026 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+
+use anyhow::{bail, Context, Result};
+use cgmath::prelude::*;
+
+use crate::preprocessor::{Preprocessed, ShaderDefs, ShaderLibrary};
+use crate::raycast::Aabb;
+use crate::reflection::ShaderReflection;
+use crate::{texture, Mesh, Vertex};
+
+/// Where the app's own assets live, relative to the working directory
+/// natively and to the page on the web.
+pub const ASSET_DIR: &str = "assets";
+
+/// The path of one of the app's own assets.
+pub fn asset_path(name: &str) -> PathBuf {
+    Path::new(ASSET_DIR).join(name)
+}
+
+/// `path` without `.` components and with `dir/..` pairs removed, so every
+/// way of writing a path to the same file gives the same key. Doesn't look
+/// at the file system, which there isn't one of on the web, so symbolic
+/// links aren't resolved.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    use std::path::Component;
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            // Going above the root stays at the root
+            Component::ParentDir if normalized.has_root() => {}
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Reads a whole file, from disk natively or with `fetch` on the web.
+pub async fn load_bytes(path: &Path) -> Result<Vec<u8>> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            fetch(&path.to_string_lossy()).await.with_context(|| format!("Couldn't fetch {}", path.display()))
+        } else {
+            std::fs::read(path).with_context(|| format!("Couldn't read {}", path.display()))
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn fetch(url: &str) -> Result<Vec<u8>> {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+
+    let js_error = |value: wasm_bindgen::JsValue| anyhow::anyhow!("{:?}", value);
+    let window = web_sys::window().context("There's no window to fetch from")?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(url))
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    if !response.ok() {
+        bail!("{} {}", response.status(), response.status_text());
+    }
+    let buffer = JsFuture::from(response.array_buffer().map_err(js_error)?).await.map_err(js_error)?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+/// Refers to an asset of type `T` loaded by `Assets`. The asset stays
+/// loaded while any clone of its handle is alive.
+pub struct Handle<T> {
+    index: usize,
+    // Shared with the asset's slot, so the strong count is the number of
+    // handles plus one
+    refs: Arc<()>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            index: self.index,
+            refs: self.refs.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.refs, &other.refs)
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle({})", self.index)
+    }
+}
+
+/// How far along an asset is.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadStatus {
+    /// Being read and decoded in the background, a placeholder (or the
+    /// previous version, when reloading) stands in until it's ready.
+    Loading,
+    Loaded,
+    /// The placeholder, or the previous version, stays until the file
+    /// changes again.
+    Failed(String),
+}
+
+/// Background loads started since the last time nothing was loading, for
+/// loading screens.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LoadProgress {
+    /// Loaded or failed.
+    pub finished: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl LoadProgress {
+    pub fn is_done(&self) -> bool {
+        self.finished == self.total
+    }
+
+    /// From 0 to 1, 1 when nothing is loading.
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.finished as f32 / self.total as f32
+        }
+    }
+}
+
+struct Slot<T> {
+    key: PathBuf,
+    // Tells a reused slot apart from the asset that was loading into it
+    id: u64,
+    // None until a background load finishes
+    asset: Option<T>,
+    status: LoadStatus,
+    refs: Arc<()>,
+}
+
+// Assets of one type, looked up by the path they were loaded from.
+struct AssetStore<T> {
+    slots: Vec<Option<Slot<T>>>,
+    by_key: HashMap<PathBuf, usize>,
+}
+
+impl<T> AssetStore<T> {
+    fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            by_key: HashMap::new(),
+        }
+    }
+
+    fn find(&self, key: &Path) -> Option<Handle<T>> {
+        let index = *self.by_key.get(key)?;
+        let slot = self.slots[index].as_ref()?;
+        Some(Handle {
+            index,
+            refs: slot.refs.clone(),
+            marker: PhantomData,
+        })
+    }
+
+    fn insert(&mut self, key: PathBuf, id: u64, asset: Option<T>) -> Handle<T> {
+        let refs = Arc::new(());
+        let slot = Slot {
+            key: key.clone(),
+            id,
+            status: if asset.is_some() { LoadStatus::Loaded } else { LoadStatus::Loading },
+            asset,
+            refs: refs.clone(),
+        };
+        let index = match self.slots.iter().position(Option::is_none) {
+            Some(index) => {
+                self.slots[index] = Some(slot);
+                index
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        self.by_key.insert(key, index);
+        Handle {
+            index,
+            refs,
+            marker: PhantomData,
+        }
+    }
+
+    fn slot(&self, handle: &Handle<T>) -> &Slot<T> {
+        // A live handle keeps its slot from being freed
+        self.slots[handle.index].as_ref().expect("Asset freed while a handle was alive")
+    }
+
+    fn get(&self, handle: &Handle<T>) -> Option<&T> {
+        self.slot(handle).asset.as_ref()
+    }
+
+    // Gives a slot that's loading for the first time something to stand in
+    // for the asset until it's ready.
+    fn set_placeholder(&mut self, index: usize, id: u64, placeholder: T) {
+        if let Some(slot) = self.slots.get_mut(index).and_then(Option::as_mut).filter(|slot| slot.id == id) {
+            slot.asset.get_or_insert(placeholder);
+        }
+    }
+
+    // Starts loading a new version into a slot, which keeps its current
+    // asset until the new one is ready.
+    fn restart(&mut self, index: usize, id: u64) {
+        if let Some(slot) = self.slots[index].as_mut() {
+            slot.id = id;
+            slot.status = LoadStatus::Loading;
+        }
+    }
+
+    // Stores the outcome of a background load, unless every handle to it was
+    // dropped while it loaded.
+    fn finish(&mut self, index: usize, id: u64, result: std::result::Result<T, String>) {
+        let Some(slot) = self.slots.get_mut(index).and_then(Option::as_mut).filter(|slot| slot.id == id) else {
+            return;
+        };
+        match result {
+            Ok(asset) => {
+                slot.asset = Some(asset);
+                slot.status = LoadStatus::Loaded;
+            }
+            Err(message) => slot.status = LoadStatus::Failed(message),
+        }
+    }
+
+    fn loaded_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.slots.iter_mut().filter_map(|slot| slot.as_mut()?.asset.as_mut())
+    }
+
+    fn loaded(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| Some((index, slot.as_ref()?.asset.as_ref()?)))
+    }
+
+    // Returns the keys of the freed assets.
+    fn free_unused(&mut self) -> Vec<PathBuf> {
+        let mut freed = Vec::new();
+        for slot in self.slots.iter_mut() {
+            if slot.as_ref().is_some_and(|slot| Arc::strong_count(&slot.refs) == 1) {
+                let slot = slot.take().unwrap();
+                self.by_key.remove(&slot.key);
+                freed.push(slot.key);
+            }
+        }
+        freed
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Kind {
+    Texture,
+    Mesh,
+}
+
+// A file to read and decode in the background.
+#[derive(Clone)]
+struct Job {
+    id: u64,
+    index: usize,
+    kind: Kind,
+    path: PathBuf,
+}
+
+// Everything but the GPU upload, which has to happen on the render thread.
+enum Decoded {
+    Texture(image::RgbaImage),
+    Mesh { vertices: Vec<Vertex>, indices: Vec<u16> },
+    /// How big a mesh will be, known from the file before its buffers are
+    /// read. Sent ahead of the mesh itself.
+    MeshBounds(Aabb),
+}
+
+struct Done {
+    job: Job,
+    result: Result<Decoded>,
+}
+
+// Meshes report their bounds to `bounds` as soon as they're known.
+async fn decode(kind: Kind, path: &Path, bounds: impl Fn(Aabb)) -> Result<Decoded> {
+    let bytes = load_bytes(path).await?;
+    match kind {
+        Kind::Texture => {
+            let image = image::load_from_memory(&bytes).with_context(|| format!("Couldn't decode texture {}", path.display()))?;
+            Ok(Decoded::Texture(image.to_rgba8()))
+        }
+        Kind::Mesh => {
+            let (vertices, indices) = load_gltf_mesh(&bytes, path.parent(), bounds)
+                .await
+                .with_context(|| format!("Couldn't load mesh {}", path.display()))?;
+            Ok(Decoded::Mesh { vertices, indices })
+        }
+    }
+}
+
+// Runs jobs away from the render thread: on a few worker threads natively,
+// and as futures on the browser's event loop on the web, where fetching is
+// asynchronous anyway.
+struct Loader {
+    #[cfg(not(target_arch = "wasm32"))]
+    jobs: mpsc::Sender<Job>,
+    #[cfg(target_arch = "wasm32")]
+    done_sender: mpsc::Sender<Done>,
+    done: mpsc::Receiver<Done>,
+}
+
+impl Loader {
+    fn new() -> Self {
+        let (done_sender, done) = mpsc::channel();
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                Self { done_sender, done }
+            } else {
+                let (jobs, receiver) = mpsc::channel::<Job>();
+                let receiver = Arc::new(std::sync::Mutex::new(receiver));
+                let workers = std::thread::available_parallelism().map_or(2, |count| count.get()).min(4);
+                for worker in 0..workers {
+                    let receiver = receiver.clone();
+                    let done_sender = done_sender.clone();
+                    std::thread::Builder::new()
+                        .name(format!("asset loader {}", worker))
+                        .spawn(move || loop {
+                            // Only holds the lock while waiting for a job. Stops
+                            // once the loader is dropped.
+                            let Ok(job) = receiver.lock().unwrap().recv() else {
+                                break;
+                            };
+                            let bounds = |bounds| {
+                                let _ = done_sender.send(Done { job: job.clone(), result: Ok(Decoded::MeshBounds(bounds)) });
+                            };
+                            // Reading files natively never actually waits
+                            let result = pollster::block_on(decode(job.kind, &job.path, bounds));
+                            if done_sender.send(Done { job, result }).is_err() {
+                                break;
+                            }
+                        })
+                        .expect("Couldn't start an asset loader thread");
+                }
+                Self { jobs, done }
+            }
+        }
+    }
+
+    fn start(&self, job: Job) {
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let done_sender = self.done_sender.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let bounds = |bounds| {
+                        let _ = done_sender.send(Done { job: job.clone(), result: Ok(Decoded::MeshBounds(bounds)) });
+                    };
+                    let result = decode(job.kind, &job.path, bounds).await;
+                    let _ = done_sender.send(Done { job, result });
+                });
+            } else {
+                // The workers live as long as the loader
+                let _ = self.jobs.send(job);
+            }
+        }
+    }
+}
+
+/// A compiled variant of a WGSL file.
+pub struct Shader {
+    pub module: wgpu::ShaderModule,
+    pub reflection: ShaderReflection,
+    pub path: PathBuf,
+    pub defs: ShaderDefs,
+    /// `path` and every file it included, any of which changing makes for
+    /// a new version.
+    pub files: Vec<PathBuf>,
+    /// Different for every version of every shader, which is what pipelines
+    /// made from it are cached by.
+    pub id: u64,
+}
+
+// Where a variant is kept, the file's path when it has no definitions.
+fn variant_key(path: &Path, defs: &ShaderDefs) -> PathBuf {
+    if defs.is_empty() {
+        path.to_path_buf()
+    } else {
+        PathBuf::from(format!("{}?{}", path.display(), defs.key()))
+    }
+}
+
+// What naga says went wrong, with the errors that caused it.
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
+
+/// Compiles a preprocessed WGSL variant, reporting errors with the file,
+/// line and column they're at rather than letting wgpu panic on them.
+async fn compile_shader(device: &wgpu::Device, shader: &Preprocessed) -> Result<(wgpu::ShaderModule, ShaderReflection)> {
+    // wgpu compiles with naga too but only panics with the message, so
+    // check the shader first to get the whole diagnostic
+    let module = naga::front::wgsl::parse_str(&shader.source).map_err(|e| {
+        let labels = e.labels().filter_map(|(span, label)| Some((span.to_range()?, label)));
+        anyhow::anyhow!("{}", shader.diagnostic(e.message(), labels))
+    })?;
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| {
+            let labels = e.spans().filter_map(|(span, label)| Some((span.to_range()?, label.as_str())));
+            anyhow::anyhow!("{}", shader.diagnostic(&error_chain(e.as_inner()), labels))
+        })?;
+    // Whatever naga can't know about, like the device's limits
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let compiled = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(&shader.files[0].to_string_lossy()),
+        source: wgpu::ShaderSource::Wgsl(shader.source.as_str().into()),
+    });
+    if let Some(error) = device.pop_error_scope().await {
+        bail!("Couldn't compile shader {}: {}", shader.files[0].display(), error);
+    }
+    Ok((compiled, ShaderReflection::new(module, info)))
+}
+
+/// What changed in one call to `Assets::poll`, so whatever was made from
+/// those assets can be made again.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct AssetChanges {
+    /// Textures that finished loading, invalidating bind groups made with
+    /// their placeholder or previous version.
+    pub textures: usize,
+    /// Meshes that finished loading. Meshes are looked up every frame, so
+    /// these need nothing remade.
+    pub meshes: usize,
+    /// Shaders that were reloaded, invalidating pipelines made with the
+    /// previous version.
+    pub shaders: usize,
+}
+
+/// Loads textures, meshes and shaders by path at runtime, from disk or with
+/// `fetch` on the web.
+///
+/// Textures and meshes are read and decoded in the background and handed
+/// out straight away, drawing as a checkerboard texture or a box until `poll`
+/// uploads them. The box is a unit box until `poll` gets the size of the
+/// mesh, which glTF files give before the rest of the mesh is read.
+///
+/// Loading a path that is already loaded hands out another handle to the
+/// same asset rather than loading it twice. Handles count references, and
+/// `free_unused` drops the assets nothing refers to any more along with
+/// their GPU memory. Handles only work with the `Assets` that made them.
+///
+/// Natively, `watch_files` makes `poll` reload files that change on disk
+/// into the handles that are already out there.
+pub struct Assets {
+    textures: AssetStore<texture::Texture>,
+    meshes: AssetStore<Mesh>,
+    shaders: AssetStore<Shader>,
+    shader_library: ShaderLibrary,
+    placeholder_texture: texture::Texture,
+    placeholder_mesh: Mesh,
+    loader: Loader,
+    next_id: u64,
+    progress: LoadProgress,
+    #[cfg(not(target_arch = "wasm32"))]
+    watcher: Option<crate::watcher::FileWatcher>,
+}
+
+impl Assets {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let (vertices, indices) = box_mesh(&UNIT_BOX);
+        Self {
+            textures: AssetStore::new(),
+            meshes: AssetStore::new(),
+            shaders: AssetStore::new(),
+            shader_library: ShaderLibrary::new(),
+            placeholder_texture: texture::Texture::from_rgba(device, queue, &checkerboard(), Some("placeholder_texture")),
+            placeholder_mesh: Mesh::new(device, "placeholder", &vertices, &indices),
+            loader: Loader::new(),
+            next_id: 0,
+            progress: LoadProgress::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            watcher: None,
+        }
+    }
+
+    /// Starts loading a PNG or JPEG image in the background.
+    pub fn load_texture(&mut self, path: impl AsRef<Path>) -> Handle<texture::Texture> {
+        let path = &normalize_path(path.as_ref());
+        if let Some(handle) = self.textures.find(path) {
+            return handle;
+        }
+        let id = self.start(Kind::Texture, path);
+        let handle = self.textures.insert(path.to_path_buf(), id, None);
+        self.start_job(id, handle.index, Kind::Texture, path);
+        handle
+    }
+
+    /// Starts loading every triangle mesh in the default scene of a `.gltf`
+    /// or `.glb` file as one static mesh, with node transforms applied.
+    pub(crate) fn load_mesh(&mut self, path: impl AsRef<Path>) -> Handle<Mesh> {
+        let path = &normalize_path(path.as_ref());
+        if let Some(handle) = self.meshes.find(path) {
+            return handle;
+        }
+        let id = self.start(Kind::Mesh, path);
+        let handle = self.meshes.insert(path.to_path_buf(), id, None);
+        self.start_job(id, handle.index, Kind::Mesh, path);
+        handle
+    }
+
+    /// Adds a mesh made in code under `key`, or hands out the one already
+    /// there.
+    pub(crate) fn add_mesh(&mut self, key: impl AsRef<Path>, create: impl FnOnce() -> Mesh) -> Handle<Mesh> {
+        let key = key.as_ref();
+        match self.meshes.find(key) {
+            Some(handle) => handle,
+            None => {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.meshes.insert(key.to_path_buf(), id, Some(create()))
+            }
+        }
+    }
+
+    // Counts a new background load, starting over once the last batch is done.
+    fn start(&mut self, kind: Kind, path: &Path) -> u64 {
+        if self.progress.is_done() {
+            self.progress = LoadProgress::default();
+        }
+        self.progress.total += 1;
+        log::info!("Loading {:?} {}", kind, path.display());
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn start_job(&mut self, id: u64, index: usize, kind: Kind, path: &Path) {
+        self.watch(path);
+        self.loader.start(Job {
+            id,
+            index,
+            kind,
+            path: path.to_path_buf(),
+        });
+    }
+
+    fn watch(&mut self, path: &Path) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(watcher) = self.watcher.as_mut() {
+            watcher.watch(path);
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = path;
+    }
+
+    /// Watches every file loaded so far and from now on, reloading it in
+    /// `poll` when it changes. There's no file system to watch on the web.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch_files(&mut self) {
+        let mut watcher = crate::watcher::FileWatcher::new(std::time::Duration::from_millis(500));
+        let files = self.shaders.loaded().flat_map(|(_, shader)| shader.files.iter());
+        for path in self.textures.by_key.keys().chain(self.meshes.by_key.keys()).chain(files) {
+            watcher.watch(path);
+        }
+        self.watcher = Some(watcher);
+    }
+
+    // Reloads whatever was loaded from `path`, returning whether a shader
+    // was, any variant including it counting. Textures and meshes reload in
+    // the background like they loaded, shaders straight away so the
+    // pipelines using them can be remade. The previous version stays when
+    // the new one fails to load.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload(&mut self, device: &wgpu::Device, path: &Path) -> bool {
+        if let Some(&index) = self.textures.by_key.get(path) {
+            let id = self.start(Kind::Texture, path);
+            self.textures.restart(index, id);
+            self.start_job(id, index, Kind::Texture, path);
+        }
+        if let Some(&index) = self.meshes.by_key.get(path) {
+            let id = self.start(Kind::Mesh, path);
+            self.meshes.restart(index, id);
+            self.start_job(id, index, Kind::Mesh, path);
+        }
+        let variants = self
+            .shaders
+            .loaded()
+            .filter(|(_, shader)| shader.files.iter().any(|file| file == path))
+            .map(|(index, shader)| (index, shader.path.clone(), shader.defs.clone()))
+            .collect::<Vec<_>>();
+        if variants.is_empty() {
+            return false;
+        }
+        self.shader_library.forget(path);
+        let mut reloaded = false;
+        for (index, shader_path, defs) in variants {
+            log::info!("Reloading shader {} {}", shader_path.display(), defs.key());
+            let id = self.next_id;
+            self.next_id += 1;
+            self.shaders.restart(index, id);
+            // Reading files and popping error scopes never actually waits natively
+            match pollster::block_on(self.compile_variant(device, &shader_path, defs)) {
+                Ok(shader) => {
+                    // It may include other files now
+                    for file in shader.files.iter() {
+                        self.watch(file);
+                    }
+                    self.shaders.finish(index, id, Ok(shader));
+                    reloaded = true;
+                }
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    log::error!("Keeping the previous version of {}", shader_path.display());
+                    self.shaders.finish(index, id, Err(format!("{:#}", e)));
+                }
+            }
+        }
+        reloaded
+    }
+
+    async fn compile_variant(&mut self, device: &wgpu::Device, path: &Path, defs: ShaderDefs) -> Result<Shader> {
+        self.shader_library.load(path).await?;
+        let preprocessed = self.shader_library.preprocess(path, &defs)?;
+        let (module, reflection) = compile_shader(device, &preprocessed).await?;
+        Ok(Shader {
+            module,
+            reflection,
+            path: path.to_path_buf(),
+            defs,
+            files: preprocessed.files,
+            id: crate::pipeline::unique_id(),
+        })
+    }
+
+    /// Loads, preprocesses and compiles the variant of a WGSL shader for
+    /// `defs`, see `ShaderLibrary` for the directives it can use. Variants
+    /// are kept by the definitions they were made with, so loading the same
+    /// one twice hands out another handle to it. Compile errors are
+    /// reported with naga's diagnostic, pointing at the file and line they
+    /// came from, rather than letting wgpu panic on them.
+    pub async fn load_shader(
+        &mut self,
+        device: &wgpu::Device,
+        path: impl AsRef<Path>,
+        defs: &ShaderDefs,
+    ) -> Result<Handle<Shader>> {
+        let path = &normalize_path(path.as_ref());
+        let key = variant_key(path, defs);
+        if let Some(handle) = self.shaders.find(&key) {
+            return Ok(handle);
+        }
+        let shader = self.compile_variant(device, path, defs.clone()).await?;
+        for file in shader.files.iter() {
+            self.watch(file);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        Ok(self.shaders.insert(key, id, Some(shader)))
+    }
+
+    /// Uploads whatever finished loading in the background since the last
+    /// call, and reloads watched files that changed. Failures are logged and
+    /// keep their placeholder or previous version. Call once a frame.
+    pub fn poll(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> AssetChanges {
+        let mut changes = AssetChanges::default();
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let changed = self.watcher.as_mut().map(|watcher| watcher.changed()).unwrap_or_default();
+            for path in changed {
+                if self.reload(device, &path) {
+                    changes.shaders += 1;
+                }
+            }
+        }
+        while let Ok(Done { job, result }) = self.loader.done.try_recv() {
+            let label = job.path.to_string_lossy();
+            match result {
+                Ok(Decoded::Texture(image)) => {
+                    let texture = texture::Texture::from_rgba(device, queue, &image, Some(&label));
+                    self.textures.finish(job.index, job.id, Ok(texture));
+                    changes.textures += 1;
+                }
+                Ok(Decoded::Mesh { vertices, indices }) => {
+                    let mesh = Mesh::new(device, &label, &vertices, &indices);
+                    self.meshes.finish(job.index, job.id, Ok(mesh));
+                    changes.meshes += 1;
+                }
+                Ok(Decoded::MeshBounds(bounds)) => {
+                    let (vertices, indices) = box_mesh(&bounds);
+                    let placeholder = Mesh::new(device, &format!("{} placeholder", label), &vertices, &indices);
+                    self.meshes.set_placeholder(job.index, job.id, placeholder);
+                    // Still loading
+                    continue;
+                }
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    let message = format!("{:#}", e);
+                    match job.kind {
+                        Kind::Texture => self.textures.finish(job.index, job.id, Err(message)),
+                        Kind::Mesh => self.meshes.finish(job.index, job.id, Err(message)),
+                    }
+                    self.progress.failed += 1;
+                }
+            }
+            self.progress.finished += 1;
+        }
+        changes
+    }
+
+    pub fn progress(&self) -> LoadProgress {
+        self.progress
+    }
+
+    pub fn texture_status(&self, handle: &Handle<texture::Texture>) -> &LoadStatus {
+        &self.textures.slot(handle).status
+    }
+
+    /// The texture, or the checkerboard placeholder while it loads.
+    pub fn texture(&self, handle: &Handle<texture::Texture>) -> &texture::Texture {
+        self.textures.get(handle).unwrap_or(&self.placeholder_texture)
+    }
+
+    /// The mesh, or a placeholder box while it loads.
+    pub(crate) fn mesh(&self, handle: &Handle<Mesh>) -> &Mesh {
+        self.meshes.get(handle).unwrap_or(&self.placeholder_mesh)
+    }
+
+    /// Every mesh, including the placeholders of those still loading.
+    pub(crate) fn meshes_mut(&mut self) -> impl Iterator<Item = &mut Mesh> {
+        self.meshes.loaded_mut().chain(std::iter::once(&mut self.placeholder_mesh))
+    }
+
+    pub fn shader(&self, handle: &Handle<Shader>) -> &Shader {
+        self.shaders.get(handle).expect("Shaders are loaded before they get a handle")
+    }
+
+    /// Every loaded shader variant, as of its latest version.
+    pub fn shaders(&self) -> impl Iterator<Item = &Shader> {
+        self.shaders.loaded().map(|(_, shader)| shader)
+    }
+
+    /// Drops every asset without a live handle, returning how many were
+    /// freed. Loads in flight for dropped assets are ignored when they
+    /// finish.
+    pub fn free_unused(&mut self) -> usize {
+        let mut freed = self.textures.free_unused();
+        freed.extend(self.meshes.free_unused());
+        freed.extend(self.shaders.free_unused());
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(watcher) = self.watcher.as_mut() {
+            for key in freed.iter() {
+                watcher.unwatch(key);
+            }
+        }
+        freed.len()
+    }
+}
+
+// Magenta and black squares, hard to mistake for a real texture.
+fn checkerboard() -> image::RgbaImage {
+    const SIZE: u32 = 64;
+    const SQUARE: u32 = 8;
+    image::RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+        if (x / SQUARE + y / SQUARE).is_multiple_of(2) {
+            image::Rgba([255, 0, 255, 255])
+        } else {
+            image::Rgba([0, 0, 0, 255])
+        }
+    })
+}
+
+const UNIT_BOX: Aabb = Aabb {
+    min: cgmath::Point3::new(-0.5, -0.5, -0.5),
+    max: cgmath::Point3::new(0.5, 0.5, 0.5),
+};
+
+// A box filling `bounds`, facing outwards.
+fn box_mesh(bounds: &Aabb) -> (Vec<Vertex>, Vec<u16>) {
+    let center = bounds.center().to_vec();
+    let size = bounds.max - bounds.min;
+    // Each face's normal and two edges, with edge_u × edge_v = normal so the
+    // corners wind counter-clockwise seen from outside
+    let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]),
+    ];
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for (normal, u, v) in faces {
+        let (normal, u, v) = (
+            cgmath::Vector3::from(normal),
+            cgmath::Vector3::from(u),
+            cgmath::Vector3::from(v),
+        );
+        let base = vertices.len() as u16;
+        for (s, t) in [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)] {
+            let corner = normal * 0.5 + u * s + v * t;
+            vertices.push(Vertex {
+                position: (center + corner.mul_element_wise(size)).into(),
+                tex_coords: [s + 0.5, 0.5 - t],
+            });
+        }
+        indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+    (vertices, indices)
+}
+
+/// Reads every buffer of a glTF file, the binary chunk of a `.glb` or the
+/// files they refer to in `base`.
+pub(crate) async fn load_gltf_buffers(gltf: &gltf::Gltf, base: Option<&Path>) -> Result<Vec<Vec<u8>>> {
+    let mut buffers = Vec::new();
+    for buffer in gltf.buffers() {
+        buffers.push(match buffer.source() {
+            gltf::buffer::Source::Bin => gltf.blob.clone().context("glTF file references a missing binary chunk")?,
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                bail!("Embedded base64 buffers aren't supported, export as .glb or with a separate .bin")
+            }
+            gltf::buffer::Source::Uri(uri) => {
+                load_bytes(&base.map(|base| base.join(uri)).unwrap_or_else(|| uri.into())).await?
+            }
+        });
+    }
+    Ok(buffers)
+}
+
+/// A primitive's indices offset by `first_vertex`, where its `vertex_count`
+/// vertices start. Primitives without indices draw their vertices in order.
+pub(crate) fn gltf_primitive_indices(
+    read: Option<gltf::mesh::util::ReadIndices>,
+    first_vertex: u32,
+    vertex_count: u32,
+) -> Result<Vec<u32>> {
+    let Some(read) = read else {
+        return Ok((first_vertex..first_vertex + vertex_count).collect());
+    };
+    read.into_u32()
+        .map(|index| {
+            if index >= vertex_count {
+                bail!("Mesh primitive uses vertex {} but only has {} vertices", index, vertex_count);
+            }
+            Ok(index + first_vertex)
+        })
+        .collect()
+}
+
+// The triangle primitives in the default scene, with the transforms of the
+// nodes they're in.
+fn gltf_triangles(gltf: &gltf::Gltf) -> Result<Vec<(gltf::Primitive<'_>, cgmath::Matrix4<f32>)>> {
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .context("glTF file has no scenes")?;
+    let mut primitives = Vec::new();
+    let mut nodes = scene
+        .nodes()
+        .map(|node| (node, cgmath::Matrix4::<f32>::identity()))
+        .collect::<Vec<_>>();
+    while let Some((node, parent)) = nodes.pop() {
+        let transform = parent * cgmath::Matrix4::from(node.transform().matrix());
+        nodes.extend(node.children().map(|child| (child, transform)));
+        let Some(mesh) = node.mesh() else {
+            continue;
+        };
+        primitives.extend(
+            mesh.primitives()
+                .filter(|primitive| primitive.mode() == gltf::mesh::Mode::Triangles)
+                .map(|primitive| (primitive, transform)),
+        );
+    }
+    Ok(primitives)
+}
+
+// Meshes report their bounds to `bounds` before their buffers are read,
+// from the minimum and maximum glTF requires of every position accessor.
+async fn load_gltf_mesh(bytes: &[u8], base: Option<&Path>, bounds: impl Fn(Aabb)) -> Result<(Vec<Vertex>, Vec<u16>)> {
+    let gltf = gltf::Gltf::from_slice(bytes)?;
+    let primitives = gltf_triangles(&gltf)?;
+    let extent = primitives.iter().fold(Aabb::EMPTY, |extent, (primitive, transform)| {
+        let corners = primitive.bounding_box();
+        extent.union(&Aabb::from_points([corners.min.into(), corners.max.into()]).transform(transform))
+    });
+    if !primitives.is_empty() {
+        bounds(extent);
+    }
+    let buffers = load_gltf_buffers(&gltf, base).await?;
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for (primitive, transform) in primitives {
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+        let base_vertex = vertices.len();
+        let positions = reader.read_positions().context("Mesh primitive has no positions")?;
+        let mut tex_coords = reader.read_tex_coords(0).map(|coords| coords.into_f32());
+        for position in positions {
+            let position = transform * cgmath::Vector3::from(position).extend(1.0);
+            vertices.push(Vertex {
+                position: position.truncate().into(),
+                tex_coords: tex_coords.as_mut().and_then(Iterator::next).unwrap_or_default(),
+            });
+        }
+        // Meshes are drawn with 16 bit indices
+        if vertices.len() > u16::MAX as usize + 1 {
+            bail!("Meshes can have at most {} vertices", u16::MAX as usize + 1);
+        }
+        let primitive_indices =
+            gltf_primitive_indices(reader.read_indices(), base_vertex as u32, (vertices.len() - base_vertex) as u32)?;
+        indices.extend(primitive_indices.into_iter().map(|index| index as u16));
+    }
+    if indices.is_empty() {
+        bail!("glTF file has no triangle meshes");
+    }
+    Ok((vertices, indices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_paths_match() {
+        let normalized = |path: &str| normalize_path(Path::new(path));
+        assert_eq!(normalized("models/../models/./tree.gltf"), normalized("models/tree.gltf"));
+        assert_eq!(normalized("a/b/../../c.png"), PathBuf::from("c.png"));
+        // Nothing to remove the leading .. against
+        assert_eq!(normalized("../a/./b.png"), PathBuf::from("../a/b.png"));
+        assert_eq!(normalized("/../a.png"), PathBuf::from("/a.png"));
+    }
+
+    #[test]
+    fn placeholder_box_fills_the_bounds() {
+        let bounds = Aabb {
+            min: cgmath::Point3::new(-1.0, 0.0, 2.0),
+            max: cgmath::Point3::new(3.0, 0.5, 2.0),
+        };
+        let (vertices, indices) = box_mesh(&bounds);
+        assert_eq!(Aabb::from_points(vertices.iter().map(|vertex| vertex.position.into())), bounds);
+        assert_eq!(indices.len(), 36);
+    }
+
+    #[test]
+    fn equivalent_paths_find_the_same_asset() {
+        let mut store = AssetStore::<&str>::new();
+        let handle = store.insert(normalize_path(Path::new("models/./tree.gltf")), 1, Some("tree"));
+        let found = store.find(&normalize_path(Path::new("models/../models/tree.gltf"))).expect("tree should be found");
+        assert_eq!(found, handle);
+        assert_eq!(store.get(&found), Some(&"tree"));
+        assert_eq!(store.find(&normalize_path(Path::new("models/bush.gltf"))), None);
+    }
+
+    #[test]
+    fn free_unused_keeps_assets_with_handles() {
+        let mut store = AssetStore::<&str>::new();
+        let kept = store.insert(PathBuf::from("kept.png"), 1, Some("kept"));
+        let dropped = store.insert(PathBuf::from("dropped.png"), 2, Some("dropped"));
+        let cloned = store.insert(PathBuf::from("cloned.png"), 3, None).clone();
+        drop(dropped);
+        assert_eq!(store.free_unused(), vec![PathBuf::from("dropped.png")]);
+        assert_eq!(store.get(&kept), Some(&"kept"));
+        assert_eq!(store.slot(&cloned).status, LoadStatus::Loading);
+        assert_eq!(store.find(Path::new("dropped.png")), None);
+        assert!(store.find(Path::new("cloned.png")).is_some());
+        // Nothing more to free until the handles go
+        assert!(store.free_unused().is_empty());
+        drop((kept, cloned));
+        assert_eq!(store.free_unused().len(), 2);
+        assert_eq!(store.loaded().count(), 0);
+    }
+
+    #[test]
+    fn reused_slots_ignore_stale_loads() {
+        let mut store = AssetStore::<&str>::new();
+        let old = store.insert(PathBuf::from("old.png"), 1, None);
+        let index = old.index;
+        drop(old);
+        store.free_unused();
+        let new = store.insert(PathBuf::from("new.png"), 2, None);
+        assert_eq!(new.index, index, "the freed slot should be reused");
+
+        // The old load finishing late doesn't land in the new asset's slot
+        store.set_placeholder(index, 1, "old placeholder");
+        store.finish(index, 1, Ok("old"));
+        assert_eq!(store.get(&new), None);
+        assert_eq!(store.slot(&new).status, LoadStatus::Loading);
+        store.set_placeholder(index, 2, "placeholder");
+        store.finish(index, 2, Ok("new"));
+        assert_eq!(store.get(&new), Some(&"new"));
+        assert_eq!(store.slot(&new).status, LoadStatus::Loaded);
+
+        // Nor does a reload that was overtaken by another one
+        store.restart(index, 3);
+        store.restart(index, 4);
+        store.finish(index, 3, Err("stale".to_string()));
+        assert_eq!(store.slot(&new).status, LoadStatus::Loading);
+        store.finish(index, 4, Err("broken".to_string()));
+        assert_eq!(store.slot(&new).status, LoadStatus::Failed("broken".to_string()));
+        // The last good version stays
+        assert_eq!(store.get(&new), Some(&"new"));
+    }
+}
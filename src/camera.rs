@@ -0,0 +1,665 @@
+use cgmath::prelude::*;
+
+use crate::input::InputMap;
+
+// We need this for Rust to store our data correctly for the shaders
+#[repr(C)]
+// This is so we can store this in a buffer
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    // We can't use cgmath with bytemuck directly, so we'll have
+    // to convert the Matrix4 into a 4x4 f32 array
+    view_proj: [[f32; 4]; 4],
+    // Just the view, for distances from the camera that don't depend on
+    // the projection
+    view: [[f32; 4]; 4],
+}
+
+impl CameraUniform {
+    pub fn new() -> Self {
+        Self {
+            view_proj: cgmath::Matrix4::identity().into(),
+            view: cgmath::Matrix4::identity().into(),
+        }
+    }
+
+    pub fn update_view_proj(&mut self, camera: &Camera) {
+        self.view_proj = camera.build_view_projection_matrix().into();
+        self.view = camera.build_view_matrix().into();
+    }
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How a camera maps view space onto the screen.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    Perspective { fovy: cgmath::Deg<f32> },
+    /// Perspective with the far plane at infinity and depth running from 1 at
+    /// the near plane to 0 at infinity. Floating point depth is far more
+    /// precise close to 0, so reversing spreads that precision over the
+    /// distance instead of wasting it right in front of the camera.
+    /// `Camera::zfar` is ignored.
+    ReverseZ { fovy: cgmath::Deg<f32> },
+    /// Parallel projection showing `height` world units vertically, the
+    /// width follows from the aspect ratio.
+    Orthographic { height: f32 },
+    /// Asymmetric frustum given by the extents of the near plane, for
+    /// stereo rendering, tiled displays and lens shifts. Ignores the aspect.
+    OffAxis { left: f32, right: f32, bottom: f32, top: f32 },
+}
+
+impl Projection {
+    pub fn is_reverse_z(&self) -> bool {
+        matches!(self, Projection::ReverseZ { .. })
+    }
+
+    /// The depth compare function pipelines drawing with this projection need.
+    pub fn depth_compare(&self) -> wgpu::CompareFunction {
+        if self.is_reverse_z() {
+            wgpu::CompareFunction::Greater
+        } else {
+            wgpu::CompareFunction::Less
+        }
+    }
+
+    /// The value to clear the depth buffer to, i.e. the far plane.
+    pub fn depth_clear_value(&self) -> f32 {
+        if self.is_reverse_z() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+}
+
+pub struct Camera {
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    pub up: cgmath::Vector3<f32>,
+    pub aspect: f32,
+    pub projection: Projection,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+/// Where a camera is and where it looks, without its projection.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraPose {
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    pub up: cgmath::Vector3<f32>,
+}
+
+impl CameraPose {
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            eye: camera.eye,
+            target: camera.target,
+            up: camera.up,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.eye;
+        camera.target = self.target;
+        camera.up = self.up;
+    }
+
+    /// Whether every coordinate is within `epsilon` of `other`'s.
+    pub fn is_near(&self, other: &CameraPose, epsilon: f32) -> bool {
+        use cgmath::AbsDiffEq;
+        self.eye.abs_diff_eq(&other.eye, epsilon)
+            && self.target.abs_diff_eq(&other.target, epsilon)
+            && self.up.abs_diff_eq(&other.up, epsilon)
+    }
+}
+
+// Maps OpenGL's -1..1 depth to wgpu's 0..1 with z' = (z + w) / 2. The
+// arguments are columns, so the last one carries the 0.5 * w.
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+impl Camera {
+    pub fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
+    /// Projection matrix in wgpu's clip space (depth in 0..1).
+    pub fn build_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        match self.projection {
+            Projection::Perspective { fovy } => {
+                OPENGL_TO_WGPU_MATRIX * cgmath::perspective(fovy, self.aspect, self.znear, self.zfar)
+            }
+            Projection::ReverseZ { fovy } => {
+                // Written directly in wgpu's depth range, so no OPENGL_TO_WGPU_MATRIX
+                let f = 1.0 / (cgmath::Rad::from(fovy).0 / 2.0).tan();
+                #[rustfmt::skip]
+                let proj = cgmath::Matrix4::new(
+                    f / self.aspect, 0.0, 0.0, 0.0,
+                    0.0, f, 0.0, 0.0,
+                    0.0, 0.0, 0.0, -1.0,
+                    0.0, 0.0, self.znear, 0.0,
+                );
+                proj
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                OPENGL_TO_WGPU_MATRIX
+                    * cgmath::ortho(-half_width, half_width, -half_height, half_height, self.znear, self.zfar)
+            }
+            Projection::OffAxis { left, right, bottom, top } => {
+                OPENGL_TO_WGPU_MATRIX * cgmath::frustum(left, right, bottom, top, self.znear, self.zfar)
+            }
+        }
+    }
+
+    pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        self.build_projection_matrix() * self.build_view_matrix()
+    }
+
+    /// The vertical field of view, for projections that have one.
+    pub fn fovy(&self) -> Option<cgmath::Deg<f32>> {
+        match self.projection {
+            Projection::Perspective { fovy } | Projection::ReverseZ { fovy } => Some(fovy),
+            _ => None,
+        }
+    }
+
+    /// Sets the vertical field of view; ignored for projections without one.
+    pub fn set_fovy(&mut self, value: cgmath::Deg<f32>) {
+        if let Projection::Perspective { fovy } | Projection::ReverseZ { fovy } = &mut self.projection {
+            *fovy = value;
+        }
+    }
+}
+
+// Stay just short of straight up/down so look_at never sees eye - target
+// parallel to `up`, which would flip the view.
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+/// Orbits the camera around `Camera::target` using spherical coordinates.
+///
+/// Dragging with `orbit_rotate` rotates, `orbit_pan` (or `pan_modifier` +
+/// `orbit_rotate`) pans the target and the `zoom` axis zooms. The
+/// `move_forward` and `move_right` axes zoom and orbit too, as do `look_right`
+/// and `look_up` (a gamepad's right stick by default). On touchscreens one
+/// finger rotates, two fingers pan and pinching zooms. Input sets goal
+/// values which the camera eases towards at a rate set by `damping`.
+pub struct OrbitController {
+    /// Keyboard zoom speed in units per second.
+    pub speed: f32,
+    /// Keyboard and stick orbit speed in radians per second.
+    pub key_rotate_speed: f32,
+    /// Radians per pixel of mouse drag.
+    pub rotate_sensitivity: f32,
+    /// Fraction of the orbit distance moved per pixel of pan.
+    pub pan_sensitivity: f32,
+    /// Fraction of the orbit distance zoomed per scroll line.
+    pub zoom_sensitivity: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// How quickly the camera catches up with input, per second. Zero
+    /// disables smoothing.
+    pub damping: f32,
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+    target: cgmath::Point3<f32>,
+    goal_yaw: f32,
+    goal_pitch: f32,
+    goal_distance: f32,
+    goal_target: cgmath::Point3<f32>,
+    synced: bool,
+    // Whether a mouse button or finger is dragging the orbit around
+    dragging: bool,
+    // (move_forward, move_right) axes
+    movement: (f32, f32),
+    // (look_right, look_up) axes
+    look: (f32, f32),
+}
+
+impl OrbitController {
+    pub fn new(speed: f32) -> Self {
+        Self {
+            speed,
+            key_rotate_speed: 1.5,
+            rotate_sensitivity: 0.005,
+            pan_sensitivity: 0.002,
+            zoom_sensitivity: 0.1,
+            min_distance: 1.0,
+            max_distance: 50.0,
+            damping: 12.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            distance: 1.0,
+            target: cgmath::Point3::origin(),
+            goal_yaw: 0.0,
+            goal_pitch: 0.0,
+            goal_distance: 1.0,
+            goal_target: cgmath::Point3::origin(),
+            synced: false,
+            dragging: false,
+            movement: (0.0, 0.0),
+            look: (0.0, 0.0),
+        }
+    }
+
+    /// Picks up the camera's current position as the orbit, e.g. after the
+    /// camera was moved by something other than this controller.
+    pub fn sync_with(&mut self, camera: &Camera) {
+        let offset = camera.eye - camera.target;
+        let distance = offset.magnitude().max(f32::EPSILON);
+        self.yaw = offset.x.atan2(offset.z);
+        self.pitch = (offset.y / distance).asin().clamp(-MAX_PITCH, MAX_PITCH);
+        self.distance = distance.clamp(self.min_distance, self.max_distance);
+        self.target = camera.target;
+        self.goal_yaw = self.yaw;
+        self.goal_pitch = self.pitch;
+        self.goal_distance = self.distance;
+        self.goal_target = self.target;
+        self.synced = true;
+    }
+
+    /// Reads this frame's input. Pointer drags and scrolling move the goal
+    /// straight away, held axes are applied on every `update_camera`.
+    pub fn process_input(&mut self, input: &InputMap) {
+        self.movement = (input.axis("move_forward"), input.axis("move_right"));
+        self.look = (input.axis("look_right"), input.axis("look_up"));
+        let (dx, dy) = input.cursor_delta();
+        let is_rotating = input.held("orbit_rotate");
+        let is_panning = input.held("orbit_pan");
+        if is_panning || (is_rotating && input.held("pan_modifier")) {
+            self.pan(dx, dy);
+        } else if is_rotating {
+            self.rotate(dx, dy);
+        }
+        self.zoom(input.axis("zoom"));
+
+        let touch = input.touch();
+        self.dragging = is_rotating || is_panning || touch.touch_count() > 0;
+        let (dx, dy) = touch.drag();
+        self.rotate(dx, dy);
+        let (dx, dy) = touch.pan();
+        self.pan(dx, dy);
+        self.pinch(touch.pinch());
+    }
+
+    /// Rotates the orbit by a drag of `dx`, `dy` pixels.
+    pub fn rotate(&mut self, dx: f32, dy: f32) {
+        self.goal_yaw -= dx * self.rotate_sensitivity;
+        self.goal_pitch = (self.goal_pitch + dy * self.rotate_sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    /// Moves the target in the view plane by a drag of `dx`, `dy` pixels.
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        let (right, up) = self.view_axes();
+        let scale = self.pan_sensitivity * self.goal_distance;
+        self.goal_target += (-right * dx + up * dy) * scale;
+    }
+
+    /// Zooms in by `lines` scroll wheel lines, negative to zoom out.
+    pub fn zoom(&mut self, lines: f32) {
+        let factor = (1.0 - self.zoom_sensitivity).powf(lines);
+        self.goal_distance = (self.goal_distance * factor).clamp(self.min_distance, self.max_distance);
+    }
+
+    /// Zooms in as two fingers move `scale` times further apart.
+    pub fn pinch(&mut self, scale: f32) {
+        self.goal_distance = (self.goal_distance / scale).clamp(self.min_distance, self.max_distance);
+    }
+
+    fn view_axes(&self) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        let forward = -spherical_offset(self.goal_yaw, self.goal_pitch);
+        let right = forward.cross(cgmath::Vector3::unit_y()).normalize();
+        let up = right.cross(forward);
+        (right, up)
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        if !self.synced {
+            self.sync_with(camera);
+        }
+
+        let (forward, right) = self.movement;
+        self.goal_distance = (self.goal_distance - forward * self.speed * dt).clamp(self.min_distance, self.max_distance);
+        let (look_right, look_up) = self.look;
+        self.goal_yaw += (right + look_right) * self.key_rotate_speed * dt;
+        self.goal_pitch = (self.goal_pitch + look_up * self.key_rotate_speed * dt).clamp(-MAX_PITCH, MAX_PITCH);
+
+        // Frame rate independent exponential smoothing
+        let t = if self.damping > 0.0 {
+            1.0 - (-self.damping * dt).exp()
+        } else {
+            1.0
+        };
+        self.yaw += (self.goal_yaw - self.yaw) * t;
+        self.pitch += (self.goal_pitch - self.pitch) * t;
+        self.distance += (self.goal_distance - self.distance) * t;
+        self.target += (self.goal_target - self.target) * t;
+
+        camera.target = self.target;
+        camera.eye = self.target + spherical_offset(self.yaw, self.pitch) * self.distance;
+    }
+}
+
+// Unit vector from the target towards the eye.
+fn spherical_offset(yaw: f32, pitch: f32) -> cgmath::Vector3<f32> {
+    cgmath::Vector3::new(pitch.cos() * yaw.sin(), pitch.sin(), pitch.cos() * yaw.cos())
+}
+
+/// Free-fly first person controller.
+///
+/// The `move_forward` and `move_right` axes move along the view direction
+/// and strafe, `move_up` moves straight up and down and `sprint` speeds it
+/// all up. `mouse_look` turns on mouse look, during which raw device motion
+/// turns the camera, which keeps working when the cursor is grabbed and
+/// pinned to the window. The `look_right` and `look_up` axes turn the camera
+/// without mouse look. On touchscreens one finger looks around, two fingers
+/// move sideways and up and pinching moves forwards.
+pub struct FlyController {
+    /// Movement speed in units per second.
+    pub speed: f32,
+    pub sprint_multiplier: f32,
+    /// Radians per unit of raw mouse motion.
+    pub sensitivity: f32,
+    /// Radians per second at full `look_right`/`look_up`.
+    pub look_speed: f32,
+    /// Units moved per pixel of two-finger drag.
+    pub touch_move_sensitivity: f32,
+    yaw: f32,
+    pitch: f32,
+    synced: bool,
+    mouse_look: bool,
+    look_delta: (f32, f32),
+    // (move_right, move_up, move_forward) axes
+    movement: cgmath::Vector3<f32>,
+    // (look_right, look_up) axes
+    look: (f32, f32),
+    // Touch movement since the last update, in (right, up, forward) units
+    touch_move: cgmath::Vector3<f32>,
+    is_sprinting: bool,
+    // Whether fingers are on the screen
+    dragging: bool,
+}
+
+impl FlyController {
+    pub fn new(speed: f32, sensitivity: f32) -> Self {
+        Self {
+            speed,
+            sprint_multiplier: 3.0,
+            sensitivity,
+            look_speed: 2.0,
+            touch_move_sensitivity: 0.01,
+            yaw: 0.0,
+            pitch: 0.0,
+            synced: false,
+            mouse_look: false,
+            look_delta: (0.0, 0.0),
+            movement: cgmath::Vector3::zero(),
+            look: (0.0, 0.0),
+            touch_move: cgmath::Vector3::zero(),
+            is_sprinting: false,
+            dragging: false,
+        }
+    }
+
+    /// Takes over the camera's current view direction.
+    pub fn sync_with(&mut self, camera: &Camera) {
+        let direction = (camera.target - camera.eye).normalize();
+        self.yaw = direction.x.atan2(direction.z);
+        self.pitch = direction.y.asin().clamp(-MAX_PITCH, MAX_PITCH);
+        self.synced = true;
+    }
+
+    /// Whether mouse motion currently turns the camera. The owner of the
+    /// window should grab and hide the cursor while this is true.
+    pub fn mouse_look(&self) -> bool {
+        self.mouse_look
+    }
+
+    pub fn set_mouse_look(&mut self, enabled: bool) {
+        self.mouse_look = enabled;
+        self.look_delta = (0.0, 0.0);
+    }
+
+    /// Reads this frame's input. Mouse motion is accumulated until the
+    /// next `update_camera`.
+    pub fn process_input(&mut self, input: &InputMap) {
+        if input.pressed("mouse_look") {
+            self.set_mouse_look(true);
+        }
+        if self.mouse_look {
+            let (dx, dy) = input.mouse_motion();
+            self.look_delta.0 += dx;
+            self.look_delta.1 += dy;
+        }
+        self.movement = cgmath::Vector3::new(input.axis("move_right"), input.axis("move_up"), input.axis("move_forward"));
+        self.look = (input.axis("look_right"), input.axis("look_up"));
+        self.is_sprinting = input.held("sprint");
+
+        let touch = input.touch();
+        self.dragging = touch.touch_count() > 0;
+        let (dx, dy) = touch.drag();
+        self.look_delta.0 += dx;
+        self.look_delta.1 += dy;
+        // Drag the world along with the fingers
+        let (dx, dy) = touch.pan();
+        self.touch_move.x -= dx * self.touch_move_sensitivity;
+        self.touch_move.y += dy * self.touch_move_sensitivity;
+        // Spreading the fingers twice as far apart moves ~0.7 * speed
+        self.touch_move.z += touch.pinch().ln() * self.speed;
+    }
+
+    /// Turns the camera by `dx`, `dy` units of mouse motion.
+    pub fn look(&mut self, dx: f32, dy: f32) {
+        self.yaw -= dx * self.sensitivity;
+        self.pitch = (self.pitch - dy * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        if !self.synced {
+            self.sync_with(camera);
+        }
+
+        let (dx, dy) = std::mem::take(&mut self.look_delta);
+        self.look(dx, dy);
+        let (look_right, look_up) = self.look;
+        self.yaw -= look_right * self.look_speed * dt;
+        self.pitch = (self.pitch + look_up * self.look_speed * dt).clamp(-MAX_PITCH, MAX_PITCH);
+
+        let forward = spherical_offset(self.yaw, self.pitch);
+        let right = forward.cross(cgmath::Vector3::unit_y()).normalize();
+        let direction = right * self.movement.x + cgmath::Vector3::unit_y() * self.movement.y + forward * self.movement.z;
+        if direction.magnitude2() > 0.0 {
+            let speed = if self.is_sprinting {
+                self.speed * self.sprint_multiplier
+            } else {
+                self.speed
+            };
+            // Analog input may ask for less than full speed
+            let magnitude = direction.magnitude().min(1.0);
+            camera.eye += direction.normalize() * magnitude * speed * dt;
+        }
+        let touch_move = std::mem::replace(&mut self.touch_move, cgmath::Vector3::zero());
+        camera.eye += right * touch_move.x + cgmath::Vector3::unit_y() * touch_move.y + forward * touch_move.z;
+        camera.target = camera.eye + forward;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    Orbit,
+    Fly,
+}
+
+/// Routes input to whichever controller is active and hands the camera over
+/// between them when the mode changes.
+pub struct CameraController {
+    pub orbit: OrbitController,
+    pub fly: FlyController,
+    mode: CameraMode,
+}
+
+impl CameraController {
+    pub fn new(orbit: OrbitController, fly: FlyController) -> Self {
+        Self {
+            orbit,
+            fly,
+            mode: CameraMode::Orbit,
+        }
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: CameraMode, camera: &Camera) {
+        if mode == self.mode {
+            return;
+        }
+        match mode {
+            CameraMode::Orbit => {
+                self.fly.set_mouse_look(false);
+                self.orbit.sync_with(camera);
+            }
+            CameraMode::Fly => self.fly.sync_with(camera),
+        }
+        self.mode = mode;
+    }
+
+    pub fn toggle_mode(&mut self, camera: &Camera) {
+        let mode = match self.mode {
+            CameraMode::Orbit => CameraMode::Fly,
+            CameraMode::Fly => CameraMode::Orbit,
+        };
+        self.set_mode(mode, camera);
+    }
+
+    /// True while the cursor should be grabbed for mouse look.
+    pub fn wants_cursor_grab(&self) -> bool {
+        self.mode == CameraMode::Fly && self.fly.mouse_look()
+    }
+
+    pub fn process_input(&mut self, input: &InputMap) {
+        match self.mode {
+            CameraMode::Orbit => self.orbit.process_input(input),
+            CameraMode::Fly => self.fly.process_input(input),
+        }
+    }
+
+    /// True while the pointer or a finger is held down to move the camera,
+    /// as of the last `process_input`. The camera may be standing still
+    /// in the middle of such a drag.
+    pub fn is_dragging(&self) -> bool {
+        match self.mode {
+            CameraMode::Orbit => self.orbit.dragging,
+            CameraMode::Fly => self.fly.dragging,
+        }
+    }
+
+    /// Picks up the camera after something else moved it.
+    pub fn sync_with(&mut self, camera: &Camera) {
+        match self.mode {
+            CameraMode::Orbit => self.orbit.sync_with(camera),
+            CameraMode::Fly => self.fly.sync_with(camera),
+        }
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        match self.mode {
+            CameraMode::Orbit => self.orbit.update_camera(camera, dt),
+            CameraMode::Fly => self.fly.update_camera(camera, dt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(projection: Projection) -> Camera {
+        Camera {
+            eye: (0.0, 0.0, 0.0).into(),
+            target: (0.0, 0.0, -1.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 1.5,
+            projection,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+
+    fn projections() -> [Projection; 4] {
+        [
+            Projection::Perspective { fovy: cgmath::Deg(60.0) },
+            Projection::ReverseZ { fovy: cgmath::Deg(60.0) },
+            Projection::Orthographic { height: 10.0 },
+            Projection::OffAxis { left: -0.05, right: 0.1, bottom: -0.05, top: 0.05 },
+        ]
+    }
+
+    // The depth a point `distance` in front of the camera ends up with
+    fn ndc_depth(camera: &Camera, distance: f32) -> f32 {
+        let clip = camera.build_view_projection_matrix() * cgmath::Vector4::new(0.0, 0.0, -distance, 1.0);
+        clip.z / clip.w
+    }
+
+    #[test]
+    fn near_and_far_planes_land_on_the_ends_of_the_depth_range() {
+        for projection in projections() {
+            let camera = camera(projection);
+            let near = ndc_depth(&camera, camera.znear);
+            // Reverse-Z has no far plane, so look very far away instead
+            let far = if projection.is_reverse_z() { ndc_depth(&camera, 1e6) } else { ndc_depth(&camera, camera.zfar) };
+            let (expected_near, expected_far) = if projection.is_reverse_z() { (1.0, 0.0) } else { (0.0, 1.0) };
+            assert!((near - expected_near).abs() < 1e-5, "{:?}: near depth {}", projection, near);
+            assert!((far - expected_far).abs() < 1e-5, "{:?}: far depth {}", projection, far);
+            // And everything in between is inside the range
+            let middle = ndc_depth(&camera, 10.0);
+            assert!(middle > 0.0 && middle < 1.0, "{:?}: middle depth {}", projection, middle);
+        }
+    }
+
+    #[test]
+    fn depth_settings_agree_with_the_matrix() {
+        for projection in projections() {
+            let camera = camera(projection);
+            let far = if projection.is_reverse_z() { ndc_depth(&camera, 1e6) } else { ndc_depth(&camera, camera.zfar) };
+            assert!((projection.depth_clear_value() - far).abs() < 1e-5, "{:?}", projection);
+            // The depth test passes for the closer of two points
+            let (closer, further) = (ndc_depth(&camera, 1.0), ndc_depth(&camera, 2.0));
+            let passes = match projection.depth_compare() {
+                wgpu::CompareFunction::Less => closer < further,
+                wgpu::CompareFunction::Greater => closer > further,
+                compare => panic!("unexpected depth compare {:?}", compare),
+            };
+            assert!(passes, "{:?}: {} vs {}", projection, closer, further);
+            assert_eq!(projection.is_reverse_z(), projection.depth_compare() == wgpu::CompareFunction::Greater);
+        }
+    }
+
+    #[test]
+    fn symmetric_off_axis_matches_perspective() {
+        let perspective = camera(Projection::Perspective { fovy: cgmath::Deg(60.0) });
+        let top = perspective.znear * (30.0f32).to_radians().tan();
+        let right = top * perspective.aspect;
+        let off_axis = camera(Projection::OffAxis { left: -right, right, bottom: -top, top });
+        let (a, b) = (perspective.build_projection_matrix(), off_axis.build_projection_matrix());
+        for column in 0..4 {
+            for row in 0..4 {
+                assert!((a[column][row] - b[column][row]).abs() < 1e-4, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+}
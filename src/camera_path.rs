@@ -0,0 +1,361 @@
+use std::path::Path;
+
+use anyhow::*;
+use cgmath::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+
+/// How the path bends between keyframes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Spline {
+    /// Passes through every keyframe.
+    CatmullRom,
+    /// Composite cubic Bezier: keyframes 0, 3, 6, ... are passed through,
+    /// the two keyframes between each pair are control handles. Only the
+    /// times of the pass-through keyframes are used.
+    Bezier,
+}
+
+/// Remaps playback time over the whole path, e.g. to ease in and out of the
+/// flythrough instead of starting and stopping abruptly.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds from the start of the path.
+    pub time: f32,
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    /// Vertical field of view in degrees. Keyframes without one keep the
+    /// value of the surrounding keyframes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fovy: Option<f32>,
+}
+
+impl Keyframe {
+    pub fn from_camera(time: f32, camera: &Camera) -> Self {
+        Self {
+            time,
+            eye: camera.eye.into(),
+            target: camera.target.into(),
+            fovy: camera.fovy().map(|fovy| fovy.0),
+        }
+    }
+}
+
+/// Where the camera should be at a point in time along a path.
+#[derive(Copy, Clone, Debug)]
+pub struct PathSample {
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    pub fovy: Option<cgmath::Deg<f32>>,
+}
+
+/// A recorded camera flythrough, stored as RON or JSON.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub spline: Spline,
+    pub easing: Easing,
+    #[serde(default)]
+    pub looping: bool,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn new(spline: Spline, easing: Easing) -> Self {
+        Self {
+            spline,
+            easing,
+            looping: false,
+            keyframes: Vec::new(),
+        }
+    }
+
+    /// Reads a path, picking the format from the file extension (`.json`,
+    /// anything else is treated as RON).
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+        let camera_path: Self = if is_json(path) {
+            serde_json::from_str(&text).with_context(|| format!("Invalid camera path {}", path.display()))?
+        } else {
+            ron::from_str(&text).with_context(|| format!("Invalid camera path {}", path.display()))?
+        };
+        camera_path.validate()?;
+        Ok(camera_path)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?
+        };
+        std::fs::write(path, text).with_context(|| format!("Couldn't write {}", path.display()))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some((index, key)) = self.keyframes.iter().enumerate().find(|(_, key)| !(key.time >= 0.0 && key.time.is_finite())) {
+            bail!("Camera path keyframe {} has time {}, times must be finite and not negative", index, key.time);
+        }
+        // Bezier handles only shape the curve, so their times don't matter
+        let timed = match self.spline {
+            Spline::CatmullRom => self.keyframes.iter().collect::<Vec<_>>(),
+            Spline::Bezier => self.keyframes.iter().step_by(3).collect(),
+        };
+        if timed.windows(2).any(|pair| pair[1].time <= pair[0].time) {
+            bail!("Camera path keyframe times must increase from one keyframe to the next");
+        }
+        if self.spline == Spline::Bezier && !self.keyframes.is_empty() && !(self.keyframes.len() - 1).is_multiple_of(3) {
+            bail!(
+                "Bezier camera paths need 3n + 1 keyframes (anchor, handle, handle, anchor, ...), got {}",
+                self.keyframes.len()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|key| key.time).unwrap_or(0.0)
+    }
+
+    /// Appends a keyframe `interval` seconds after the last one.
+    pub fn push_camera(&mut self, camera: &Camera, interval: f32) {
+        let time = if self.keyframes.is_empty() { 0.0 } else { self.duration() + interval };
+        self.keyframes.push(Keyframe::from_camera(time, camera));
+    }
+
+    pub fn sample(&self, time: f32) -> Option<PathSample> {
+        let first = self.keyframes.first()?;
+        let duration = self.duration();
+        if duration <= 0.0 {
+            return Some(PathSample {
+                eye: first.eye.into(),
+                target: first.target.into(),
+                fovy: first.fovy.map(cgmath::Deg),
+            });
+        }
+        let time = if self.looping { time.rem_euclid(duration) } else { time.clamp(0.0, duration) };
+        let time = self.easing.apply(time / duration) * duration;
+        Some(match self.spline {
+            Spline::CatmullRom => self.sample_catmull_rom(time),
+            Spline::Bezier => self.sample_bezier(time),
+        })
+    }
+
+    fn sample_catmull_rom(&self, time: f32) -> PathSample {
+        let keys = &self.keyframes;
+        let last = keys.len() - 1;
+        let next = keys.partition_point(|key| key.time <= time).clamp(1, last.max(1));
+        let (i1, i2) = (next - 1, next.min(last));
+        let i0 = i1.saturating_sub(1);
+        let i3 = (i2 + 1).min(last);
+        let t = segment_t(keys[i1].time, keys[i2].time, time);
+        let point = |get: fn(&Keyframe) -> [f32; 3]| {
+            catmull_rom(
+                get(&keys[i0]).into(),
+                get(&keys[i1]).into(),
+                get(&keys[i2]).into(),
+                get(&keys[i3]).into(),
+                t,
+            )
+        };
+        PathSample {
+            eye: cgmath::Point3::from_vec(point(|key| key.eye)),
+            target: cgmath::Point3::from_vec(point(|key| key.target)),
+            fovy: lerp_fovy(&keys[i1], &keys[i2], t),
+        }
+    }
+
+    fn sample_bezier(&self, time: f32) -> PathSample {
+        let keys = &self.keyframes;
+        let segments = (keys.len() - 1) / 3;
+        if segments == 0 {
+            return PathSample {
+                eye: keys[0].eye.into(),
+                target: keys[0].target.into(),
+                fovy: keys[0].fovy.map(cgmath::Deg),
+            };
+        }
+        let segment = (0..segments).find(|segment| time <= keys[segment * 3 + 3].time).unwrap_or(segments - 1);
+        let base = segment * 3;
+        let t = segment_t(keys[base].time, keys[base + 3].time, time);
+        let point = |get: fn(&Keyframe) -> [f32; 3]| {
+            bezier(
+                get(&keys[base]).into(),
+                get(&keys[base + 1]).into(),
+                get(&keys[base + 2]).into(),
+                get(&keys[base + 3]).into(),
+                t,
+            )
+        };
+        PathSample {
+            eye: cgmath::Point3::from_vec(point(|key| key.eye)),
+            target: cgmath::Point3::from_vec(point(|key| key.target)),
+            fovy: lerp_fovy(&keys[base], &keys[base + 3], t),
+        }
+    }
+
+    /// Moves `camera` to where the path is at `time`.
+    pub fn apply(&self, time: f32, camera: &mut Camera) {
+        if let Some(sample) = self.sample(time) {
+            camera.eye = sample.eye;
+            camera.target = sample.target;
+            if let Some(fovy) = sample.fovy {
+                camera.set_fovy(fovy);
+            }
+        }
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+fn segment_t(start: f32, end: f32, time: f32) -> f32 {
+    if end > start {
+        ((time - start) / (end - start)).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+fn lerp_fovy(a: &Keyframe, b: &Keyframe, t: f32) -> Option<cgmath::Deg<f32>> {
+    match (a.fovy, b.fovy) {
+        (Some(a), Some(b)) => Some(cgmath::Deg(a + (b - a) * t)),
+        (Some(fovy), None) | (None, Some(fovy)) => Some(cgmath::Deg(fovy)),
+        (None, None) => None,
+    }
+}
+
+fn catmull_rom(
+    p0: cgmath::Vector3<f32>,
+    p1: cgmath::Vector3<f32>,
+    p2: cgmath::Vector3<f32>,
+    p3: cgmath::Vector3<f32>,
+    t: f32,
+) -> cgmath::Vector3<f32> {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0 + (p2 - p0) * t + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2 + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3) * 0.5
+}
+
+fn bezier(
+    p0: cgmath::Vector3<f32>,
+    p1: cgmath::Vector3<f32>,
+    p2: cgmath::Vector3<f32>,
+    p3: cgmath::Vector3<f32>,
+    t: f32,
+) -> cgmath::Vector3<f32> {
+    let u = 1.0 - t;
+    p0 * (u * u * u) + p1 * (3.0 * u * u * t) + p2 * (3.0 * u * t * t) + p3 * (t * t * t)
+}
+
+/// Plays a path back on a camera. Advance it from the fixed timestep so the
+/// same path always produces the same frames.
+pub struct PathPlayer {
+    pub path: CameraPath,
+    time: f32,
+    playing: bool,
+}
+
+impl PathPlayer {
+    pub fn new(path: CameraPath) -> Self {
+        Self {
+            path,
+            time: 0.0,
+            playing: false,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Starts playing from the beginning.
+    pub fn play(&mut self) {
+        self.time = 0.0;
+        self.playing = !self.path.keyframes.is_empty();
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Advances playback by `dt` seconds and moves the camera. Returns false
+    /// once playback has finished or when stopped.
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) -> bool {
+        if !self.playing {
+            return false;
+        }
+        self.time += dt;
+        self.path.apply(self.time, camera);
+        if !self.path.looping && self.time >= self.path.duration() {
+            self.playing = false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(spline: Spline, times: &[f32]) -> CameraPath {
+        let mut path = CameraPath::new(spline, Easing::Linear);
+        path.keyframes = times
+            .iter()
+            .map(|&time| Keyframe { time, eye: [0.0, 0.0, 5.0], target: [0.0; 3], fovy: None })
+            .collect();
+        path
+    }
+
+    #[test]
+    fn increasing_times_are_valid() {
+        assert!(path(Spline::CatmullRom, &[0.0, 1.0, 2.5]).validate().is_ok());
+        assert!(path(Spline::CatmullRom, &[]).validate().is_ok());
+    }
+
+    #[test]
+    fn bad_times_are_rejected() {
+        for times in [&[0.0, f32::NAN][..], &[-1.0, 1.0], &[0.0, 2.0, 1.0], &[0.0, 1.0, 1.0], &[0.0, f32::INFINITY]] {
+            assert!(path(Spline::CatmullRom, times).validate().is_err(), "{:?} was accepted", times);
+        }
+    }
+
+    #[test]
+    fn only_bezier_anchors_need_increasing_times() {
+        assert!(path(Spline::Bezier, &[0.0, 0.0, 0.0, 2.0]).validate().is_ok());
+        assert!(path(Spline::Bezier, &[1.0, 0.0, 0.0, 1.0]).validate().is_err());
+    }
+}
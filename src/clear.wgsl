@@ -0,0 +1,24 @@
+struct ClearUniform {
+    color: vec4<f32>,
+    depth: f32,
+};
+@group(0) @binding(0)
+var<uniform> clear: ClearUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// A single triangle covering the whole viewport, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, clear.depth, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return clear.color;
+}
@@ -0,0 +1,109 @@
+use crate::pipeline::PipelineBuilder;
+use crate::preprocessor::ShaderDefs;
+
+/// Ways of drawing the scene that show its geometry rather than its
+/// textures. Each one is a variant of the main shader, see
+/// `assets/debug.wgsl`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DebugView {
+    /// Drawn normally.
+    #[default]
+    Shaded,
+    /// The edges of every triangle, front and back.
+    Wireframe,
+    /// The direction each triangle faces as a color, x as red, y as green
+    /// and z as blue.
+    Normals,
+    /// A checkerboard over the texture coordinates, red along u and green
+    /// along v.
+    UvChecker,
+    /// How far away things are, white up close fading to black.
+    Depth,
+    /// How many times each pixel is drawn over, brighter for more.
+    Overdraw,
+    /// A different color for every instance.
+    InstanceId,
+}
+
+impl DebugView {
+    /// Every view besides `Shaded`.
+    pub const DEBUG: [DebugView; 6] = [
+        DebugView::Wireframe,
+        DebugView::Normals,
+        DebugView::UvChecker,
+        DebugView::Depth,
+        DebugView::Overdraw,
+        DebugView::InstanceId,
+    ];
+
+    /// The input action that switches to this view, and back.
+    pub fn action(self) -> &'static str {
+        match self {
+            DebugView::Shaded => "shaded_view",
+            DebugView::Wireframe => "wireframe_view",
+            DebugView::Normals => "normals_view",
+            DebugView::UvChecker => "uv_checker_view",
+            DebugView::Depth => "depth_view",
+            DebugView::Overdraw => "overdraw_view",
+            DebugView::InstanceId => "instance_id_view",
+        }
+    }
+
+    /// What to define on top of `defs` for the shader to draw this view.
+    ///
+    /// Wireframes use `wgpu::PolygonMode::Line` when `line_polygon_mode` is
+    /// available. Otherwise (on WebGL, for one) the shader draws them itself
+    /// from which corner of its triangle each vertex is, which needs meshes
+    /// drawn with `MeshDraw::draw_corners` or `SkinnedInstance::draw_corners`.
+    pub fn defs(self, defs: ShaderDefs, line_polygon_mode: bool) -> ShaderDefs {
+        let name = match self {
+            DebugView::Shaded => return defs,
+            DebugView::Wireframe if !line_polygon_mode => {
+                return defs.define("DEBUG_VIEW").define("DEBUG_WIREFRAME").define("BARYCENTRIC_WIREFRAME")
+            }
+            DebugView::Wireframe => "DEBUG_WIREFRAME",
+            DebugView::Normals => "DEBUG_NORMALS",
+            DebugView::UvChecker => "DEBUG_UV_CHECKER",
+            DebugView::Depth => "DEBUG_DEPTH",
+            DebugView::Overdraw => "DEBUG_OVERDRAW",
+            DebugView::InstanceId => "DEBUG_INSTANCE_ID",
+        };
+        defs.define("DEBUG_VIEW").define(name)
+    }
+
+    /// Sets up a pipeline for this view's shader. Views ignore how
+    /// materials blend and cull, showing every triangle the same way.
+    pub fn pipeline<'a>(self, builder: PipelineBuilder<'a>, line_polygon_mode: bool) -> PipelineBuilder<'a> {
+        let builder = builder.blend(Some(wgpu::BlendState::REPLACE)).depth_write(true);
+        match self {
+            DebugView::Shaded | DebugView::Normals | DebugView::UvChecker | DebugView::Depth | DebugView::InstanceId => {
+                builder
+            }
+            DebugView::Wireframe if line_polygon_mode => {
+                builder.cull_mode(None).polygon_mode(wgpu::PolygonMode::Line)
+            }
+            DebugView::Wireframe => builder.cull_mode(None),
+            // Every layer counts, however far back it is
+            DebugView::Overdraw => builder
+                .cull_mode(None)
+                .blend(Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::OVER,
+                }))
+                .depth_write(false),
+        }
+    }
+
+    /// The depth test this view's pipeline uses instead of `compare`, the
+    /// camera's.
+    pub fn depth_compare(self, compare: wgpu::CompareFunction) -> wgpu::CompareFunction {
+        match self {
+            DebugView::Overdraw => wgpu::CompareFunction::Always,
+            _ => compare,
+        }
+    }
+}
@@ -0,0 +1,465 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+/// Buttons named by position, so bindings work the same on Xbox, PlayStation
+/// and Switch style controllers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// Sticks go from -1 to 1 with positive being right and up, triggers go
+/// from 0 to 1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl GamepadAxis {
+    pub const COUNT: usize = 6;
+    pub const ALL: [GamepadAxis; Self::COUNT] = [
+        GamepadAxis::LeftStickX,
+        GamepadAxis::LeftStickY,
+        GamepadAxis::RightStickX,
+        GamepadAxis::RightStickY,
+        GamepadAxis::LeftTrigger,
+        GamepadAxis::RightTrigger,
+    ];
+}
+
+/// A snapshot of one connected gamepad, as reported by the backend.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GamepadState {
+    /// Stays the same for as long as the gamepad is connected.
+    pub id: usize,
+    pub name: String,
+    pub buttons: HashSet<GamepadButton>,
+    /// Raw axis values indexed by `GamepadAxis as usize`, before deadzones.
+    pub axes: [f32; GamepadAxis::COUNT],
+}
+
+impl GamepadState {
+    pub fn new(id: usize, name: impl Into<String>) -> Self {
+        Self {
+            id,
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn axis(&self, axis: GamepadAxis) -> f32 {
+        self.axes[axis as usize]
+    }
+
+    pub fn set_axis(&mut self, axis: GamepadAxis, value: f32) {
+        self.axes[axis as usize] = value;
+    }
+}
+
+/// Shapes axis values once they're past the deadzone. Steeper curves give
+/// finer control near the center of the stick.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ResponseCurve {
+    Linear,
+    Quadratic,
+    Cubic,
+    /// Raises the value to the given power.
+    Power(f32),
+}
+
+impl ResponseCurve {
+    pub fn apply(&self, t: f32) -> f32 {
+        match self {
+            ResponseCurve::Linear => t,
+            ResponseCurve::Quadratic => t * t,
+            ResponseCurve::Cubic => t * t * t,
+            ResponseCurve::Power(exponent) => t.powf(*exponent),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GamepadSettings {
+    /// Sticks report zero until pushed this far from the center. The
+    /// deadzone is radial so diagonals don't snap to the axes.
+    pub stick_deadzone: f32,
+    pub trigger_deadzone: f32,
+    pub curve: ResponseCurve,
+    /// How far an axis has to move before it counts as pressed for actions.
+    pub press_threshold: f32,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            stick_deadzone: 0.15,
+            trigger_deadzone: 0.05,
+            curve: ResponseCurve::Quadratic,
+            press_threshold: 0.5,
+        }
+    }
+}
+
+impl GamepadSettings {
+    /// Applies deadzones and the response curve to every axis of `pad`.
+    pub fn apply(&self, pad: &GamepadState) -> [f32; GamepadAxis::COUNT] {
+        use GamepadAxis::*;
+        let mut axes = [0.0; GamepadAxis::COUNT];
+        for (x, y) in [(LeftStickX, LeftStickY), (RightStickX, RightStickY)] {
+            let (sx, sy) = self.stick(pad.axis(x), pad.axis(y));
+            axes[x as usize] = sx;
+            axes[y as usize] = sy;
+        }
+        for trigger in [LeftTrigger, RightTrigger] {
+            axes[trigger as usize] = self.rescale(pad.axis(trigger), self.trigger_deadzone);
+        }
+        axes
+    }
+
+    fn stick(&self, x: f32, y: f32) -> (f32, f32) {
+        let magnitude = (x * x + y * y).sqrt();
+        let scaled = self.rescale(magnitude, self.stick_deadzone);
+        if scaled == 0.0 {
+            return (0.0, 0.0);
+        }
+        (x / magnitude * scaled, y / magnitude * scaled)
+    }
+
+    // Maps deadzone..1 onto 0..1 so there's no jump at the deadzone edge.
+    fn rescale(&self, value: f32, deadzone: f32) -> f32 {
+        if value <= deadzone {
+            return 0.0;
+        }
+        let t = ((value - deadzone) / (1.0 - deadzone)).min(1.0);
+        self.curve.apply(t)
+    }
+}
+
+/// Where gamepad state comes from.
+pub trait GamepadBackend {
+    /// Returns the current state of every connected gamepad. Called once
+    /// per frame.
+    fn poll(&mut self) -> Vec<GamepadState>;
+}
+
+/// A backend whose gamepads are set by hand, for tests and for builds
+/// without gamepad support. Clones share the same gamepads, so keep one to
+/// drive the copy handed to `Gamepads`.
+#[derive(Clone, Default)]
+pub struct SyntheticBackend {
+    pads: Rc<RefCell<Vec<GamepadState>>>,
+}
+
+impl SyntheticBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the connected gamepads. An empty list unplugs them all.
+    pub fn set(&self, pads: Vec<GamepadState>) {
+        *self.pads.borrow_mut() = pads;
+    }
+}
+
+impl GamepadBackend for SyntheticBackend {
+    fn poll(&mut self) -> Vec<GamepadState> {
+        self.pads.borrow().clone()
+    }
+}
+
+#[cfg(feature = "gamepad")]
+pub use self::gilrs_backend::GilrsBackend;
+
+#[cfg(feature = "gamepad")]
+mod gilrs_backend {
+    use super::*;
+    use anyhow::*;
+
+    const BUTTONS: [(GamepadButton, gilrs::Button); 14] = [
+        (GamepadButton::South, gilrs::Button::South),
+        (GamepadButton::East, gilrs::Button::East),
+        (GamepadButton::North, gilrs::Button::North),
+        (GamepadButton::West, gilrs::Button::West),
+        (GamepadButton::LeftBumper, gilrs::Button::LeftTrigger),
+        (GamepadButton::RightBumper, gilrs::Button::RightTrigger),
+        (GamepadButton::Select, gilrs::Button::Select),
+        (GamepadButton::Start, gilrs::Button::Start),
+        (GamepadButton::LeftStick, gilrs::Button::LeftThumb),
+        (GamepadButton::RightStick, gilrs::Button::RightThumb),
+        (GamepadButton::DPadUp, gilrs::Button::DPadUp),
+        (GamepadButton::DPadDown, gilrs::Button::DPadDown),
+        (GamepadButton::DPadLeft, gilrs::Button::DPadLeft),
+        (GamepadButton::DPadRight, gilrs::Button::DPadRight),
+    ];
+
+    const STICKS: [(GamepadAxis, gilrs::Axis); 4] = [
+        (GamepadAxis::LeftStickX, gilrs::Axis::LeftStickX),
+        (GamepadAxis::LeftStickY, gilrs::Axis::LeftStickY),
+        (GamepadAxis::RightStickX, gilrs::Axis::RightStickX),
+        (GamepadAxis::RightStickY, gilrs::Axis::RightStickY),
+    ];
+
+    /// Real gamepads through gilrs (evdev, XInput, IOKit or the web
+    /// Gamepad API depending on the platform).
+    pub struct GilrsBackend {
+        gilrs: gilrs::Gilrs,
+    }
+
+    impl GilrsBackend {
+        pub fn new() -> Result<Self> {
+            let gilrs = gilrs::Gilrs::new().map_err(|e| anyhow!("Couldn't initialize gamepads: {}", e))?;
+            Ok(Self { gilrs })
+        }
+    }
+
+    impl GamepadBackend for GilrsBackend {
+        fn poll(&mut self) -> Vec<GamepadState> {
+            // gilrs only updates its cached state and notices hot-plugging
+            // while events are drained.
+            while self.gilrs.next_event().is_some() {}
+            self.gilrs
+                .gamepads()
+                .map(|(id, pad)| {
+                    let mut state = GamepadState::new(usize::from(id), pad.name());
+                    for (button, gilrs_button) in BUTTONS {
+                        if pad.is_pressed(gilrs_button) {
+                            state.buttons.insert(button);
+                        }
+                    }
+                    for (axis, gilrs_axis) in STICKS {
+                        state.set_axis(axis, pad.value(gilrs_axis));
+                    }
+                    // Analog triggers are reported as buttons with a value
+                    let trigger = |button| pad.button_data(button).map(|data| data.value()).unwrap_or(0.0);
+                    state.set_axis(GamepadAxis::LeftTrigger, trigger(gilrs::Button::LeftTrigger2));
+                    state.set_axis(GamepadAxis::RightTrigger, trigger(gilrs::Button::RightTrigger2));
+                    state
+                })
+                .collect()
+        }
+    }
+}
+
+/// Polls a backend every frame and reports gamepads coming and going.
+pub struct Gamepads {
+    backend: Box<dyn GamepadBackend>,
+    states: Vec<GamepadState>,
+}
+
+impl Gamepads {
+    pub fn new(backend: Box<dyn GamepadBackend>) -> Self {
+        Self {
+            backend,
+            states: Vec::new(),
+        }
+    }
+
+    /// Real gamepads when built with the `gamepad` feature, none otherwise.
+    pub fn with_default_backend() -> Self {
+        #[cfg(feature = "gamepad")]
+        match GilrsBackend::new() {
+            std::result::Result::Ok(backend) => return Self::new(Box::new(backend)),
+            Err(e) => log::warn!("{:?}", e),
+        }
+        Self::new(Box::new(SyntheticBackend::new()))
+    }
+
+    pub fn set_backend(&mut self, backend: Box<dyn GamepadBackend>) {
+        self.backend = backend;
+    }
+
+    pub fn update(&mut self) -> &[GamepadState] {
+        let states = self.backend.poll();
+        self.set_states(states)
+    }
+
+    /// Takes `states` as the connected gamepads instead of polling the
+    /// backend, e.g. when they come from a recording.
+    pub fn set_states(&mut self, states: Vec<GamepadState>) -> &[GamepadState] {
+        for pad in states.iter() {
+            if !self.states.iter().any(|previous| previous.id == pad.id) {
+                log::info!("Gamepad connected: {}", pad.name);
+            }
+        }
+        for previous in self.states.iter() {
+            if !states.iter().any(|pad| pad.id == previous.id) {
+                log::info!("Gamepad disconnected: {}", previous.name);
+            }
+        }
+        self.states = states;
+        &self.states
+    }
+
+    /// The gamepads as of the last `update`.
+    pub fn states(&self) -> &[GamepadState] {
+        &self.states
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{AxisDirection, Binding, InputConfig, InputMap};
+
+    fn linear() -> GamepadSettings {
+        GamepadSettings {
+            curve: ResponseCurve::Linear,
+            ..GamepadSettings::default()
+        }
+    }
+
+    fn pad_with(axis: GamepadAxis, value: f32) -> GamepadState {
+        let mut pad = GamepadState::new(0, "Test pad");
+        pad.set_axis(axis, value);
+        pad
+    }
+
+    #[test]
+    fn sticks_are_zero_inside_the_deadzone() {
+        let settings = linear();
+        let axes = settings.apply(&pad_with(GamepadAxis::LeftStickX, 0.14));
+        assert_eq!(axes[GamepadAxis::LeftStickX as usize], 0.0);
+        // Just outside, the value starts from zero rather than jumping
+        let axes = settings.apply(&pad_with(GamepadAxis::LeftStickX, 0.16));
+        let x = axes[GamepadAxis::LeftStickX as usize];
+        assert!(x > 0.0 && x < 0.02, "{}", x);
+        let axes = settings.apply(&pad_with(GamepadAxis::LeftStickX, -1.0));
+        assert_eq!(axes[GamepadAxis::LeftStickX as usize], -1.0);
+    }
+
+    #[test]
+    fn stick_deadzone_is_radial() {
+        let settings = linear();
+        // Each axis is inside the deadzone on its own, but not together
+        let mut pad = GamepadState::new(0, "Test pad");
+        pad.set_axis(GamepadAxis::RightStickX, 0.12);
+        pad.set_axis(GamepadAxis::RightStickY, 0.12);
+        let axes = settings.apply(&pad);
+        let (x, y) = (axes[GamepadAxis::RightStickX as usize], axes[GamepadAxis::RightStickY as usize]);
+        assert!(x > 0.0 && (x - y).abs() < 1e-6, "{} {}", x, y);
+        // And a diagonal stays diagonal
+        pad.set_axis(GamepadAxis::RightStickX, 0.1);
+        pad.set_axis(GamepadAxis::RightStickY, 0.1);
+        let axes = settings.apply(&pad);
+        assert_eq!(axes[GamepadAxis::RightStickX as usize], 0.0);
+        assert_eq!(axes[GamepadAxis::RightStickY as usize], 0.0);
+    }
+
+    #[test]
+    fn triggers_have_their_own_deadzone() {
+        let settings = linear();
+        let axes = settings.apply(&pad_with(GamepadAxis::LeftTrigger, 0.04));
+        assert_eq!(axes[GamepadAxis::LeftTrigger as usize], 0.0);
+        let axes = settings.apply(&pad_with(GamepadAxis::LeftTrigger, 0.1));
+        assert!(axes[GamepadAxis::LeftTrigger as usize] > 0.0);
+        let axes = settings.apply(&pad_with(GamepadAxis::LeftTrigger, 1.0));
+        assert_eq!(axes[GamepadAxis::LeftTrigger as usize], 1.0);
+    }
+
+    #[test]
+    fn response_curves() {
+        assert_eq!(ResponseCurve::Linear.apply(0.5), 0.5);
+        assert_eq!(ResponseCurve::Quadratic.apply(0.5), 0.25);
+        assert_eq!(ResponseCurve::Cubic.apply(0.5), 0.125);
+        assert_eq!(ResponseCurve::Power(0.5).apply(0.25), 0.5);
+        for curve in [ResponseCurve::Linear, ResponseCurve::Quadratic, ResponseCurve::Cubic, ResponseCurve::Power(3.0)] {
+            assert_eq!(curve.apply(0.0), 0.0);
+            assert_eq!(curve.apply(1.0), 1.0);
+        }
+        // Applied after the deadzone is taken out
+        let settings = GamepadSettings::default();
+        let axes = settings.apply(&pad_with(GamepadAxis::LeftStickY, 0.575));
+        assert!((axes[GamepadAxis::LeftStickY as usize] - 0.25).abs() < 1e-6);
+    }
+
+    // An input map fed from a synthetic backend, and the backend to drive it
+    fn synthetic() -> (SyntheticBackend, Gamepads, InputMap) {
+        let backend = SyntheticBackend::new();
+        let gamepads = Gamepads::new(Box::new(backend.clone()));
+        let mut input = InputMap::new(InputConfig::defaults());
+        input.bind("jump", Binding::GamepadAxis(GamepadAxis::LeftStickY, AxisDirection::Positive));
+        (backend, gamepads, input)
+    }
+
+    #[test]
+    fn gamepad_values_drive_axes_and_actions() {
+        let (backend, mut gamepads, mut input) = synthetic();
+        let mut pad = GamepadState::new(3, "Test pad");
+        pad.set_axis(GamepadAxis::RightTrigger, 1.0);
+        pad.set_axis(GamepadAxis::RightStickX, -1.0);
+        // 0.25 after the deadzone and curve, short of the press threshold
+        pad.set_axis(GamepadAxis::LeftStickY, 0.575);
+        pad.buttons.insert(GamepadButton::South);
+        backend.set(vec![pad.clone()]);
+        input.update_gamepads(gamepads.update());
+        assert_eq!(input.axis("move_up"), 1.0);
+        assert_eq!(input.axis("look_right"), -1.0);
+        assert!((input.axis("move_forward") - 0.25).abs() < 1e-6);
+        assert!(input.pressed("toggle_texture"));
+        assert!(!input.held("jump"));
+        input.end_frame(0.016);
+
+        // Past the threshold the stick counts as a press, once
+        pad.set_axis(GamepadAxis::LeftStickY, 1.0);
+        backend.set(vec![pad.clone()]);
+        input.update_gamepads(gamepads.update());
+        assert!(input.pressed("jump"));
+        assert!(!input.pressed("toggle_texture"));
+        assert!(input.held("toggle_texture"));
+        input.end_frame(0.016);
+        input.update_gamepads(gamepads.update());
+        assert!(!input.pressed("jump"));
+        assert!(input.held("jump"));
+        input.end_frame(0.016);
+
+        // Back under it, it's released
+        pad.set_axis(GamepadAxis::LeftStickY, 0.3);
+        backend.set(vec![pad]);
+        input.update_gamepads(gamepads.update());
+        assert!(input.released("jump"));
+        assert!(!input.held("jump"));
+    }
+
+    #[test]
+    fn unplugging_a_gamepad_releases_its_actions() {
+        let (backend, mut gamepads, mut input) = synthetic();
+        let mut pad = GamepadState::new(0, "Test pad");
+        pad.buttons.insert(GamepadButton::North);
+        pad.set_axis(GamepadAxis::LeftStickY, 1.0);
+        backend.set(vec![pad]);
+        assert_eq!(gamepads.update().len(), 1);
+        input.update_gamepads(gamepads.states());
+        assert!(input.held("toggle_camera_mode"));
+        assert!(input.held("jump"));
+        input.end_frame(0.016);
+
+        backend.set(Vec::new());
+        assert!(gamepads.update().is_empty());
+        input.update_gamepads(gamepads.states());
+        assert!(input.released("toggle_camera_mode"));
+        assert!(input.released("jump"));
+        assert!(!input.held("toggle_camera_mode"));
+        assert_eq!(input.axis("move_forward"), 0.0);
+    }
+}
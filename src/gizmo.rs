@@ -0,0 +1,752 @@
+use cgmath::prelude::*;
+
+use crate::camera::Camera;
+use crate::pipeline::{PipelineBuilder, PipelineLayout};
+use crate::raycast::Ray;
+use crate::viewport::View;
+use crate::{Instance, InstanceHandle};
+
+type Point = cgmath::Point3<f32>;
+type Vector = cgmath::Vector3<f32>;
+
+// Handle dimensions, as fractions of the gizmo size
+const AXIS_START: f32 = 0.2;
+const TIP_LENGTH: f32 = 0.15;
+const TIP_RADIUS: f32 = 0.05;
+const PLANE_MIN: f32 = 0.25;
+const PLANE_MAX: f32 = 0.45;
+const BOX_HALF_SIZE: f32 = 0.05;
+const UNIFORM_HALF_SIZE: f32 = 0.08;
+// How close the cursor ray has to pass to a handle to grab it
+const PICK_RADIUS: f32 = 0.07;
+const RING_SEGMENTS: usize = 48;
+const TIP_SEGMENTS: usize = 8;
+// Scaling never goes below this factor, so instances can't be flattened or
+// mirrored by accident
+const MIN_SCALE_FACTOR: f32 = 0.01;
+
+const AXIS_COLORS: [[f32; 4]; 3] = [[0.9, 0.2, 0.2, 1.0], [0.3, 0.85, 0.3, 1.0], [0.25, 0.45, 1.0, 1.0]];
+const UNIFORM_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.9, 0.2, 1.0];
+const PLANE_ALPHA: f32 = 0.4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+/// A part of the gizmo that can be grabbed. Axes are numbered 0 = x, 1 = y,
+/// 2 = z.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GizmoHandle {
+    /// Moves along, rotates around or scales along one axis.
+    Axis(usize),
+    /// Moves within the plane perpendicular to an axis.
+    Plane(usize),
+    /// Scales evenly along every axis.
+    Uniform,
+}
+
+/// Increments drags snap to while snapping is on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Snapping {
+    /// World units.
+    pub translate: f32,
+    pub rotate: cgmath::Deg<f32>,
+    /// Steps of the scale factor.
+    pub scale: f32,
+}
+
+impl Default for Snapping {
+    fn default() -> Self {
+        Self {
+            translate: 0.25,
+            rotate: cgmath::Deg(15.0),
+            scale: 0.1,
+        }
+    }
+}
+
+// Where the gizmo sits and how big it is, in world space
+#[derive(Copy, Clone)]
+struct GizmoFrame {
+    origin: Point,
+    axes: [Vector; 3],
+    size: f32,
+}
+
+impl GizmoFrame {
+    fn new(camera: &Camera, mode: GizmoMode, pivot: &Instance, screen_size: f32) -> Self {
+        let origin = Point::from_vec(pivot.position);
+        // Instance::scale is along the instance's own axes, so the scale
+        // handles follow its rotation. Moving and rotating use world axes.
+        let rotation = if mode == GizmoMode::Scale {
+            pivot.rotation
+        } else {
+            cgmath::Quaternion::one()
+        };
+        let axes = [
+            rotation.rotate_vector(Vector::unit_x()),
+            rotation.rotate_vector(Vector::unit_y()),
+            rotation.rotate_vector(Vector::unit_z()),
+        ];
+        // The world height the view shows at the pivot's depth, which works
+        // for every kind of projection: w is the depth for perspective
+        // projections and 1 for parallel ones.
+        let clip = camera.build_view_projection_matrix() * origin.to_homogeneous();
+        let view_height = 2.0 * clip.w.abs().max(camera.znear) / camera.build_projection_matrix().y.y;
+        Self {
+            origin,
+            axes,
+            size: screen_size * view_height,
+        }
+    }
+
+    // The other two axes, spanning the plane perpendicular to `axis`
+    fn plane_axes(&self, axis: usize) -> (Vector, Vector) {
+        (self.axes[(axis + 1) % 3], self.axes[(axis + 2) % 3])
+    }
+
+    // The handle under `ray` closest to the camera
+    fn hit(&self, mode: GizmoMode, ray: &Ray) -> Option<GizmoHandle> {
+        let radius = PICK_RADIUS * self.size;
+        let mut hits = Vec::new();
+        for axis in 0..3 {
+            let direction = self.axes[axis];
+            match mode {
+                GizmoMode::Translate | GizmoMode::Scale => {
+                    if let Some((distance, along)) = ray.closest_to_line(self.origin, direction) {
+                        let on_axis = self.origin + direction * along;
+                        if distance >= 0.0
+                            && (AXIS_START * self.size..=self.size).contains(&along)
+                            && ray.at(distance).distance(on_axis) < radius
+                        {
+                            hits.push((distance, GizmoHandle::Axis(axis)));
+                        }
+                    }
+                }
+                GizmoMode::Rotate => {
+                    if let Some(distance) = ray.intersect_plane(self.origin, direction) {
+                        let from_center = ray.at(distance).distance(self.origin);
+                        if (from_center - self.size).abs() < radius {
+                            hits.push((distance, GizmoHandle::Axis(axis)));
+                        }
+                    }
+                }
+            }
+            if mode == GizmoMode::Translate {
+                if let Some(distance) = ray.intersect_plane(self.origin, direction) {
+                    let offset = ray.at(distance) - self.origin;
+                    let (u, v) = self.plane_axes(axis);
+                    let range = PLANE_MIN * self.size..=PLANE_MAX * self.size;
+                    if range.contains(&offset.dot(u)) && range.contains(&offset.dot(v)) {
+                        hits.push((distance, GizmoHandle::Plane(axis)));
+                    }
+                }
+            }
+        }
+        if mode == GizmoMode::Scale {
+            let distance = (self.origin - ray.origin).dot(ray.direction);
+            if distance >= 0.0 && ray.at(distance).distance(self.origin) < UNIFORM_HALF_SIZE * self.size * 1.5 {
+                hits.push((distance, GizmoHandle::Uniform));
+            }
+        }
+        hits.into_iter()
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, handle)| handle)
+    }
+}
+
+// A handle being dragged
+struct Drag {
+    id: u64,
+    mode: GizmoMode,
+    handle: GizmoHandle,
+    // Frozen when the drag starts so the constraint doesn't move under the
+    // cursor
+    frame: GizmoFrame,
+    view_direction: Vector,
+    // Where the handle was grabbed, relative to the origin
+    grab: Vector,
+    // The constrained point last frame, and how far rotate handles have
+    // turned since the grab. Adding up each frame's turn rather than taking
+    // the angle between the grab and the cursor lets drags go past half a
+    // turn without flipping to the other side.
+    previous: Vector,
+    angle: f32,
+    start: Vec<(InstanceHandle, Instance)>,
+}
+
+impl Drag {
+    // Where the cursor ray meets the handle's constraint, relative to the
+    // origin. None when the ray runs parallel to it.
+    fn constrained_point(&self, ray: &Ray) -> Option<Vector> {
+        let frame = &self.frame;
+        let on_plane = |normal: Vector| {
+            ray.intersect_plane(frame.origin, normal)
+                .map(|distance| ray.at(distance) - frame.origin)
+        };
+        match (self.mode, self.handle) {
+            (GizmoMode::Rotate, GizmoHandle::Axis(axis)) | (_, GizmoHandle::Plane(axis)) => on_plane(frame.axes[axis]),
+            (_, GizmoHandle::Axis(axis)) => ray
+                .closest_to_line(frame.origin, frame.axes[axis])
+                .map(|(_, along)| frame.axes[axis] * along),
+            (_, GizmoHandle::Uniform) => on_plane(self.view_direction),
+        }
+    }
+
+    // Follows the cursor to `current`, turning rotate handles by the angle
+    // between it and the previous point about their axis.
+    fn track(&mut self, current: Vector) {
+        if let (GizmoMode::Rotate, GizmoHandle::Axis(axis)) = (self.mode, self.handle) {
+            let direction = self.frame.axes[axis];
+            self.angle += direction.dot(self.previous.cross(current)).atan2(self.previous.dot(current));
+        }
+        self.previous = current;
+    }
+
+    fn apply(&self, current: Vector, snapping: Option<&Snapping>) -> Vec<(InstanceHandle, Instance)> {
+        let snap = |value: f32, step: f32| match snapping {
+            Some(_) if step > 0.0 => (value / step).round() * step,
+            _ => value,
+        };
+        let snap_scale = |factor: f32| {
+            let step = snapping.map_or(0.0, |snapping| snapping.scale);
+            (1.0 + snap(factor - 1.0, step)).max(MIN_SCALE_FACTOR)
+        };
+        let step = snapping.copied().unwrap_or_default();
+        let frame = &self.frame;
+        let edit = |change: &dyn Fn(&mut Instance)| {
+            self.start
+                .iter()
+                .map(|(handle, start)| {
+                    let mut instance = start.clone();
+                    change(&mut instance);
+                    (*handle, instance)
+                })
+                .collect()
+        };
+        match (self.mode, self.handle) {
+            (GizmoMode::Translate, GizmoHandle::Axis(axis)) => {
+                let direction = frame.axes[axis];
+                let offset = direction * snap((current - self.grab).dot(direction), step.translate);
+                edit(&|instance| instance.position += offset)
+            }
+            (GizmoMode::Translate, _) => {
+                // Translate axes are the world axes, so snapping each
+                // coordinate keeps the offset on the plane
+                let offset = (current - self.grab).map(|value| snap(value, step.translate));
+                edit(&|instance| instance.position += offset)
+            }
+            (GizmoMode::Rotate, GizmoHandle::Axis(axis)) => {
+                let direction = frame.axes[axis];
+                let angle = snap(self.angle, cgmath::Rad::from(step.rotate).0);
+                let rotation = cgmath::Quaternion::from_axis_angle(direction, cgmath::Rad(angle));
+                // Every instance swings around the pivot as well as turning
+                edit(&|instance| {
+                    instance.rotation = rotation * instance.rotation;
+                    instance.position = frame.origin.to_vec() + rotation.rotate_vector(instance.position - frame.origin.to_vec());
+                })
+            }
+            (GizmoMode::Scale, GizmoHandle::Axis(axis)) => {
+                let direction = frame.axes[axis];
+                let grabbed = self.grab.dot(direction);
+                let factor = if grabbed.abs() > f32::EPSILON {
+                    snap_scale(current.dot(direction) / grabbed)
+                } else {
+                    1.0
+                };
+                // Every instance moves away from the pivot along the axis as
+                // well as growing
+                edit(&|instance| {
+                    instance.scale[axis] *= factor;
+                    let offset = instance.position - frame.origin.to_vec();
+                    instance.position += direction * (offset.dot(direction) * (factor - 1.0));
+                })
+            }
+            (GizmoMode::Scale, _) => {
+                let grabbed = self.grab.magnitude();
+                let factor = if grabbed > f32::EPSILON {
+                    snap_scale(current.magnitude() / grabbed)
+                } else {
+                    1.0
+                };
+                edit(&|instance| {
+                    instance.scale *= factor;
+                    instance.position = frame.origin.to_vec() + (instance.position - frame.origin.to_vec()) * factor;
+                })
+            }
+            (GizmoMode::Rotate, _) => edit(&|_| {}),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GizmoVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl GizmoVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GizmoVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// Handle geometry for one frame, as filled triangles and lines
+#[derive(Default)]
+struct GizmoMesh {
+    triangles: Vec<GizmoVertex>,
+    lines: Vec<GizmoVertex>,
+}
+
+impl GizmoMesh {
+    fn line(&mut self, from: Point, to: Point, color: [f32; 4]) {
+        self.lines.push(GizmoVertex { position: from.into(), color });
+        self.lines.push(GizmoVertex { position: to.into(), color });
+    }
+
+    fn triangle(&mut self, corners: [Point; 3], color: [f32; 4]) {
+        self.triangles.extend(corners.map(|corner| GizmoVertex { position: corner.into(), color }));
+    }
+
+    fn quad(&mut self, corners: [Point; 4], color: [f32; 4]) {
+        self.triangle([corners[0], corners[1], corners[2]], color);
+        self.triangle([corners[0], corners[2], corners[3]], color);
+    }
+
+    fn cone(&mut self, base: Point, tip: Point, (u, v): (Vector, Vector), radius: f32, color: [f32; 4]) {
+        let rim = |i: usize| {
+            let angle = i as f32 / TIP_SEGMENTS as f32 * std::f32::consts::TAU;
+            base + (u * angle.cos() + v * angle.sin()) * radius
+        };
+        for i in 0..TIP_SEGMENTS {
+            self.triangle([rim(i), rim(i + 1), tip], color);
+            self.triangle([base, rim(i + 1), rim(i)], color);
+        }
+    }
+
+    fn cube(&mut self, center: Point, axes: &[Vector; 3], half_size: f32, color: [f32; 4]) {
+        for axis in 0..3 {
+            let (u, v) = (axes[(axis + 1) % 3] * half_size, axes[(axis + 2) % 3] * half_size);
+            for side in [-1.0, 1.0] {
+                let face = center + axes[axis] * half_size * side;
+                self.quad([face - u - v, face + u - v, face + u + v, face - u + v], color);
+            }
+        }
+    }
+
+    fn ring(&mut self, center: Point, (u, v): (Vector, Vector), radius: f32, color: [f32; 4]) {
+        let point = |i: usize| {
+            let angle = i as f32 / RING_SEGMENTS as f32 * std::f32::consts::TAU;
+            center + (u * angle.cos() + v * angle.sin()) * radius
+        };
+        for i in 0..RING_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    fn build(frame: &GizmoFrame, mode: GizmoMode, highlighted: Option<GizmoHandle>) -> Self {
+        let mut mesh = Self::default();
+        let color = |handle: GizmoHandle, base: [f32; 4]| {
+            if highlighted == Some(handle) {
+                HIGHLIGHT_COLOR
+            } else {
+                base
+            }
+        };
+        let origin = frame.origin;
+        let size = frame.size;
+        for (axis, base_color) in AXIS_COLORS.into_iter().enumerate() {
+            let direction = frame.axes[axis];
+            let plane_axes = frame.plane_axes(axis);
+            let axis_color = color(GizmoHandle::Axis(axis), base_color);
+            match mode {
+                GizmoMode::Translate => {
+                    let base = origin + direction * size * (1.0 - TIP_LENGTH);
+                    mesh.line(origin + direction * size * AXIS_START, base, axis_color);
+                    mesh.cone(base, origin + direction * size, plane_axes, TIP_RADIUS * size, axis_color);
+
+                    let [r, g, b, _] = color(GizmoHandle::Plane(axis), base_color);
+                    let (u, v) = (plane_axes.0 * size, plane_axes.1 * size);
+                    mesh.quad(
+                        [
+                            origin + u * PLANE_MIN + v * PLANE_MIN,
+                            origin + u * PLANE_MAX + v * PLANE_MIN,
+                            origin + u * PLANE_MAX + v * PLANE_MAX,
+                            origin + u * PLANE_MIN + v * PLANE_MAX,
+                        ],
+                        [r, g, b, PLANE_ALPHA],
+                    );
+                }
+                GizmoMode::Rotate => mesh.ring(origin, plane_axes, size, axis_color),
+                GizmoMode::Scale => {
+                    let end = origin + direction * size;
+                    mesh.line(origin + direction * size * AXIS_START, end, axis_color);
+                    mesh.cube(end, &frame.axes, BOX_HALF_SIZE * size, axis_color);
+                }
+            }
+        }
+        if mode == GizmoMode::Scale {
+            let uniform_color = color(GizmoHandle::Uniform, UNIFORM_COLOR);
+            mesh.cube(origin, &frame.axes, UNIFORM_HALF_SIZE * size, uniform_color);
+        }
+        mesh
+    }
+}
+
+/// Translate, rotate and scale handles drawn over the selected instances.
+///
+/// Each frame `hover` the cursor ray to highlight the handle under it, then
+/// `begin_drag` on click, `drag` while the button is held and `end_drag` on
+/// release. The gizmo sits on the last selected instance (the pivot) and
+/// keeps the same size on screen.
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub snapping: Snapping,
+    /// Length of the handles as a fraction of the view height.
+    pub screen_size: f32,
+    hovered: Option<GizmoHandle>,
+    drag: Option<Drag>,
+    drag_count: u64,
+    triangle_pipeline: wgpu::RenderPipeline,
+    line_pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    vertex_capacity: usize,
+    num_triangle_vertices: u32,
+    num_line_vertices: u32,
+}
+
+impl Gizmo {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_entries: &[wgpu::BindGroupLayoutEntry],
+    ) -> anyhow::Result<Self> {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("gizmo.wgsl"));
+        let layout = PipelineLayout::new(device, "Gizmo Pipeline Layout", &[(camera_bind_group_layout, camera_entries)]);
+        // Handles are seen from every side and drawn without depth so the
+        // scene never hides them
+        let create_pipeline = |label, topology| {
+            PipelineBuilder::from_module(label, &layout, &shader, config.format)
+                .vertex_buffers(&[GizmoVertex::desc()])
+                .blend(Some(wgpu::BlendState::ALPHA_BLENDING))
+                .topology(topology)
+                .cull_mode(None)
+                .no_depth()
+                .build(device)
+        };
+        let triangle_pipeline = create_pipeline("Gizmo Triangle Pipeline", wgpu::PrimitiveTopology::TriangleList)?;
+        let line_pipeline = create_pipeline("Gizmo Line Pipeline", wgpu::PrimitiveTopology::LineList)?;
+        let vertex_capacity = 1024;
+        let vertex_buffer = create_vertex_buffer(device, vertex_capacity);
+        Ok(Self {
+            mode: GizmoMode::Translate,
+            snapping: Snapping::default(),
+            screen_size: 0.15,
+            hovered: None,
+            drag: None,
+            drag_count: 0,
+            triangle_pipeline,
+            line_pipeline,
+            vertex_buffer,
+            vertex_capacity,
+            num_triangle_vertices: 0,
+            num_line_vertices: 0,
+        })
+    }
+
+    /// The handle under the cursor, or the one being dragged.
+    pub fn hovered(&self) -> Option<GizmoHandle> {
+        self.drag.as_ref().map(|drag| drag.handle).or(self.hovered)
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Identifies the current drag, different for every drag.
+    pub fn drag_id(&self) -> Option<u64> {
+        self.drag.as_ref().map(|drag| drag.id)
+    }
+
+    /// Finds the handle under the cursor `ray` for a gizmo on `pivot`.
+    /// Without a pivot or ray nothing is hovered.
+    pub fn hover(&mut self, camera: &Camera, pivot: Option<&Instance>, ray: Option<&Ray>) {
+        self.hovered = match (pivot, ray) {
+            (Some(pivot), Some(ray)) => GizmoFrame::new(camera, self.mode, pivot, self.screen_size).hit(self.mode, ray),
+            _ => None,
+        };
+    }
+
+    /// Grabs the hovered handle to transform `targets`, the last of which
+    /// is the pivot. Returns false if no handle was hovered.
+    pub fn begin_drag(&mut self, camera: &Camera, ray: &Ray, targets: Vec<(InstanceHandle, Instance)>) -> bool {
+        let (Some(handle), Some((_, pivot))) = (self.hovered, targets.last()) else {
+            return false;
+        };
+        let mut drag = Drag {
+            id: self.drag_count,
+            mode: self.mode,
+            handle,
+            frame: GizmoFrame::new(camera, self.mode, pivot, self.screen_size),
+            view_direction: (camera.target - camera.eye).normalize(),
+            grab: Vector::zero(),
+            previous: Vector::zero(),
+            angle: 0.0,
+            start: Vec::new(),
+        };
+        let Some(grab) = drag.constrained_point(ray) else {
+            return false;
+        };
+        drag.grab = grab;
+        drag.previous = grab;
+        drag.start = targets;
+        self.drag = Some(drag);
+        self.drag_count += 1;
+        true
+    }
+
+    /// The transformed instances for the cursor on `ray`. None if nothing
+    /// is being dragged or the ray can't reach the handle's constraint.
+    pub fn drag(&mut self, ray: &Ray, snap: bool) -> Option<Vec<(InstanceHandle, Instance)>> {
+        let drag = self.drag.as_mut()?;
+        let current = drag.constrained_point(ray)?;
+        drag.track(current);
+        Some(drag.apply(current, snap.then_some(&self.snapping)))
+    }
+
+    /// Lets go of the handle.
+    pub fn end_drag(&mut self) {
+        self.drag = None;
+    }
+
+    /// Rebuilds the handles around `pivot` as seen by `camera`, or hides
+    /// them when there's no pivot.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, camera: &Camera, pivot: Option<&Instance>) {
+        let mesh = match pivot {
+            Some(pivot) => {
+                let frame = GizmoFrame::new(camera, self.mode, pivot, self.screen_size);
+                GizmoMesh::build(&frame, self.mode, self.hovered())
+            }
+            None => GizmoMesh::default(),
+        };
+        self.num_triangle_vertices = mesh.triangles.len() as u32;
+        self.num_line_vertices = mesh.lines.len() as u32;
+        let vertices = [mesh.triangles, mesh.lines].concat();
+        if vertices.is_empty() {
+            return;
+        }
+        if vertices.len() > self.vertex_capacity {
+            self.vertex_capacity = vertices.len().next_power_of_two();
+            self.vertex_buffer = create_vertex_buffer(device, self.vertex_capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+
+    /// Draws the handles over `target` within `view`.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        view: &View,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) {
+        if self.num_triangle_vertices + self.num_line_vertices == 0 {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Gizmo Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        let (x, y, width, height) = view.viewport.to_physical(size);
+        render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+        render_pass.set_scissor_rect(x, y, width, height);
+        render_pass.set_bind_group(0, view.camera_bind_group(), &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        let triangles_end = self.num_triangle_vertices;
+        render_pass.set_pipeline(&self.triangle_pipeline);
+        render_pass.draw(0..triangles_end, 0..1);
+        render_pass.set_pipeline(&self.line_pipeline);
+        render_pass.draw(triangles_end..triangles_end + self.num_line_vertices, 0..1);
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Gizmo Vertex Buffer"),
+        size: (capacity * std::mem::size_of::<GizmoVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(position: (f32, f32, f32), scale: (f32, f32, f32)) -> Instance {
+        Instance {
+            position: position.into(),
+            rotation: cgmath::Quaternion::one(),
+            scale: scale.into(),
+        }
+    }
+
+    // A gizmo one unit big on world axes
+    fn frame(origin: Point) -> GizmoFrame {
+        GizmoFrame {
+            origin,
+            axes: [Vector::unit_x(), Vector::unit_y(), Vector::unit_z()],
+            size: 1.0,
+        }
+    }
+
+    fn drag(mode: GizmoMode, handle: GizmoHandle, grab: Vector, start: Vec<Instance>) -> Drag {
+        let origin = Point::from_vec(start.last().expect("the pivot").position);
+        Drag {
+            id: 0,
+            mode,
+            handle,
+            frame: frame(origin),
+            view_direction: -Vector::unit_z(),
+            grab,
+            previous: grab,
+            angle: 0.0,
+            start: start.into_iter().enumerate().map(|(index, instance)| (InstanceHandle(index), instance)).collect(),
+        }
+    }
+
+    fn assert_near(a: Vector, b: Vector) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn translate_axis_snaps_to_steps() {
+        let mut drag = drag(
+            GizmoMode::Translate,
+            GizmoHandle::Axis(0),
+            Vector::new(0.5, 0.0, 0.0),
+            vec![instance((0.0, 1.0, 0.0), (1.0, 1.0, 1.0))],
+        );
+        // Looking down at the x axis from above a point 0.6 along
+        let ray = Ray::new(Point::new(1.1, 1.0, 5.0), Vector::new(0.0, -0.2, -1.0));
+        let current = drag.constrained_point(&ray).expect("ray should reach the axis");
+        assert_near(current, Vector::new(1.1, 0.0, 0.0));
+        drag.track(current);
+        assert_near(drag.apply(current, None)[0].1.position, Vector::new(0.6, 1.0, 0.0));
+        let snapping = Snapping::default();
+        assert_near(drag.apply(current, Some(&snapping))[0].1.position, Vector::new(0.5, 1.0, 0.0));
+        let snapping = Snapping { translate: 0.4, ..Snapping::default() };
+        assert_near(drag.apply(current, Some(&snapping))[0].1.position, Vector::new(0.8, 1.0, 0.0));
+    }
+
+    #[test]
+    fn rotate_keeps_turning_past_half_a_turn() {
+        let pivot = instance((0.0, 0.0, 0.0), (1.0, 1.0, 1.0));
+        let other = instance((2.0, 0.0, 0.0), (1.0, 1.0, 1.0));
+        let mut drag = drag(GizmoMode::Rotate, GizmoHandle::Axis(2), Vector::unit_x(), vec![other, pivot]);
+        // Three quarters of a turn around z in 30 degree steps
+        let mut current = Vector::unit_x();
+        for step in 1..=9 {
+            let angle = cgmath::Deg(30.0 * step as f32);
+            current = Vector::new(angle.cos(), angle.sin(), 0.0) * 0.9;
+            drag.track(current);
+        }
+        assert!((drag.angle - 1.5 * std::f32::consts::PI).abs() < 1e-4, "{}", drag.angle);
+        let moved = drag.apply(current, None);
+        // Swung around the pivot to where -90 degrees would also put it
+        assert_near(moved[0].1.position, Vector::new(0.0, -2.0, 0.0));
+        assert_near(moved[1].1.position, Vector::zero());
+        let rotated = moved[0].1.rotation.rotate_vector(Vector::unit_x());
+        assert_near(rotated, -Vector::unit_y());
+
+        // Going back past the start turns the other way
+        for step in (-3..9).rev() {
+            let angle = cgmath::Deg(30.0 * step as f32);
+            drag.track(Vector::new(angle.cos(), angle.sin(), 0.0));
+        }
+        assert!((drag.angle + 0.5 * std::f32::consts::PI).abs() < 1e-4, "{}", drag.angle);
+    }
+
+    #[test]
+    fn scaling_moves_instances_away_from_the_pivot() {
+        let other = instance((3.0, 1.0, 0.0), (1.0, 2.0, 1.0));
+        let pivot = instance((1.0, 1.0, 0.0), (1.0, 1.0, 1.0));
+        let targets = vec![other, pivot];
+
+        let axis = drag(GizmoMode::Scale, GizmoHandle::Axis(0), Vector::new(0.5, 0.0, 0.0), targets.clone());
+        let scaled = axis.apply(Vector::new(1.0, 0.0, 0.0), None);
+        assert_near(scaled[0].1.position, Vector::new(5.0, 1.0, 0.0));
+        assert_near(scaled[0].1.scale, Vector::new(2.0, 2.0, 1.0));
+        assert_near(scaled[1].1.position, Vector::new(1.0, 1.0, 0.0));
+        assert_near(scaled[1].1.scale, Vector::new(2.0, 1.0, 1.0));
+
+        let uniform = drag(GizmoMode::Scale, GizmoHandle::Uniform, Vector::new(0.5, 0.0, 0.0), targets);
+        let scaled = uniform.apply(Vector::new(0.0, 1.5, 0.0), None);
+        assert_near(scaled[0].1.position, Vector::new(7.0, 1.0, 0.0));
+        assert_near(scaled[0].1.scale, Vector::new(3.0, 6.0, 3.0));
+        assert_near(scaled[1].1.position, Vector::new(1.0, 1.0, 0.0));
+        assert_near(scaled[1].1.scale, Vector::new(3.0, 3.0, 3.0));
+    }
+
+    #[test]
+    fn scaling_never_flattens_or_mirrors() {
+        let drag = drag(
+            GizmoMode::Scale,
+            GizmoHandle::Axis(1),
+            Vector::new(0.0, 0.5, 0.0),
+            vec![instance((0.0, 0.0, 0.0), (2.0, 2.0, 2.0))],
+        );
+        for current in [Vector::zero(), Vector::new(0.0, -0.5, 0.0), Vector::new(0.0, 0.001, 0.0)] {
+            for snapping in [None, Some(&Snapping::default())] {
+                let scale = drag.apply(current, snapping)[0].1.scale;
+                assert_near(scale, Vector::new(2.0, 2.0 * MIN_SCALE_FACTOR, 2.0));
+            }
+        }
+    }
+
+    #[test]
+    fn hit_picks_the_nearest_handle() {
+        let frame = frame(Point::new(0.0, 0.0, 0.0));
+        // Through the xz square first and then the xy one
+        let ray = Ray::new(Point::new(0.35, -1.0, 1.35), Vector::new(0.0, 1.0, -1.0));
+        assert_eq!(frame.hit(GizmoMode::Translate, &ray), Some(GizmoHandle::Plane(1)));
+        let ray = Ray::new(Point::new(0.35, 1.35, -1.0), Vector::new(0.0, -1.0, 1.0));
+        assert_eq!(frame.hit(GizmoMode::Translate, &ray), Some(GizmoHandle::Plane(2)));
+
+        // Through the x ring at (0, 0.6, 0.8) and the y ring at (0.8, 0, -0.6)
+        let direction = Vector::new(0.8, -0.6, -1.4);
+        let ray = Ray::new(Point::new(0.0, 0.6, 0.8) - direction, direction);
+        assert_eq!(frame.hit(GizmoMode::Rotate, &ray), Some(GizmoHandle::Axis(0)));
+        let ray = Ray::new(Point::new(0.8, 0.0, -0.6) + direction, -direction);
+        assert_eq!(frame.hit(GizmoMode::Rotate, &ray), Some(GizmoHandle::Axis(1)));
+
+        // Between the handles there's nothing to grab
+        let ray = Ray::new(Point::new(0.1, 0.1, 5.0), -Vector::unit_z());
+        assert_eq!(frame.hit(GizmoMode::Translate, &ray), None);
+        let ray = Ray::new(Point::new(0.0, 0.0, 5.0), -Vector::unit_z());
+        assert_eq!(frame.hit(GizmoMode::Scale, &ray), Some(GizmoHandle::Uniform));
+    }
+}
+
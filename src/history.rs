@@ -0,0 +1,445 @@
+use std::any::Any;
+use std::collections::VecDeque;
+
+use crate::camera::CameraPose;
+use crate::{Instance, InstanceHandle};
+
+/// The state commands edit, implemented by `State`.
+pub trait EditTarget {
+    fn set_instance(&mut self, handle: InstanceHandle, instance: Instance);
+    /// The look toggled by the `toggle_texture` action: everything as
+    /// textured hexagons when on, the scene's own meshes and materials when
+    /// off.
+    fn set_texture_toggle(&mut self, on: bool);
+    /// Moves the main view's camera.
+    fn set_camera_pose(&mut self, pose: CameraPose);
+}
+
+/// A reversible edit.
+pub trait Command {
+    /// Describes the edit in the log when undoing and redoing.
+    fn name(&self) -> &str;
+    fn apply(&self, target: &mut dyn EditTarget);
+    fn revert(&self, target: &mut dyn EditTarget);
+    /// Absorbs `next`, executed straight after this command, so both undo
+    /// as one step. Returns false to keep them separate.
+    fn merge(&mut self, _next: &dyn Command) -> bool {
+        false
+    }
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// One instance changed by `SetInstances`.
+#[derive(Clone)]
+pub struct InstanceChange {
+    pub instance: InstanceHandle,
+    pub before: Instance,
+    pub after: Instance,
+}
+
+/// Moves, rotates or scales instances.
+#[derive(Clone)]
+pub struct SetInstances {
+    pub changes: Vec<InstanceChange>,
+    /// Commands with the same id that change the same instances merge,
+    /// e.g. every frame of one gizmo drag.
+    pub merge_id: Option<u64>,
+}
+
+impl Command for SetInstances {
+    fn name(&self) -> &str {
+        "transform instances"
+    }
+
+    fn apply(&self, target: &mut dyn EditTarget) {
+        for change in self.changes.iter() {
+            target.set_instance(change.instance, change.after.clone());
+        }
+    }
+
+    fn revert(&self, target: &mut dyn EditTarget) {
+        for change in self.changes.iter().rev() {
+            target.set_instance(change.instance, change.before.clone());
+        }
+    }
+
+    fn merge(&mut self, next: &dyn Command) -> bool {
+        let Some(next) = next.as_any().downcast_ref::<SetInstances>() else {
+            return false;
+        };
+        let same_instances = self.changes.len() == next.changes.len()
+            && self.changes.iter().zip(next.changes.iter()).all(|(a, b)| a.instance == b.instance);
+        if self.merge_id.is_none() || self.merge_id != next.merge_id || !same_instances {
+            return false;
+        }
+        for (change, next) in self.changes.iter_mut().zip(next.changes.iter()) {
+            change.after = next.after.clone();
+        }
+        true
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Switches the material, see `EditTarget::set_texture_toggle`.
+#[derive(Copy, Clone)]
+pub struct SetTextureToggle {
+    pub before: bool,
+    pub after: bool,
+}
+
+impl Command for SetTextureToggle {
+    fn name(&self) -> &str {
+        "toggle texture"
+    }
+
+    fn apply(&self, target: &mut dyn EditTarget) {
+        target.set_texture_toggle(self.after);
+    }
+
+    fn revert(&self, target: &mut dyn EditTarget) {
+        target.set_texture_toggle(self.before);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Moves the main camera.
+#[derive(Copy, Clone)]
+pub struct SetCamera {
+    pub before: CameraPose,
+    pub after: CameraPose,
+}
+
+impl Command for SetCamera {
+    fn name(&self) -> &str {
+        "move camera"
+    }
+
+    fn apply(&self, target: &mut dyn EditTarget) {
+        target.set_camera_pose(self.after);
+    }
+
+    fn revert(&self, target: &mut dyn EditTarget) {
+        target.set_camera_pose(self.before);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Commands grouped into one undo step, applied in order and reverted in
+/// reverse.
+pub struct Transaction {
+    pub name: String,
+    pub commands: Vec<Box<dyn Command>>,
+}
+
+impl Command for Transaction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&self, target: &mut dyn EditTarget) {
+        for command in self.commands.iter() {
+            command.apply(target);
+        }
+    }
+
+    fn revert(&self, target: &mut dyn EditTarget) {
+        for command in self.commands.iter().rev() {
+            command.revert(target);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// Merges `command` into `last` if it can, handing it back otherwise.
+fn merge_into(last: Option<&mut Box<dyn Command>>, command: Box<dyn Command>) -> Option<Box<dyn Command>> {
+    if let Some(last) = last {
+        if last.merge(command.as_ref()) {
+            return None;
+        }
+    }
+    Some(command)
+}
+
+/// Undo and redo stacks of commands.
+///
+/// `execute` applies and records a command. Commands executed between
+/// `begin_transaction` and `commit_transaction` become one undo step, and
+/// the oldest steps are forgotten past `limit`.
+pub struct History {
+    /// Most undo steps kept.
+    pub limit: usize,
+    undo: VecDeque<Box<dyn Command>>,
+    redo: Vec<Box<dyn Command>>,
+    transaction: Option<Transaction>,
+    // Nested transactions join the outermost one
+    transaction_depth: usize,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            transaction: None,
+            transaction_depth: 0,
+        }
+    }
+
+    /// Applies `command` to `target` and records it.
+    pub fn execute(&mut self, command: Box<dyn Command>, target: &mut dyn EditTarget) {
+        command.apply(target);
+        self.record(command);
+    }
+
+    /// Records a command whose edit has already been made, merging it into
+    /// the previous command where possible. Clears the redo stack.
+    pub fn record(&mut self, command: Box<dyn Command>) {
+        self.redo.clear();
+        if let Some(transaction) = self.transaction.as_mut() {
+            if let Some(command) = merge_into(transaction.commands.last_mut(), command) {
+                transaction.commands.push(command);
+            }
+            return;
+        }
+        if let Some(command) = merge_into(self.undo.back_mut(), command) {
+            self.undo.push_back(command);
+        }
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Groups the commands executed until the matching `commit_transaction`
+    /// into one undo step called `name`.
+    pub fn begin_transaction(&mut self, name: impl Into<String>) {
+        self.transaction_depth += 1;
+        if self.transaction.is_none() {
+            self.transaction = Some(Transaction {
+                name: name.into(),
+                commands: Vec::new(),
+            });
+        }
+    }
+
+    /// Closes the innermost transaction, recording the group once the
+    /// outermost one closes. Empty transactions are dropped.
+    pub fn commit_transaction(&mut self) {
+        self.transaction_depth = self.transaction_depth.saturating_sub(1);
+        if self.transaction_depth > 0 {
+            return;
+        }
+        if let Some(transaction) = self.transaction.take().filter(|transaction| !transaction.commands.is_empty()) {
+            self.record(Box::new(transaction));
+        }
+    }
+
+    /// Reverts everything executed since `begin_transaction` and drops it,
+    /// closing every open transaction.
+    pub fn cancel_transaction(&mut self, target: &mut dyn EditTarget) {
+        self.transaction_depth = 0;
+        if let Some(transaction) = self.transaction.take() {
+            transaction.revert(target);
+        }
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() && !self.in_transaction()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty() && !self.in_transaction()
+    }
+
+    /// Reverts the last undo step. Does nothing during a transaction.
+    /// Returns the step's name.
+    pub fn undo(&mut self, target: &mut dyn EditTarget) -> Option<String> {
+        if self.in_transaction() {
+            return None;
+        }
+        let command = self.undo.pop_back()?;
+        command.revert(target);
+        let name = command.name().to_string();
+        self.redo.push(command);
+        Some(name)
+    }
+
+    /// Reapplies the last undone step. Does nothing during a transaction.
+    /// Returns the step's name.
+    pub fn redo(&mut self, target: &mut dyn EditTarget) -> Option<String> {
+        if self.in_transaction() {
+            return None;
+        }
+        let command = self.redo.pop()?;
+        command.apply(target);
+        let name = command.name().to_string();
+        self.undo.push_back(command);
+        Some(name)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.transaction = None;
+        self.transaction_depth = 0;
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::prelude::*;
+
+    #[derive(Default)]
+    struct Target {
+        positions: Vec<f32>,
+        texture: bool,
+    }
+
+    impl EditTarget for Target {
+        fn set_instance(&mut self, handle: InstanceHandle, instance: Instance) {
+            self.positions[handle.0] = instance.position.x;
+        }
+
+        fn set_texture_toggle(&mut self, on: bool) {
+            self.texture = on;
+        }
+
+        fn set_camera_pose(&mut self, _pose: CameraPose) {}
+    }
+
+    fn at(x: f32) -> Instance {
+        Instance {
+            position: cgmath::Vector3::new(x, 0.0, 0.0),
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    fn move_instance(index: usize, before: f32, after: f32, merge_id: Option<u64>) -> Box<dyn Command> {
+        Box::new(SetInstances {
+            changes: vec![InstanceChange {
+                instance: InstanceHandle(index),
+                before: at(before),
+                after: at(after),
+            }],
+            merge_id,
+        })
+    }
+
+    fn toggle(before: bool) -> Box<dyn Command> {
+        Box::new(SetTextureToggle { before, after: !before })
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut target = Target { positions: vec![0.0], ..Default::default() };
+        let mut history = History::default();
+        history.execute(move_instance(0, 0.0, 1.0, None), &mut target);
+        history.execute(toggle(false), &mut target);
+        assert_eq!((target.positions[0], target.texture), (1.0, true));
+
+        assert_eq!(history.undo(&mut target).as_deref(), Some("toggle texture"));
+        assert_eq!(history.undo(&mut target).as_deref(), Some("transform instances"));
+        assert_eq!((target.positions[0], target.texture), (0.0, false));
+        assert_eq!(history.undo(&mut target), None);
+
+        assert_eq!(history.redo(&mut target).as_deref(), Some("transform instances"));
+        assert_eq!(target.positions[0], 1.0);
+        // A new edit forgets what was undone
+        history.execute(move_instance(0, 1.0, 2.0, None), &mut target);
+        assert!(!history.can_redo());
+        assert_eq!(history.redo(&mut target), None);
+    }
+
+    #[test]
+    fn oldest_steps_are_forgotten_past_the_limit() {
+        let mut target = Target { positions: vec![0.0], ..Default::default() };
+        let mut history = History::new(2);
+        for step in 0..3 {
+            history.execute(move_instance(0, step as f32, step as f32 + 1.0, None), &mut target);
+        }
+        assert!(history.undo(&mut target).is_some());
+        assert!(history.undo(&mut target).is_some());
+        assert!(history.undo(&mut target).is_none());
+        assert_eq!(target.positions[0], 1.0);
+    }
+
+    #[test]
+    fn transactions_undo_as_one_step() {
+        let mut target = Target { positions: vec![0.0, 0.0], ..Default::default() };
+        let mut history = History::default();
+        history.begin_transaction("move both");
+        history.execute(move_instance(0, 0.0, 1.0, None), &mut target);
+        // Nested transactions join the outer one
+        history.begin_transaction("inner");
+        history.execute(move_instance(1, 0.0, 2.0, None), &mut target);
+        history.commit_transaction();
+        assert!(history.in_transaction());
+        assert!(!history.can_undo());
+        history.commit_transaction();
+
+        assert_eq!(history.undo(&mut target).as_deref(), Some("move both"));
+        assert_eq!(target.positions, [0.0, 0.0]);
+        assert!(!history.can_undo());
+        assert_eq!(history.redo(&mut target).as_deref(), Some("move both"));
+        assert_eq!(target.positions, [1.0, 2.0]);
+
+        // Cancelling puts everything back and records nothing
+        history.begin_transaction("cancelled");
+        history.execute(move_instance(0, 1.0, 5.0, None), &mut target);
+        history.cancel_transaction(&mut target);
+        assert_eq!(target.positions, [1.0, 2.0]);
+        assert_eq!(history.undo(&mut target).as_deref(), Some("move both"));
+
+        // So does committing an empty one
+        history.begin_transaction("empty");
+        history.commit_transaction();
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn commands_with_the_same_merge_id_merge() {
+        let mut target = Target { positions: vec![0.0, 0.0], ..Default::default() };
+        let mut history = History::default();
+        history.execute(move_instance(0, 0.0, 1.0, Some(7)), &mut target);
+        history.execute(move_instance(0, 1.0, 2.0, Some(7)), &mut target);
+        // A different id, a different instance or no id at all don't merge
+        history.execute(move_instance(0, 2.0, 3.0, Some(8)), &mut target);
+        history.execute(move_instance(1, 0.0, 1.0, Some(8)), &mut target);
+        history.execute(move_instance(1, 1.0, 2.0, None), &mut target);
+        history.execute(move_instance(1, 2.0, 3.0, None), &mut target);
+
+        for _ in 0..3 {
+            history.undo(&mut target);
+        }
+        assert_eq!(target.positions, [3.0, 0.0]);
+        history.undo(&mut target);
+        assert_eq!(target.positions, [2.0, 0.0]);
+        history.undo(&mut target);
+        assert_eq!(target.positions, [0.0, 0.0]);
+        assert!(!history.can_undo());
+    }
+}
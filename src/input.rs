@@ -0,0 +1,653 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use winit::event::*;
+
+use crate::gamepad::{GamepadAxis, GamepadButton, GamepadSettings, GamepadState};
+use crate::touch::TouchGestures;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ScrollDirection {
+    Up,
+    Down,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AxisDirection {
+    Positive,
+    Negative,
+}
+
+/// A physical input that can trigger an action.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    /// Fires once per scroll wheel notch.
+    Scroll(ScrollDirection),
+    /// A quick touch with one finger.
+    Tap,
+    Gamepad(GamepadButton),
+    /// One half of a gamepad stick or a trigger. Counts as held once past
+    /// `GamepadSettings::press_threshold`, and is analog in axes.
+    GamepadAxis(GamepadAxis, AxisDirection),
+}
+
+impl Binding {
+    fn is_gamepad(&self) -> bool {
+        matches!(self, Binding::Gamepad(_) | Binding::GamepadAxis(..))
+    }
+}
+
+/// Two sets of bindings combined into a value in -1..1, e.g. W and S for
+/// moving forwards and backwards.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AxisBinding {
+    #[serde(default)]
+    pub positive: Vec<Binding>,
+    #[serde(default)]
+    pub negative: Vec<Binding>,
+}
+
+/// The named actions and axes and what they're bound to, as stored in an
+/// input config file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputConfig {
+    #[serde(default)]
+    pub actions: BTreeMap<String, Vec<Binding>>,
+    #[serde(default)]
+    pub axes: BTreeMap<String, AxisBinding>,
+    #[serde(default)]
+    pub gamepad: GamepadSettings,
+}
+
+impl InputConfig {
+    /// The bindings the app ships with.
+    pub fn defaults() -> Self {
+        use AxisDirection::*;
+        use Binding::*;
+        use crate::gamepad::GamepadAxis as Pad;
+        use VirtualKeyCode as K;
+        let actions = [
+            ("quit", vec![Key(K::Escape)]),
+            ("toggle_texture", vec![Key(K::Space), Gamepad(GamepadButton::South), Tap]),
+            ("toggle_camera_mode", vec![Key(K::C), Gamepad(GamepadButton::North)]),
+            ("cycle_view_layout", vec![Key(K::V), Gamepad(GamepadButton::Select)]),
+            ("record_keyframe", vec![Key(K::K), Gamepad(GamepadButton::West)]),
+            ("play_camera_path", vec![Key(K::P), Gamepad(GamepadButton::Start)]),
+            ("save_camera_path", vec![Key(K::F5)]),
+            ("save_scene", vec![Key(K::F6)]),
+            ("sprint", vec![Key(K::LShift), Key(K::RShift), Gamepad(GamepadButton::LeftStick)]),
+            ("orbit_rotate", vec![Mouse(MouseButton::Left)]),
+            ("orbit_pan", vec![Mouse(MouseButton::Middle)]),
+            ("pan_modifier", vec![Key(K::LShift), Key(K::RShift)]),
+            ("mouse_look", vec![Mouse(MouseButton::Left)]),
+            ("pick", vec![Mouse(MouseButton::Left)]),
+            ("extend_selection", vec![Key(K::LControl), Key(K::RControl)]),
+            ("gizmo_drag", vec![Mouse(MouseButton::Left)]),
+            ("gizmo_snap", vec![Key(K::LAlt), Key(K::RAlt)]),
+            ("gizmo_cancel", vec![Mouse(MouseButton::Right)]),
+            ("gizmo_translate", vec![Key(K::Key1)]),
+            ("gizmo_rotate", vec![Key(K::Key2)]),
+            ("gizmo_scale", vec![Key(K::Key3)]),
+            ("wireframe_view", vec![Key(K::Key4)]),
+            ("normals_view", vec![Key(K::Key5)]),
+            ("uv_checker_view", vec![Key(K::Key6)]),
+            ("depth_view", vec![Key(K::Key7)]),
+            ("overdraw_view", vec![Key(K::Key8)]),
+            ("instance_id_view", vec![Key(K::Key9)]),
+            ("shaded_view", vec![Key(K::Key0)]),
+            ("undo", vec![Key(K::Z)]),
+            ("redo", vec![Key(K::Y)]),
+        ];
+        let axes = [
+            (
+                "move_forward",
+                vec![Key(K::W), Key(K::Up), GamepadAxis(Pad::LeftStickY, Positive)],
+                vec![Key(K::S), Key(K::Down), GamepadAxis(Pad::LeftStickY, Negative)],
+            ),
+            (
+                "move_right",
+                vec![Key(K::D), Key(K::Right), GamepadAxis(Pad::LeftStickX, Positive)],
+                vec![Key(K::A), Key(K::Left), GamepadAxis(Pad::LeftStickX, Negative)],
+            ),
+            (
+                "move_up",
+                vec![Key(K::E), GamepadAxis(Pad::RightTrigger, Positive)],
+                vec![Key(K::Q), GamepadAxis(Pad::LeftTrigger, Positive)],
+            ),
+            ("zoom", vec![Scroll(ScrollDirection::Up)], vec![Scroll(ScrollDirection::Down)]),
+            (
+                "look_right",
+                vec![GamepadAxis(Pad::RightStickX, Positive)],
+                vec![GamepadAxis(Pad::RightStickX, Negative)],
+            ),
+            (
+                "look_up",
+                vec![GamepadAxis(Pad::RightStickY, Positive)],
+                vec![GamepadAxis(Pad::RightStickY, Negative)],
+            ),
+        ];
+        Self {
+            actions: actions.into_iter().map(|(name, bindings)| (name.to_string(), bindings)).collect(),
+            axes: axes
+                .into_iter()
+                .map(|(name, positive, negative)| (name.to_string(), AxisBinding { positive, negative }))
+                .collect(),
+            gamepad: GamepadSettings::default(),
+        }
+    }
+
+    /// Reads bindings from a RON (or `.json`) file. Actions and axes missing
+    /// from the file keep their default bindings.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+        let loaded: Self = if is_json(path) {
+            serde_json::from_str(&text).with_context(|| format!("Invalid input config {}", path.display()))?
+        } else {
+            ron::from_str(&text).with_context(|| format!("Invalid input config {}", path.display()))?
+        };
+        let mut config = Self::defaults();
+        config.actions.extend(loaded.actions);
+        config.axes.extend(loaded.axes);
+        config.gamepad = loaded.gamepad;
+        Ok(config)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?
+        };
+        std::fs::write(path, text).with_context(|| format!("Couldn't write {}", path.display()))
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+/// Tracks the state of every binding and answers queries by action name.
+///
+/// Feed it window and device events as they arrive, query it during the
+/// frame, then call `end_frame` so "pressed" and "released" only report
+/// transitions that happened since the previous frame.
+pub struct InputMap {
+    config: InputConfig,
+    held: HashSet<Binding>,
+    pressed: HashSet<Binding>,
+    released: HashSet<Binding>,
+    scroll: [f32; 2],
+    cursor: Option<winit::dpi::PhysicalPosition<f64>>,
+    cursor_delta: (f32, f32),
+    mouse_motion: (f32, f32),
+    // After deadzones, strongest value across all gamepads
+    gamepad_axes: [f32; GamepadAxis::COUNT],
+    touch: TouchGestures,
+    capture: Option<String>,
+}
+
+impl InputMap {
+    pub fn new(config: InputConfig) -> Self {
+        Self {
+            config,
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            released: HashSet::new(),
+            scroll: [0.0; 2],
+            cursor: None,
+            cursor_delta: (0.0, 0.0),
+            mouse_motion: (0.0, 0.0),
+            gamepad_axes: [0.0; GamepadAxis::COUNT],
+            touch: TouchGestures::new(),
+            capture: None,
+        }
+    }
+
+    pub fn config(&self) -> &InputConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: InputConfig) {
+        self.config = config;
+    }
+
+    /// Adds a binding to an action, creating the action if needed.
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        let bindings = self.config.actions.entry(action.to_string()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: &str, binding: Binding) {
+        if let Some(bindings) = self.config.actions.get_mut(action) {
+            bindings.retain(|b| *b != binding);
+        }
+    }
+
+    /// Replaces every binding of an action with `binding`.
+    pub fn rebind(&mut self, action: &str, binding: Binding) {
+        self.config.actions.insert(action.to_string(), vec![binding]);
+    }
+
+    /// Binds the next key, button or scroll the user presses to `action`,
+    /// replacing its current bindings. That input is swallowed.
+    pub fn rebind_next(&mut self, action: &str) {
+        self.capture = Some(action.to_string());
+    }
+
+    /// Updates binding state from a window event. Returns true if the event
+    /// was a bound input.
+    pub fn process_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                ..
+            } => self.set_binding(Binding::Key(*keycode), *state == ElementState::Pressed),
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.set_binding(Binding::Mouse(*button), *state == ElementState::Pressed)
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // Roughly one line per 100 pixels on touchpads
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 100.0,
+                };
+                let direction = if lines >= 0.0 { ScrollDirection::Up } else { ScrollDirection::Down };
+                self.scroll[direction as usize] += lines.abs();
+                self.trigger(Binding::Scroll(direction))
+            }
+            WindowEvent::Touch(touch) if self.touch.process(touch) => self.trigger(Binding::Tap),
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some(previous) = self.cursor.replace(*position) {
+                    self.cursor_delta.0 += (position.x - previous.x) as f32;
+                    self.cursor_delta.1 += (position.y - previous.y) as f32;
+                }
+                false
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                false
+            }
+            WindowEvent::Focused(false) => {
+                // Keys released while unfocused never reach us
+                self.released.extend(self.held.drain());
+                self.touch.clear();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    pub fn process_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
+            self.mouse_motion.0 += *dx as f32;
+            self.mouse_motion.1 += *dy as f32;
+        }
+    }
+
+    /// Updates gamepad bindings from the connected gamepads. Call once per
+    /// frame, before reading the input; with no gamepads connected every
+    /// gamepad binding is released.
+    pub fn update_gamepads(&mut self, pads: &[GamepadState]) {
+        let settings = self.config.gamepad;
+        let mut axes = [0.0_f32; GamepadAxis::COUNT];
+        let mut held = HashSet::new();
+        for pad in pads {
+            for (value, pad_value) in axes.iter_mut().zip(settings.apply(pad)) {
+                if pad_value.abs() > value.abs() {
+                    *value = pad_value;
+                }
+            }
+            held.extend(pad.buttons.iter().map(|button| Binding::Gamepad(*button)));
+        }
+        for axis in GamepadAxis::ALL {
+            let value = axes[axis as usize];
+            if value > settings.press_threshold {
+                held.insert(Binding::GamepadAxis(axis, AxisDirection::Positive));
+            } else if value < -settings.press_threshold {
+                held.insert(Binding::GamepadAxis(axis, AxisDirection::Negative));
+            }
+        }
+        self.gamepad_axes = axes;
+
+        let released = self
+            .held
+            .iter()
+            .filter(|binding| binding.is_gamepad() && !held.contains(*binding))
+            .copied()
+            .collect::<Vec<_>>();
+        for binding in released {
+            self.set_binding(binding, false);
+        }
+        for binding in held {
+            self.set_binding(binding, true);
+        }
+    }
+
+    fn set_binding(&mut self, binding: Binding, is_pressed: bool) -> bool {
+        if is_pressed {
+            if self.capture_binding(binding) {
+                // Held so it doesn't count as a fresh press next frame
+                self.held.insert(binding);
+                return true;
+            }
+            // Ignore key repeat
+            if self.held.insert(binding) {
+                self.pressed.insert(binding);
+            }
+        } else if self.held.remove(&binding) {
+            self.released.insert(binding);
+        }
+        self.is_bound(binding)
+    }
+
+    // Presses and releases a binding that has no held state, like a scroll
+    // notch or a tap.
+    fn trigger(&mut self, binding: Binding) -> bool {
+        if self.capture_binding(binding) {
+            return true;
+        }
+        self.pressed.insert(binding);
+        self.released.insert(binding);
+        self.is_bound(binding)
+    }
+
+    fn capture_binding(&mut self, binding: Binding) -> bool {
+        match self.capture.take() {
+            Some(action) => {
+                log::info!("Bound {:?} to {}", binding, action);
+                self.rebind(&action, binding);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn is_bound(&self, binding: Binding) -> bool {
+        self.config.actions.values().any(|bindings| bindings.contains(&binding))
+            || self
+                .config
+                .axes
+                .values()
+                .any(|axis| axis.positive.contains(&binding) || axis.negative.contains(&binding))
+    }
+
+    fn any(&self, action: &str, set: &HashSet<Binding>) -> bool {
+        self.config
+            .actions
+            .get(action)
+            .is_some_and(|bindings| bindings.iter().any(|binding| set.contains(binding)))
+    }
+
+    /// True on the frame any binding of `action` went down.
+    pub fn pressed(&self, action: &str) -> bool {
+        self.any(action, &self.pressed)
+    }
+
+    /// True while any binding of `action` is down.
+    pub fn held(&self, action: &str) -> bool {
+        self.any(action, &self.held)
+    }
+
+    /// True on the frame any binding of `action` went up.
+    pub fn released(&self, action: &str) -> bool {
+        self.any(action, &self.released)
+    }
+
+    /// The value of an axis: held keys and buttons count as 1, scroll
+    /// bindings count the notches scrolled this frame and gamepad axes give
+    /// their analog value.
+    pub fn axis(&self, axis: &str) -> f32 {
+        let Some(axis) = self.config.axes.get(axis) else {
+            return 0.0;
+        };
+        let value = |bindings: &[Binding]| {
+            bindings
+                .iter()
+                .map(|binding| match binding {
+                    Binding::Scroll(direction) => self.scroll[*direction as usize],
+                    Binding::GamepadAxis(axis, direction) => {
+                        let value = self.gamepad_axes[*axis as usize];
+                        match direction {
+                            AxisDirection::Positive => value.max(0.0),
+                            AxisDirection::Negative => (-value).max(0.0),
+                        }
+                    }
+                    binding if self.held.contains(binding) => 1.0,
+                    _ => 0.0,
+                })
+                .fold(0.0, f32::max)
+        };
+        value(&axis.positive) - value(&axis.negative)
+    }
+
+    /// How far the cursor moved in pixels this frame.
+    pub fn cursor_delta(&self) -> (f32, f32) {
+        self.cursor_delta
+    }
+
+    /// Drops this frame's cursor movement so nothing else reacts to it, for
+    /// when something like a gizmo drag has taken the mouse.
+    pub fn consume_cursor_delta(&mut self) {
+        self.cursor_delta = (0.0, 0.0);
+    }
+
+    pub fn cursor_position(&self) -> Option<winit::dpi::PhysicalPosition<f64>> {
+        self.cursor
+    }
+
+    /// Touch gestures made this frame.
+    pub fn touch(&self) -> &TouchGestures {
+        &self.touch
+    }
+
+    /// Raw, unaccelerated mouse motion this frame. Keeps reporting while the
+    /// cursor is grabbed.
+    pub fn mouse_motion(&self) -> (f32, f32) {
+        self.mouse_motion
+    }
+
+    /// Clears per frame state; call after everything has read the input.
+    /// `delta` is the length of the frame in seconds.
+    pub fn end_frame(&mut self, delta: f32) {
+        self.pressed.clear();
+        self.released.clear();
+        self.scroll = [0.0; 2];
+        self.cursor_delta = (0.0, 0.0);
+        self.mouse_motion = (0.0, 0.0);
+        self.touch.end_frame(delta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file in the temp directory, removed again when dropped
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, text: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("input-test-{}-{}", std::process::id(), name));
+            std::fs::write(&path, text).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[allow(deprecated)]
+    fn key(keycode: VirtualKeyCode, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            // SAFETY: only compared by the input map, never passed to winit
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(keycode),
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    fn press(input: &mut InputMap, keycode: VirtualKeyCode) -> bool {
+        input.process_event(&key(keycode, ElementState::Pressed))
+    }
+
+    fn release(input: &mut InputMap, keycode: VirtualKeyCode) -> bool {
+        input.process_event(&key(keycode, ElementState::Released))
+    }
+
+    #[test]
+    fn pressed_and_released_only_last_a_frame() {
+        let mut input = InputMap::new(InputConfig::defaults());
+        assert!(press(&mut input, VirtualKeyCode::Escape));
+        assert!(input.pressed("quit") && input.held("quit") && !input.released("quit"));
+        input.end_frame(0.016);
+        assert!(!input.pressed("quit") && input.held("quit"));
+        assert!(release(&mut input, VirtualKeyCode::Escape));
+        assert!(input.released("quit") && !input.held("quit"));
+        input.end_frame(0.016);
+        assert!(!input.pressed("quit") && !input.held("quit") && !input.released("quit"));
+        // Unbound keys are tracked but don't count as handled
+        assert!(!press(&mut input, VirtualKeyCode::F12));
+    }
+
+    #[test]
+    fn a_press_and_release_in_one_frame_is_seen() {
+        let mut input = InputMap::new(InputConfig::defaults());
+        press(&mut input, VirtualKeyCode::Z);
+        release(&mut input, VirtualKeyCode::Z);
+        assert!(input.pressed("undo") && input.released("undo") && !input.held("undo"));
+    }
+
+    #[test]
+    fn key_repeat_doesnt_press_again() {
+        let mut input = InputMap::new(InputConfig::defaults());
+        press(&mut input, VirtualKeyCode::Space);
+        assert!(input.pressed("toggle_texture"));
+        input.end_frame(0.016);
+        // The OS repeats the press while the key is held
+        press(&mut input, VirtualKeyCode::Space);
+        press(&mut input, VirtualKeyCode::Space);
+        assert!(!input.pressed("toggle_texture"));
+        assert!(input.held("toggle_texture"));
+    }
+
+    #[test]
+    fn opposing_keys_make_one_axis() {
+        let mut input = InputMap::new(InputConfig::defaults());
+        assert_eq!(input.axis("move_forward"), 0.0);
+        press(&mut input, VirtualKeyCode::W);
+        assert_eq!(input.axis("move_forward"), 1.0);
+        // Either binding of a side counts, but only once
+        press(&mut input, VirtualKeyCode::Up);
+        assert_eq!(input.axis("move_forward"), 1.0);
+        press(&mut input, VirtualKeyCode::S);
+        assert_eq!(input.axis("move_forward"), 0.0);
+        release(&mut input, VirtualKeyCode::W);
+        release(&mut input, VirtualKeyCode::Up);
+        assert_eq!(input.axis("move_forward"), -1.0);
+        assert_eq!(input.axis("no_such_axis"), 0.0);
+    }
+
+    #[test]
+    fn scrolling_counts_notches_for_the_frame() {
+        let mut input = InputMap::new(InputConfig::defaults());
+        #[allow(deprecated)]
+        let scroll = |lines| WindowEvent::MouseWheel {
+            // SAFETY: only compared by the input map, never passed to winit
+            device_id: unsafe { DeviceId::dummy() },
+            delta: MouseScrollDelta::LineDelta(0.0, lines),
+            phase: TouchPhase::Moved,
+            modifiers: ModifiersState::empty(),
+        };
+        input.process_event(&scroll(2.0));
+        input.process_event(&scroll(1.0));
+        assert_eq!(input.axis("zoom"), 3.0);
+        input.end_frame(0.016);
+        assert_eq!(input.axis("zoom"), 0.0);
+        input.process_event(&scroll(-1.0));
+        assert_eq!(input.axis("zoom"), -1.0);
+    }
+
+    #[test]
+    fn losing_focus_releases_everything() {
+        let mut input = InputMap::new(InputConfig::defaults());
+        press(&mut input, VirtualKeyCode::W);
+        input.end_frame(0.016);
+        input.process_event(&WindowEvent::Focused(false));
+        assert!(!input.held("move_forward"));
+        assert_eq!(input.axis("move_forward"), 0.0);
+    }
+
+    #[test]
+    fn rebinding_takes_the_next_input() {
+        let mut input = InputMap::new(InputConfig::defaults());
+        input.rebind_next("quit");
+        assert!(press(&mut input, VirtualKeyCode::Q));
+        // Swallowed, even once the new binding is in place
+        assert!(!input.pressed("quit"));
+        assert_eq!(input.config().actions["quit"], vec![Binding::Key(VirtualKeyCode::Q)]);
+        release(&mut input, VirtualKeyCode::Q);
+        input.end_frame(0.016);
+        press(&mut input, VirtualKeyCode::Q);
+        assert!(input.pressed("quit"));
+    }
+
+    #[test]
+    fn loaded_configs_keep_the_defaults_they_dont_mention() {
+        let file = TempFile::new(
+            "partial.ron",
+            r#"(
+                actions: {"quit": [Key(Q)]},
+                gamepad: (stick_deadzone: 0.3, trigger_deadzone: 0.1, curve: Linear, press_threshold: 0.6),
+            )"#,
+        );
+        let config = InputConfig::load(&file.0).unwrap();
+        let defaults = InputConfig::defaults();
+        assert_eq!(config.actions["quit"], vec![Binding::Key(VirtualKeyCode::Q)]);
+        assert_eq!(config.actions["undo"], defaults.actions["undo"]);
+        assert_eq!(config.actions.len(), defaults.actions.len());
+        assert_eq!(config.axes, defaults.axes);
+        assert_eq!(config.gamepad.stick_deadzone, 0.3);
+
+        let file = TempFile::new("partial.JSON", r#"{"axes": {"zoom": {"positive": [{"Key": "Equals"}]}}}"#);
+        let config = InputConfig::load(&file.0).unwrap();
+        assert_eq!(config.axes["zoom"].positive, vec![Binding::Key(VirtualKeyCode::Equals)]);
+        assert!(config.axes["zoom"].negative.is_empty());
+        assert_eq!(config.actions, defaults.actions);
+        assert_eq!(config.gamepad, GamepadSettings::default());
+
+        let file = TempFile::new("broken.ron", "(actions: {\"quit\": [Key(NotAKey)]})");
+        let error = format!("{:#}", InputConfig::load(&file.0).unwrap_err());
+        assert!(error.contains("Invalid input config"), "{}", error);
+    }
+
+    #[test]
+    fn configs_round_trip() {
+        for name in ["saved.ron", "saved.json"] {
+            let file = TempFile::new(name, "");
+            let mut config = InputConfig::defaults();
+            config.actions.insert("jump".to_string(), vec![Binding::Tap, Binding::Scroll(ScrollDirection::Up)]);
+            config.save(&file.0).unwrap();
+            assert_eq!(InputConfig::load(&file.0).unwrap(), config);
+        }
+    }
+}
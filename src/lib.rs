@@ -1,4 +1,26 @@
-mod texture;
+pub mod assets;
+pub mod camera;
+pub mod camera_path;
+pub mod debug_view;
+pub mod gamepad;
+pub mod gizmo;
+pub mod history;
+pub mod input;
+pub mod outline;
+pub mod picking;
+pub mod pipeline;
+pub mod preprocessor;
+pub mod raycast;
+pub mod reflection;
+pub mod replay;
+pub mod scene;
+pub mod texture;
+pub mod touch;
+pub mod skinning;
+pub mod time;
+pub mod viewport;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod watcher;
 
 
 use winit::{
@@ -9,6 +31,7 @@ use winit::{
 
 use wgpu::util::DeviceExt;
 use cgmath::prelude::*;
+use camera::Camera;
 
 #[cfg(target_arch="wasm32")]
 use wasm_bindgen::prelude::*;
@@ -43,24 +66,99 @@ pub async fn run() {
           })
           .expect("Couldn't append canvas to document body.");
   }
-  let mut state = State::new(window).await;
+  let mut state = match State::new(window).await {
+      Ok(state) => state,
+      Err(e) => {
+          log::error!("{:?}", e);
+          return;
+      }
+  };
+  let mut fixed_timestep = 1.0 / 60.0;
+  let mut recorder: Option<(replay::InputRecorder, std::path::PathBuf)> = None;
+  let mut player: Option<replay::InputPlayer> = None;
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+      let mut args = std::env::args().skip(1);
+      let mut characters = 0;
+      while let Some(arg) = args.next() {
+          if arg == "--camera-path" {
+              // Recorded keyframes are saved back to the same file
+              match args.next() {
+                  Some(path) => state.set_camera_path_file(path.into()),
+                  None => log::error!("--camera-path needs a file name"),
+              }
+              continue;
+          }
+          if arg == "--scene" {
+              // Saving writes back to the same file
+              match args.next() {
+                  Some(path) => state.set_scene_file(path.into()),
+                  None => log::error!("--scene needs a file name"),
+              }
+              continue;
+          }
+          if arg == "--watch-assets" {
+              state.watch_assets();
+              continue;
+          }
+          if arg == "--input-config" {
+              match args.next().map(input::InputConfig::load) {
+                  Some(Ok(config)) => state.set_input_config(config),
+                  Some(Err(e)) => log::error!("{:?}", e),
+                  None => log::error!("--input-config needs a file name"),
+              }
+              continue;
+          }
+          if arg == "--record-input" {
+              // Saved when the app exits
+              match args.next() {
+                  Some(path) => recorder = Some((replay::InputRecorder::new(fixed_timestep, state.size), path.into())),
+                  None => log::error!("--record-input needs a file name"),
+              }
+              continue;
+          }
+          if arg == "--replay-input" {
+              match args.next().map(replay::InputRecording::load) {
+                  Some(Ok(recording)) => {
+                      fixed_timestep = recording.fixed_timestep;
+                      let [width, height] = recording.size;
+                      if state.size != winit::dpi::PhysicalSize::new(width, height) {
+                          // Frames only match at the recorded size
+                          state.window().set_inner_size(winit::dpi::PhysicalSize::new(width, height));
+                      }
+                      player = Some(replay::InputPlayer::new(recording));
+                  }
+                  Some(Err(e)) => log::error!("{:?}", e),
+                  None => log::error!("--replay-input needs a file name"),
+              }
+              continue;
+          }
+          // Any other files passed on the command line are loaded as animated characters.
+          match skinning::SkinnedModel::load_gltf(&arg) {
+              Ok(model) => {
+                  state.add_skinned_model(model, cgmath::Vector3::new(characters as f32 * 2.0, 0.0, 0.0));
+                  characters += 1;
+              }
+              Err(e) => log::error!("Failed to load {}: {:?}", arg, e),
+          }
+      }
+  }
+  let mut time = time::Time::new(fixed_timestep);
   event_loop.run(move |event, _, control_flow| {
       match event {
           Event::WindowEvent {
               ref event,
               window_id,
-          } if window_id == state.window().id() => if !state.input(event) {
+          } if window_id == state.window().id() => {
+              if let Some((recorder, _)) = recorder.as_mut() {
+                  recorder.record_window_event(event);
+              }
+              // Live input would change the outcome of a replay
+              if player.is_none() && state.input(event) {
+                  return;
+              }
               match event {
-                  WindowEvent::CloseRequested
-                  | WindowEvent::KeyboardInput {
-                      input:
-                          KeyboardInput {
-                              state: ElementState::Pressed,
-                              virtual_keycode: Some(VirtualKeyCode::Escape),
-                              ..
-                          },
-                      ..
-                  } => *control_flow = ControlFlow::Exit,
+                  WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                   WindowEvent::Resized(physical_size) => {
                       state.resize(*physical_size);
                   }
@@ -72,8 +170,42 @@ pub async fn run() {
                   _ => {} // catch-all for other events
               }
           }
+          Event::DeviceEvent { ref event, .. } => {
+              if let Some((recorder, _)) = recorder.as_mut() {
+                  recorder.record_device_event(event);
+              }
+              if player.is_none() {
+                  state.device_input(event);
+              }
+          }
           Event::RedrawRequested(window_id) if window_id == state.window().id() => {
-            state.update();
+            match player.as_mut().and_then(|player| player.next_frame()) {
+                Some(frame) => {
+                    for recorded in frame.events.iter() {
+                        match recorded.event.to_event() {
+                            replay::ReplayEvent::Window(event) => { state.input(&event); }
+                            replay::ReplayEvent::Device(event) => state.device_input(&event),
+                        }
+                    }
+                    // Live gamepads would change the outcome too
+                    state.set_gamepads(frame.gamepads.clone());
+                    time.advance(frame.delta);
+                }
+                None => {
+                    if player.take().is_some() {
+                        log::info!("Input replay finished, back to live input");
+                    }
+                    state.poll_gamepads();
+                    time.tick();
+                }
+            }
+            if let Some((recorder, _)) = recorder.as_mut() {
+                recorder.end_frame(time.delta(), state.gamepads());
+            }
+            state.update(&mut time);
+            if state.quit_requested() {
+                *control_flow = ControlFlow::Exit;
+            }
             match state.render() {
                 Ok(_) => {}
                 // Reconfigure the surface if lost
@@ -84,6 +216,14 @@ pub async fn run() {
                 Err(e) => eprintln!("{:?}", e),
             }
           }
+          Event::LoopDestroyed => {
+              if let Some((recorder, path)) = recorder.as_ref() {
+                  match recorder.recording().save(path) {
+                      Ok(()) => log::info!("Saved input recording to {}", path.display()),
+                      Err(e) => log::error!("{:?}", e),
+                  }
+              }
+          }
           Event::MainEventsCleared => {
               // RedrawRequested will only trigger once unless we manually
               // request it.
@@ -94,113 +234,39 @@ pub async fn run() {
   });
 }
 use winit::window::Window;
+use std::sync::Arc;
 
-struct CameraController {
-  speed: f32,
-  is_forward_pressed: bool,
-  is_backward_pressed: bool,
-  is_left_pressed: bool,
-  is_right_pressed: bool,
-}
-
-impl CameraController {
-  fn new(speed: f32) -> Self {
-      Self {
-          speed,
-          is_forward_pressed: false,
-          is_backward_pressed: false,
-          is_left_pressed: false,
-          is_right_pressed: false,
-      }
-  }
+/// Identifies one of the instances drawn by `State`, by index.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InstanceHandle(pub usize);
 
-  fn process_events(&mut self, event: &WindowEvent) -> bool {
-      match event {
-          WindowEvent::KeyboardInput {
-              input: KeyboardInput {
-                  state,
-                  virtual_keycode: Some(keycode),
-                  ..
-              },
-              ..
-          } => {
-              let is_pressed = *state == ElementState::Pressed;
-              match keycode {
-                  VirtualKeyCode::W | VirtualKeyCode::Up => {
-                      self.is_forward_pressed = is_pressed;
-                      true
-                  }
-                  VirtualKeyCode::A | VirtualKeyCode::Left => {
-                      self.is_left_pressed = is_pressed;
-                      true
-                  }
-                  VirtualKeyCode::S | VirtualKeyCode::Down => {
-                      self.is_backward_pressed = is_pressed;
-                      true
-                  }
-                  VirtualKeyCode::D | VirtualKeyCode::Right => {
-                      self.is_right_pressed = is_pressed;
-                      true
-                  }
-                  _ => false,
-              }
-          }
-          _ => false,
-      }
-  }
-
-  fn update_camera(&self, camera: &mut Camera) {
-    use cgmath::InnerSpace;
+#[derive(Clone)]
+pub struct Instance {
+  pub position: cgmath::Vector3<f32>,
+  pub rotation: cgmath::Quaternion<f32>,
+  /// Scale along the instance's own axes, applied before rotating.
+  pub scale: cgmath::Vector3<f32>,
+}
 
-    // Define a minimum distance from the target
-    let min_distance = 1.0; // Example value, adjust as needed
-    
-    let forward = camera.target - camera.eye;
-    let forward_norm = forward.normalize();
-    let forward_mag = forward.magnitude();
-    
-    // Prevents glitching when the camera gets too close to the
-    // center of the scene.
-    if self.is_forward_pressed {
-        // Check if moving forward breaches the minimum distance
-        if forward_mag - self.speed > min_distance {
-            camera.eye += forward_norm * self.speed;
-        } else {
-            // Clamp to the minimum distance if necessary
-            camera.eye = camera.target - forward_norm * min_distance;
-        }
-    }
-    if self.is_backward_pressed {
-        camera.eye -= forward_norm * self.speed;
-    }
-    
-    let right = forward_norm.cross(camera.up);
-    
-    // Redo radius calc in case the forward/backward is pressed.
-    let forward = camera.target - camera.eye;
-    let forward_mag = forward.magnitude();
-
-      if self.is_right_pressed {
-          // Rescale the distance between the target and the eye so 
-          // that it doesn't change. The eye, therefore, still 
-          // lies on the circle made by the target and eye.
-          camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
-      }
-      if self.is_left_pressed {
-          camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+impl Instance {
+  /// Blends between two simulation states, `t` being `Time::alpha`.
+  fn interpolate(&self, next: &Instance, t: f32) -> Instance {
+      Instance {
+          position: self.position.lerp(next.position, t),
+          rotation: self.rotation.nlerp(next.rotation, t),
+          scale: self.scale.lerp(next.scale, t),
       }
   }
-}
 
-struct Instance {
-  position: cgmath::Vector3<f32>,
-  rotation: cgmath::Quaternion<f32>,
-}
+  pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+      cgmath::Matrix4::from_translation(self.position)
+          * cgmath::Matrix4::from(self.rotation)
+          * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+  }
 
-impl Instance {
   fn to_raw(&self) -> InstanceRaw {
       InstanceRaw {
-          model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into(),
+          model: self.model_matrix().into(),
       }
   }
 }
@@ -250,58 +316,183 @@ impl InstanceRaw {
   }
 }
 
-// We need this for Rust to store our data correctly for the shaders
-#[repr(C)]
-// This is so we can store this in a buffer
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct CameraUniform {
-    // We can't use cgmath with bytemuck directly, so we'll have
-    // to convert the Matrix4 into a 4x4 f32 array
-    view_proj: [[f32; 4]; 4],
+/// Something built once per depth convention, so cameras using reverse-Z
+/// and regular projections can be drawn with the same renderer.
+struct DepthVariants<T> {
+    standard: T,
+    reverse_z: T,
 }
 
-impl CameraUniform {
-    fn new() -> Self {
-        use cgmath::SquareMatrix;
+impl<T> DepthVariants<T> {
+    fn try_new(mut create: impl FnMut(wgpu::CompareFunction) -> anyhow::Result<T>) -> anyhow::Result<Self> {
+        Ok(Self {
+            standard: create(wgpu::CompareFunction::Less)?,
+            reverse_z: create(wgpu::CompareFunction::Greater)?,
+        })
+    }
+
+    fn get(&self, reverse_z: bool) -> &T {
+        if reverse_z {
+            &self.reverse_z
+        } else {
+            &self.standard
+        }
+    }
+}
+
+/// An indexed mesh drawn for a range of instances. Whoever draws it binds
+/// the instance buffer the range refers to.
+#[derive(Clone)]
+pub struct MeshDraw<'a> {
+    pub vertex_buffer: &'a wgpu::Buffer,
+    pub index_buffer: &'a wgpu::Buffer,
+    pub num_indices: u32,
+    pub instances: std::ops::Range<u32>,
+    /// The vertices of every triangle in order, only made once wireframes
+    /// are drawn by the shader.
+    pub corner_buffer: Option<&'a wgpu::Buffer>,
+    /// Whether back faces are drawn too, as the material asks.
+    pub double_sided: bool,
+}
+
+impl<'a> MeshDraw<'a> {
+    /// Draws with the pipeline, bind groups and instance buffer already set.
+    pub fn draw<'pass>(&self, render_pass: &mut wgpu::RenderPass<'pass>) where 'a: 'pass {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..self.num_indices, 0, self.instances.clone());
+    }
+
+    /// Draws every triangle from vertices of its own, so `vertex_index % 3`
+    /// tells its corners apart, or like `draw` when there's no corner buffer.
+    pub fn draw_corners<'pass>(&self, render_pass: &mut wgpu::RenderPass<'pass>) where 'a: 'pass {
+        match self.corner_buffer {
+            Some(corner_buffer) => {
+                render_pass.set_vertex_buffer(0, corner_buffer.slice(..));
+                render_pass.draw(0..self.num_indices, self.instances.clone());
+            }
+            None => self.draw(render_pass),
+        }
+    }
+}
+
+struct Mesh {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    // Without line polygon mode, wireframes are drawn by the shader from a
+    // copy of the mesh with no vertices shared between triangles. It's made
+    // from the kept vertices the first time wireframes are shown.
+    corners: Option<Corners>,
+    corner_buffer: Option<wgpu::Buffer>,
+}
+
+struct Corners {
+    label: String,
+    vertices: Vec<Vertex>,
+    indices: Vec<u16>,
+}
+
+impl Mesh {
+    fn new(device: &wgpu::Device, label: &str, vertices: &[Vertex], indices: &[u16]) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", label)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", label)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let corners = (!device.features().contains(wgpu::Features::POLYGON_MODE_LINE)).then(|| Corners {
+            label: label.to_string(),
+            vertices: vertices.to_vec(),
+            indices: indices.to_vec(),
+        });
         Self {
-            view_proj: cgmath::Matrix4::identity().into(),
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as u32,
+            corners,
+            corner_buffer: None,
         }
     }
 
-    fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into();
-    }
-}
- 
-struct Camera {
-    eye: cgmath::Point3<f32>,
-    target: cgmath::Point3<f32>,
-    up: cgmath::Vector3<f32>,
-    aspect: f32,
-    fovy: f32,
-    znear: f32,
-    zfar: f32,
-}
-#[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
-    1.0, 0.0, 0.0, 0.0,
-    0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
-);
- 
-impl Camera {
-    fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        // 1.
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.
-            target, self.up);
-        // 2.
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
-
-        // 3.
-        return OPENGL_TO_WGPU_MATRIX * proj * view;
+    /// Makes the buffer `MeshDraw::draw_corners` draws from, unless it's
+    /// been made or line polygon mode makes it unnecessary.
+    fn make_corner_buffer(&mut self, device: &wgpu::Device) {
+        let Some(Corners { label, vertices, indices }) = self.corners.take() else {
+            return;
+        };
+        let corners = indices.iter().map(|&index| vertices[index as usize]).collect::<Vec<_>>();
+        self.corner_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Corner Buffer", label)),
+            contents: bytemuck::cast_slice(&corners),
+            usage: wgpu::BufferUsages::VERTEX,
+        }));
     }
+
+    fn draw(&self, instances: std::ops::Range<u32>) -> MeshDraw<'_> {
+        MeshDraw {
+            vertex_buffer: &self.vertex_buffer,
+            index_buffer: &self.index_buffer,
+            num_indices: self.num_indices,
+            instances,
+            corner_buffer: self.corner_buffer.as_ref(),
+            double_sided: false,
+        }
+    }
+}
+
+/// A mesh as the scene names it.
+struct SceneMesh {
+    name: String,
+    mesh: assets::Handle<Mesh>,
+}
+
+struct Material {
+    name: String,
+    texture: assets::Handle<texture::Texture>,
+    // Made again once the texture finishes loading
+    bind_group: wgpu::BindGroup,
+    blend: scene::BlendMode,
+    double_sided: bool,
+    // The variant of the scene pipeline for the blend mode and culling,
+    // shared with every material drawn the same way
+    pipeline: DepthVariants<Arc<wgpu::RenderPipeline>>,
+}
+
+// What skinned meshes are drawn with, when the adapter can draw them.
+struct Skinning {
+    // The skinned variant of the main shader
+    shader: assets::Handle<assets::Shader>,
+    // Group 2 of the skinned shader, as its reflection declares it
+    joint_bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: pipeline::PipelineLayout,
+}
+
+/// The variants of the main and skinned shaders drawing a debug view.
+struct DebugShaders {
+    view: debug_view::DebugView,
+    scene: assets::Handle<assets::Shader>,
+    // None without skinning
+    skinned: Option<assets::Handle<assets::Shader>>,
+}
+
+/// What the current debug view draws everything with.
+struct DebugPipelines {
+    scene: DepthVariants<Arc<wgpu::RenderPipeline>>,
+    skinned: Option<DepthVariants<Arc<wgpu::RenderPipeline>>>,
+}
+
+/// Instances sharing a mesh and material, drawn with one call.
+struct Batch {
+    mesh: usize,
+    material: usize,
+    instances: std::ops::Range<u32>,
 }
+
 struct State {
     surface: wgpu::Surface,
     device: wgpu::Device,
@@ -313,32 +504,80 @@ struct State {
     // unsafe references to the window's resources.
     window: Window,
     clear_color: wgpu::Color,
-    render_pipeline: wgpu::RenderPipeline,
-    // color_pipeline: wgpu::RenderPipeline,
-    pipeline_toggle: bool,
+    // Opaque and single-sided, for what isn't drawn with a material
+    render_pipeline: DepthVariants<Arc<wgpu::RenderPipeline>>,
+    // Kept to remake the pipelines when the shader is reloaded
+    render_pipeline_layout: pipeline::PipelineLayout,
+    shader: assets::Handle<assets::Shader>,
+    pipelines: pipeline::PipelineCache,
     // vertex_buffer: wgpu::Buffer,
     // num_vertices: u32,
     // index_buffer: wgpu::Buffer, 
     // num_indices: u32,
-    hexagon_vertex_buffer: wgpu::Buffer,
-    hexagon_index_buffer: wgpu::Buffer,
-    hexagon_num_indices: u32,
-    square_vertex_buffer: wgpu::Buffer,
-    square_index_buffer: wgpu::Buffer,
-    square_num_indices: u32,
+    // Drawn in place of every scene mesh while the toggle is on
+    hexagon: Mesh,
     spacebar_toggle: bool,
     diffuse_bind_group: wgpu::BindGroup,
     noise_bind_group: wgpu::BindGroup,
-    diffuse_texture: texture::Texture,
-    noise_texture: texture::Texture,
-    camera: Camera,
-    camera_uniform: CameraUniform,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
-    camera_controller: CameraController,
+    diffuse_texture: assets::Handle<texture::Texture>,
+    noise_texture: assets::Handle<texture::Texture>,
+    assets: assets::Assets,
+    // What the title says about loading, updated as loads finish
+    title: String,
+    shown_progress: assets::LoadProgress,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    // The scene as loaded, kept for what `State` doesn't use itself, like
+    // lights, when saving it back.
+    scene: scene::SceneDescription,
+    scene_file: std::path::PathBuf,
+    meshes: Vec<SceneMesh>,
+    materials: Vec<Material>,
+    // Instances are sorted by mesh and material when a scene loads, so
+    // every batch is one range of the instance buffer.
+    batches: Vec<Batch>,
+    // views[0] is the main view, driven by the camera controller.
+    views: Vec<viewport::View>,
+    view_layout: viewport::ViewLayout,
+    view_clearer: viewport::ViewClearer,
+    camera_controller: camera::CameraController,
+    input: input::InputMap,
+    gamepads: gamepad::Gamepads,
+    quit_requested: bool,
+    camera_path: camera_path::PathPlayer,
+    camera_path_file: std::path::PathBuf,
+    // Where the camera was when the playing path started, for undo
+    camera_path_start: Option<camera::CameraPose>,
+    // Where the camera was when the user started moving it, for undo
+    camera_move_start: Option<camera::CameraPose>,
+    cursor_grabbed: bool,
+    globals_uniform: time::GlobalsUniform,
+    globals_buffer: wgpu::Buffer,
     instances: Vec<Instance>,
+    // Instance state as of the previous fixed step, for interpolation.
+    previous_instances: Vec<Instance>,
     instance_buffer: wgpu::Buffer,
     depth_texture: texture::Texture,
+    picker: picking::Picker,
+    // Whether the pending pick adds to the selection instead of replacing it
+    pick_extends_selection: bool,
+    selection: Vec<InstanceHandle>,
+    outline: outline::Outline,
+    gizmo: gizmo::Gizmo,
+    history: history::History,
+    // Only available when the adapter allows storage buffers in vertex
+    // shaders, which rules out WebGL2.
+    skinned_pipeline: Option<DepthVariants<Arc<wgpu::RenderPipeline>>>,
+    skinning: Option<Skinning>,
+    skinned_instances: Vec<skinning::SkinnedInstance>,
+    debug_view: debug_view::DebugView,
+    // Every debug view's shaders, compiled up front as the web can't wait
+    // for a shader to compile when a key is pressed
+    debug_shaders: Vec<DebugShaders>,
+    debug_pipelines: Option<DebugPipelines>,
+    // Whether wireframes are drawn with wgpu::PolygonMode::Line rather than
+    // by the shader
+    line_polygon_mode: bool,
 }
 
 impl State {
@@ -350,7 +589,9 @@ impl State {
     }
 }
 // Creating some of the wgpu types requires async code
-  async fn new(window: Window) -> Self {
+  async fn new(window: Window) -> anyhow::Result<Self> {
+    use anyhow::Context;
+
     let size = window.inner_size();
 
     // The instance is a handle to our GPU
@@ -364,7 +605,7 @@ impl State {
     //
     // The surface needs to live as long as the window that created it.
     // State owns the window, so this should be safe.
-    let surface = unsafe { instance.create_surface(&window) }.unwrap();
+    let surface = unsafe { instance.create_surface(&window) }.context("Couldn't create a surface for the window")?;
 
     let adapter = instance.request_adapter(
         &wgpu::RequestAdapterOptions {
@@ -372,11 +613,13 @@ impl State {
             compatible_surface: Some(&surface),
             force_fallback_adapter: false,
         },
-    ).await.unwrap();
+    ).await.context("No graphics adapter can draw to the window")?;
 
     let (device, queue) = adapter.request_device(
     &wgpu::DeviceDescriptor {
-        features: wgpu::Features::empty(),
+        // Line polygon mode draws wireframes, which the shader can make do
+        // without
+        features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
         // WebGL doesn't support all of wgpu's features, so if
         // we're building for the web, we'll have to disable some.
         limits: if cfg!(target_arch = "wasm32") {
@@ -387,7 +630,7 @@ impl State {
         label: None,
     },
     None, // Trace path
-    ).await.unwrap();
+    ).await.context("Couldn't open the graphics device")?;
     
     let surface_caps = surface.get_capabilities(&adapter);
     // Shader code in this tutorial assumes an sRGB surface texture. Using a different
@@ -395,8 +638,7 @@ impl State {
     // sRGB surfaces, you'll need to account for that when drawing to the frame.
     let surface_format = surface_caps.formats.iter()
         .copied()
-        .filter(|f| f.is_srgb())
-        .next()
+        .find(|f| f.is_srgb())
         .unwrap_or(surface_caps.formats[0]);
     let config = wgpu::SurfaceConfiguration {
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -409,202 +651,92 @@ impl State {
     };
     surface.configure(&device, &config);
 
-    let diffuse_bytes = include_bytes!("happy-tree.png");
-    let diffuse_texture = texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "happy-tree.png").unwrap(); 
-
-    let noise_bytes = include_bytes!("layered-simplex-noise.png");
-    let noise_texture = texture::Texture::from_bytes(&device, &queue, noise_bytes, "layered-simplex-noise.png").unwrap(); 
+    // Textures load in the background, drawing as a placeholder until then
+    let mut assets = assets::Assets::new(&device, &queue);
+    let diffuse_texture = assets.load_texture(assets::asset_path("happy-tree.png"));
+    let noise_texture = assets.load_texture(assets::asset_path("layered-simplex-noise.png"));
 
-    const NUM_INSTANCES_PER_ROW: u32 = 10;
-    const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(NUM_INSTANCES_PER_ROW as f32 * 0.5, 0.0, NUM_INSTANCES_PER_ROW as f32 * 0.5);
-
-    let instances = (0..NUM_INSTANCES_PER_ROW).flat_map(|z| {
-      (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-          let position = cgmath::Vector3 { x: x as f32, y: 0.0, z: z as f32 } - INSTANCE_DISPLACEMENT;
-
-          let rotation = if position.is_zero() {
-              // this is needed so an object at (0, 0, 0) won't get scaled to zero
-              // as Quaternions can affect scale if they're not created correctly
-              cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0))
-          } else {
-              cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-          };
-
-          Instance {
-              position, rotation,
-          }
-      })
-    }).collect::<Vec<_>>();
-
-    let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-    let instance_buffer = device.create_buffer_init(
-      &wgpu::util::BufferInitDescriptor {
-          label: Some("Instance Buffer"),
-          contents: bytemuck::cast_slice(&instance_data),
-          usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST, // Include COPY_DST here
-      }
-    );
+    // Filled in by the scene, loaded once everything else exists
+    let instance_buffer = create_instance_buffer(&device, &[]);
    
-    let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-          entries: &[
-              wgpu::BindGroupLayoutEntry {
-                  binding: 0,
-                  visibility: wgpu::ShaderStages::FRAGMENT,
-                  ty: wgpu::BindingType::Texture {
-                      multisampled: false,
-                      view_dimension: wgpu::TextureViewDimension::D2,
-                      sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                  },
-                  count: None,
-              },
-              wgpu::BindGroupLayoutEntry {
-                  binding: 1,
-                  visibility: wgpu::ShaderStages::FRAGMENT,
-                  // This should match the filterable field of the
-                  // corresponding Texture entry above.
-                  ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                  count: None,
-              },
-          ],
-          label: Some("texture_bind_group_layout"),
-      });
-      let diffuse_bind_group = device.create_bind_group(
-        &wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                }
-            ],
-            label: Some("diffuse_bind_group"),
-        }
-  );
-       
-  let noise_bind_group = device.create_bind_group(
-    &wgpu::BindGroupDescriptor {
-        layout: &texture_bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&noise_texture.view),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::Sampler(&noise_texture.sampler),
-            }
-        ],
-        label: Some("noise_bind_group"),
-    }
-);
-let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-  entries: &[
-      wgpu::BindGroupLayoutEntry {
-          binding: 0,
-          visibility: wgpu::ShaderStages::VERTEX,
-          ty: wgpu::BindingType::Buffer {
-              ty: wgpu::BufferBindingType::Uniform,
-              has_dynamic_offset: false,
-              min_binding_size: None,
-          },
-          count: None,
-      }
-  ],
-  label: Some("camera_bind_group_layout"),
-});
+    // The bind group layouts come from what the main shader declares:
+    // textures in group 0 and the camera and frame globals in group 1.
+    let shader = assets.load_shader(&device, assets::asset_path("shader.wgsl"), &preprocessor::ShaderDefs::new()).await?;
+    let reflection = &assets.shader(&shader).reflection;
+    let (texture_bind_group_layout, texture_entries) =
+        reflection.create_bind_group_layout(&device, 0, "texture_bind_group_layout")?;
+    let (camera_bind_group_layout, camera_entries) =
+        reflection.create_bind_group_layout(&device, 1, "camera_bind_group_layout")?;
+      let diffuse_bind_group = create_texture_bind_group(
+        &device,
+        &texture_bind_group_layout,
+        assets.texture(&diffuse_texture),
+        "diffuse_bind_group",
+      );
+      let noise_bind_group = create_texture_bind_group(
+        &device,
+        &texture_bind_group_layout,
+        assets.texture(&noise_texture),
+        "noise_bind_group",
+      );
       let depth_texture = texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-      let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
-      let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-          label: Some("Render Pipeline Layout"),
-          bind_group_layouts: &[
-            &texture_bind_group_layout,
-            &camera_bind_group_layout,
-        ],
-          push_constant_ranges: &[],
-      });
-      let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-      label: Some("Render Pipeline"),
-      layout: Some(&render_pipeline_layout),
-      vertex: wgpu::VertexState {
-          module: &shader,
-          entry_point: "vs_main", 
-          buffers: &[Vertex::desc(), InstanceRaw::desc()],
-      },
-      fragment: Some(wgpu::FragmentState {
-          module: &shader,
-          entry_point: "fs_main",
-          targets: &[Some(wgpu::ColorTargetState {
-              format: config.format,
-              blend: Some(wgpu::BlendState::REPLACE),
-              write_mask: wgpu::ColorWrites::ALL,
-          })],
-      }),
-      primitive: wgpu::PrimitiveState {
-          topology: wgpu::PrimitiveTopology::TriangleList,
-          strip_index_format: None,
-          front_face: wgpu::FrontFace::Ccw,
-          cull_mode: Some(wgpu::Face::Back),
-          // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-          polygon_mode: wgpu::PolygonMode::Fill,
-          // Requires Features::DEPTH_CLIP_CONTROL
-          unclipped_depth: false,
-          // Requires Features::CONSERVATIVE_RASTERIZATION
-          conservative: false,
-      },
-      depth_stencil: Some(wgpu::DepthStencilState {
-        format: texture::Texture::DEPTH_FORMAT,
-        depth_write_enabled: true,
-        depth_compare: wgpu::CompareFunction::Less,
-        stencil: wgpu::StencilState::default(),
-        bias: wgpu::DepthBiasState::default(),
-    }),
-      multisample: wgpu::MultisampleState {
-          count: 1, 
-          mask: !0,
-          alpha_to_coverage_enabled: false,
-      },
-      multiview: None,
-  });
-  // let color_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-  //     label: Some("Color Pipeline"),
-  //     layout: Some(&render_pipeline_layout), // You can reuse the layout
-  //     vertex: wgpu::VertexState {
-  //         module: &shader, // Reuse the shader module
-  //         entry_point: "vs_main", // Entry point for the vertex shader
-  //         buffers: &[
-  //         Vertex::desc(),
-  //         ],
-  //     },
-  //     fragment: Some(wgpu::FragmentState { // Fragment shader
-  //         module: &shader, // Reuse the shader module
-  //         entry_point: "fs_main", // Entry point for the fragment shader
-  //         targets: &[Some(wgpu::ColorTargetState {
-  //             format: config.format,
-  //             blend: Some(wgpu::BlendState::REPLACE),
-  //             write_mask: wgpu::ColorWrites::ALL,
-  //         })],
-  //     }),
-  //     primitive: wgpu::PrimitiveState {
-  //         topology: wgpu::PrimitiveTopology::TriangleList,
-  //         strip_index_format: None,
-  //         front_face: wgpu::FrontFace::Ccw,
-  //         cull_mode: Some(wgpu::Face::Back),
-  //         polygon_mode: wgpu::PolygonMode::Fill,
-  //         unclipped_depth: false,
-  //         conservative: false,
-  //     },
-  //     depth_stencil: None,
-  //     multisample: wgpu::MultisampleState {
-  //         count: 1,
-  //         mask: !0,
-  //         alpha_to_coverage_enabled: false,
-  //     },
-  //     multiview: None,
-  // });
+      let picker = picking::Picker::new(&device, &config, &camera_bind_group_layout, &camera_entries)?;
+      let outline = outline::Outline::new(&device, &config, &camera_bind_group_layout, &camera_entries)?;
+      let gizmo = gizmo::Gizmo::new(&device, &config, &camera_bind_group_layout, &camera_entries)?;
+      let render_pipeline_layout = pipeline::PipelineLayout::new(&device, "Render Pipeline Layout", &[
+          (&texture_bind_group_layout, &texture_entries),
+          (&camera_bind_group_layout, &camera_entries),
+      ]);
+  // Pipelines are made through the cache, so the variants materials ask for
+  // later reuse these when they're drawn the same way
+  let mut pipelines = pipeline::PipelineCache::new();
+  let render_pipeline = scene_pipelines(
+      &device,
+      &mut pipelines,
+      &scene_pipeline_builder(&render_pipeline_layout, assets.shader(&shader), config.format),
+      scene::BlendMode::Opaque,
+      false,
+  )?;
+  let skinning = if device.limits().max_storage_buffers_per_shader_stage > 0 {
+      let defs = preprocessor::ShaderDefs::new().define("SKINNED");
+      let shader = assets.load_shader(&device, assets::asset_path("shader.wgsl"), &defs).await?;
+      // The joint matrices are in group 2, after the groups shared with the
+      // main shader
+      let (joint_bind_group_layout, joint_entries) =
+          assets.shader(&shader).reflection.create_bind_group_layout(&device, 2, "joint_bind_group_layout")?;
+      let pipeline_layout = pipeline::PipelineLayout::new(&device, "Skinned Pipeline Layout", &[
+          (&texture_bind_group_layout, &texture_entries),
+          (&camera_bind_group_layout, &camera_entries),
+          (&joint_bind_group_layout, &joint_entries),
+      ]);
+      Some(Skinning { shader, joint_bind_group_layout, pipeline_layout })
+  } else {
+      log::warn!("Storage buffers aren't available in vertex shaders, skinned meshes will not be drawn");
+      None
+  };
+  let skinned_pipeline = match &skinning {
+      Some(skinning) => Some(scene_pipelines(
+          &device,
+          &mut pipelines,
+          &skinned_pipeline_builder(&skinning.pipeline_layout, assets.shader(&skinning.shader), config.format),
+          scene::BlendMode::Opaque,
+          false,
+      )?),
+      None => None,
+  };
+  let line_polygon_mode = device.features().contains(wgpu::Features::POLYGON_MODE_LINE);
+  let mut debug_shaders = Vec::new();
+  for view in debug_view::DebugView::DEBUG {
+      let scene = assets.load_shader(&device, assets::asset_path("shader.wgsl"), &view.defs(preprocessor::ShaderDefs::new(), line_polygon_mode)).await?;
+      let skinned = match &skinning {
+          Some(_) => {
+              let defs = view.defs(preprocessor::ShaderDefs::new().define("SKINNED"), line_polygon_mode);
+              Some(assets.load_shader(&device, assets::asset_path("shader.wgsl"), &defs).await?)
+          }
+          None => None,
+      };
+      debug_shaders.push(DebugShaders { view, scene, skinned });
+  }
   // let vertex_buffer = device.create_buffer_init(
   //   &wgpu::util::BufferInitDescriptor {
   //       label: Some("Vertex Buffer"),
@@ -622,75 +754,34 @@ let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupL
   // let num_indices = INDICES.len() as u32;
   // let num_vertices = VERTICES.len() as u32;
 
-  let hexagon_vertex_buffer = device.create_buffer_init(
-    &wgpu::util::BufferInitDescriptor {
-        label: Some("Vertex Buffer"),
-        contents: bytemuck::cast_slice(HEXAGON_VERTICES),
-        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-    }
-    );
-  let hexagon_index_buffer = device.create_buffer_init(
-    &wgpu::util::BufferInitDescriptor {
-        label: Some("Index Buffer"),
-        contents: bytemuck::cast_slice(HEXAGON_INDICES),
-        usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
-    }
-    );
-  let hexagon_num_indices = HEXAGON_INDICES.len() as u32;
+  let hexagon = Mesh::new(&device, "hexagon", HEXAGON_VERTICES, HEXAGON_INDICES);
 
-  let square_vertex_buffer = device.create_buffer_init(
-    &wgpu::util::BufferInitDescriptor {
-        label: Some("Vertex Buffer"),
-        contents: bytemuck::cast_slice(SQUARE_VERTICES),
-        usage: wgpu::BufferUsages::VERTEX,
-    }
-    );
-  let square_index_buffer = device.create_buffer_init(
-    &wgpu::util::BufferInitDescriptor {
-        label: Some("Index Buffer"),
-        contents: bytemuck::cast_slice(SQUARE_INDICES),
-        usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
-    }
-    );
-  let square_num_indices = SQUARE_INDICES.len() as u32;
-
-  let camera = Camera {
-    // position the camera 1 unit up and 2 units back
-    // +z is out of the screen
-    eye: (0.0, 1.0, 2.0).into(),
-    // have it look at the origin
-    target: (0.0, 0.0, 0.0).into(),
-    // which way is "up"
-    up: cgmath::Vector3::unit_y(),
-    aspect: config.width as f32 / config.height as f32,
-    fovy: 45.0,
-    znear: 0.1,
-    zfar: 100.0,
-  };
-  let mut camera_uniform = CameraUniform::new();
-  camera_uniform.update_view_proj(&camera);
-  
-  let camera_buffer = device.create_buffer_init(
+  let scene = scene::SceneDescription::builtin();
+  let camera = scene.cameras[0].to_camera();
+  let globals_uniform = time::GlobalsUniform::new();
+  let globals_buffer = device.create_buffer_init(
       &wgpu::util::BufferInitDescriptor {
-          label: Some("Camera Buffer"),
-          contents: bytemuck::cast_slice(&[camera_uniform]),
+          label: Some("Globals Buffer"),
+          contents: bytemuck::cast_slice(&[globals_uniform]),
           usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
       }
   );
-  
-
-  let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-    layout: &camera_bind_group_layout,
-    entries: &[
-        wgpu::BindGroupEntry {
-            binding: 0,
-            resource: camera_buffer.as_entire_binding(),
-        }
-    ],
-    label: Some("camera_bind_group"),
-  });
-  let camera_controller = CameraController::new(0.2);
-  Self {
+
+  let view_clearer = viewport::ViewClearer::new(&device, config.format)?;
+  let main_view = viewport::View::new(
+    &device,
+    &camera_bind_group_layout,
+    &globals_buffer,
+    &view_clearer,
+    camera,
+    viewport::Viewport::FULL,
+    viewport::ClearMode::Keep,
+  );
+  let camera_controller = camera::CameraController::new(
+    camera::OrbitController::new(12.0),
+    camera::FlyController::new(5.0, 0.003),
+  );
+  let mut state = Self {
     window,
     surface,
     device,
@@ -699,101 +790,798 @@ let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupL
     size,
     clear_color: wgpu::Color { r: 0.1, g: 0.2, b: 0.3, a: 1.0 },
     render_pipeline,
-    // color_pipeline,
-    pipeline_toggle: false,
+    render_pipeline_layout,
+    shader,
+    pipelines,
 //   vertex_buffer,
 //   num_vertices,
 //   index_buffer,
 //   num_indices,
-    hexagon_vertex_buffer,
-    hexagon_index_buffer,
-    hexagon_num_indices,
-    square_vertex_buffer,
-    square_index_buffer,
-    square_num_indices,
+    hexagon,
     spacebar_toggle: false,
     diffuse_bind_group,
     noise_bind_group,
     diffuse_texture,
     noise_texture,
-    camera,
-    camera_uniform,
-    camera_buffer,
-    camera_bind_group,
+    assets,
+    title: "Loading".into(),
+    shown_progress: assets::LoadProgress::default(),
+    texture_bind_group_layout,
+    camera_bind_group_layout,
+    scene: scene.clone(),
+    scene_file: "scene.ron".into(),
+    meshes: Vec::new(),
+    materials: Vec::new(),
+    batches: Vec::new(),
+    views: vec![main_view],
+    view_layout: viewport::ViewLayout::Single,
+    view_clearer,
     camera_controller,
-    instances,
+    input: input::InputMap::new(input::InputConfig::defaults()),
+    gamepads: gamepad::Gamepads::with_default_backend(),
+    quit_requested: false,
+    camera_path: camera_path::PathPlayer::new(camera_path::CameraPath::new(
+      camera_path::Spline::CatmullRom,
+      camera_path::Easing::EaseInOut,
+    )),
+    camera_path_file: "camera_path.ron".into(),
+    camera_path_start: None,
+    camera_move_start: None,
+    cursor_grabbed: false,
+    globals_uniform,
+    globals_buffer,
+    previous_instances: Vec::new(),
+    instances: Vec::new(),
     instance_buffer,
     depth_texture,
-  }
+    picker,
+    pick_extends_selection: false,
+    selection: Vec::new(),
+    outline,
+    gizmo,
+    history: history::History::default(),
+    skinned_pipeline,
+    skinning,
+    skinned_instances: Vec::new(),
+    debug_view: debug_view::DebugView::Shaded,
+    debug_shaders,
+    debug_pipelines: None,
+    line_polygon_mode,
+  };
+  state.title = state.window.title();
+  state.load_scene(scene, std::path::Path::new("")).context("Couldn't load the built in scene")?;
+  Ok(state)
 }
 
+  /// The camera of the main view.
+  pub fn camera(&self) -> &Camera {
+      &self.views[0].camera
+  }
+
+  /// Adds a camera drawing into `viewport` after the existing views and
+  /// returns its index.
+  pub fn add_view(&mut self, camera: Camera, viewport: viewport::Viewport, clear: viewport::ClearMode) -> usize {
+      let mut view = viewport::View::new(
+          &self.device,
+          &self.camera_bind_group_layout,
+          &self.globals_buffer,
+          &self.view_clearer,
+          camera,
+          viewport,
+          clear,
+      );
+      view.resize(self.size);
+      self.views.push(view);
+      self.views.len() - 1
+  }
+
+  /// Replaces every view but the main one with the given preset.
+  pub fn set_view_layout(&mut self, layout: viewport::ViewLayout) {
+      let (main_viewport, others) = layout.arrange(self.camera());
+      self.views.truncate(1);
+      self.views[0].viewport = main_viewport;
+      self.views[0].resize(self.size);
+      for (camera, viewport, clear) in others {
+          self.add_view(camera, viewport, clear);
+      }
+      self.view_layout = layout;
+  }
+
+  /// Loads a camera path from `file` if it exists. Recording and saving
+  /// always use this file.
+  pub fn set_camera_path_file(&mut self, file: std::path::PathBuf) {
+      if file.exists() {
+          match camera_path::CameraPath::load(&file) {
+              Ok(path) => self.camera_path = camera_path::PathPlayer::new(path),
+              Err(e) => log::error!("{:?}", e),
+          }
+      }
+      self.camera_path_file = file;
+  }
+
+  /// Reloads textures, meshes and the main shader when their files
+  /// change, for editing them while the app runs.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn watch_assets(&mut self) {
+    self.assets.watch_files();
+  }
+
+  /// Loads the scene in `file` if it exists. Saving always writes to this
+  /// file.
+  pub fn set_scene_file(&mut self, file: std::path::PathBuf) {
+      if file.exists() {
+          let base_dir = file.parent().unwrap_or(std::path::Path::new("")).to_path_buf();
+          match scene::SceneDescription::load(&file).and_then(|scene| self.load_scene(scene, &base_dir)) {
+              Ok(()) => log::info!("Loaded scene {}", file.display()),
+              Err(e) => log::error!("{:?}", e),
+          }
+      }
+      self.scene_file = file;
+  }
+
+  /// Replaces everything in the scene with `scene`, whose files are relative
+  /// to `base_dir`. Meshes and textures load in the background, with
+  /// placeholders standing in until they're ready or if they fail.
+  pub fn load_scene(&mut self, scene: scene::SceneDescription, base_dir: &std::path::Path) -> anyhow::Result<()> {
+      use anyhow::Context;
+      use scene::ResourcePath;
+
+      scene.validate()?;
+      let mut meshes = Vec::new();
+      for mesh in scene.meshes.iter() {
+          let handle = match ResourcePath::resolve(&mesh.path, base_dir) {
+              ResourcePath::Builtin(name) => {
+                  let (vertices, indices) = builtin_mesh(name)
+                      .with_context(|| format!("There's no built in mesh called {}", name))?;
+                  self.assets.add_mesh(&mesh.path, || Mesh::new(&self.device, name, vertices, indices))
+              }
+              ResourcePath::File(path) => self.assets.load_mesh(path),
+          };
+          meshes.push(SceneMesh { name: mesh.name.clone(), mesh: handle });
+      }
+      let mut textures = std::collections::HashMap::new();
+      for texture in scene.textures.iter() {
+          // Built in textures ship in the asset directory
+          let path = match ResourcePath::resolve(&texture.path, base_dir) {
+              ResourcePath::Builtin(name) => assets::asset_path(name),
+              ResourcePath::File(path) => path,
+          };
+          textures.insert(texture.name.as_str(), self.assets.load_texture(path));
+      }
+      // validate() made sure every name refers to something
+      let builder = scene_pipeline_builder(&self.render_pipeline_layout, self.assets.shader(&self.shader), self.config.format);
+      let mut materials = Vec::new();
+      for material in scene.materials.iter() {
+          let texture = textures[material.texture.as_str()].clone();
+          materials.push(Material {
+              name: material.name.clone(),
+              bind_group: create_texture_bind_group(
+                  &self.device,
+                  &self.texture_bind_group_layout,
+                  self.assets.texture(&texture),
+                  &material.name,
+              ),
+              texture,
+              blend: material.blend,
+              double_sided: material.double_sided,
+              pipeline: scene_pipelines(&self.device, &mut self.pipelines, &builder, material.blend, material.double_sided)
+                  .with_context(|| format!("Couldn't make the pipeline for material {}", material.name))?,
+          });
+      }
+      let mesh_indices = scene.meshes.iter().enumerate()
+          .map(|(index, mesh)| (mesh.name.as_str(), index))
+          .collect::<std::collections::HashMap<_, _>>();
+      let material_indices = scene.materials.iter().enumerate()
+          .map(|(index, material)| (material.name.as_str(), index))
+          .collect::<std::collections::HashMap<_, _>>();
+      let mut instances = scene.instances.iter()
+          .map(|instance| {
+              let batch = (mesh_indices[instance.mesh.as_str()], material_indices[instance.material.as_str()]);
+              (batch, Instance {
+                  position: instance.position.into(),
+                  rotation: instance.rotation.to_quaternion(),
+                  scale: instance.scale.into(),
+              })
+          })
+          .collect::<Vec<_>>();
+      // Transparent materials go last, so they're drawn over everything
+      // opaque. They aren't sorted by distance, so transparent instances
+      // overlapping each other can blend in the wrong order.
+      instances.sort_by_key(|&((mesh, material), _)| (materials[material].blend.is_transparent(), mesh, material));
+      let mut batches: Vec<Batch> = Vec::new();
+      for (index, ((mesh, material), _)) in instances.iter().enumerate() {
+          let index = index as u32;
+          match batches.last_mut() {
+              Some(batch) if batch.mesh == *mesh && batch.material == *material => batch.instances.end = index + 1,
+              _ => batches.push(Batch { mesh: *mesh, material: *material, instances: index..index + 1 }),
+          }
+      }
+      let instances = instances.into_iter().map(|(_, instance)| instance).collect::<Vec<_>>();
+
+      self.instance_buffer = create_instance_buffer(&self.device, &instances);
+      self.previous_instances = instances.clone();
+      self.instances = instances;
+      self.meshes = meshes;
+      self.materials = materials;
+      self.batches = batches;
+      let freed = self.assets.free_unused();
+      if freed > 0 {
+          log::info!("Freed {} assets the previous scene used", freed);
+      }
+      // Handles and undo steps refer to the instances that were replaced
+      self.selection.clear();
+      self.gizmo.end_drag();
+      self.history.clear();
+      self.camera_move_start = None;
+      if let Some((main, others)) = scene.cameras.split_first() {
+          self.views.truncate(1);
+          self.view_layout = viewport::ViewLayout::Single;
+          self.views[0].camera = main.to_camera();
+          self.views[0].viewport = main.viewport();
+          self.views[0].resize(self.size);
+          self.camera_controller.sync_with(&self.views[0].camera);
+          for camera in others {
+              self.add_view(camera.to_camera(), camera.viewport(), viewport::ClearMode::Depth);
+          }
+      }
+      self.scene = scene;
+      Ok(())
+  }
+
+  /// The loaded scene with the instances and main camera as they are now.
+  pub fn scene_description(&self) -> scene::SceneDescription {
+      let mut scene = self.scene.clone();
+      scene.version = scene::SCENE_VERSION;
+      scene.instances = self.batches.iter()
+          .flat_map(|batch| {
+              let mesh = &self.meshes[batch.mesh].name;
+              let material = &self.materials[batch.material].name;
+              self.instances[batch.instances.start as usize..batch.instances.end as usize].iter()
+                  .map(move |instance| scene::InstanceDescription {
+                      mesh: mesh.clone(),
+                      material: material.clone(),
+                      position: instance.position.into(),
+                      rotation: scene::Rotation::from_quaternion(instance.rotation),
+                      scale: instance.scale.into(),
+                  })
+          })
+          .collect();
+      // Other views may come from the view layout rather than the scene, so
+      // only the main camera is updated
+      let main = &self.views[0];
+      match scene.cameras.first_mut() {
+          Some(camera) => *camera = scene::CameraDescription::from_camera(camera.name.clone(), &main.camera, main.viewport),
+          None => scene.cameras.push(scene::CameraDescription::from_camera("main", &main.camera, main.viewport)),
+      }
+      scene
+  }
+
+  /// Replaces the key and button bindings, e.g. with ones loaded from a file.
+  pub fn set_input_config(&mut self, config: input::InputConfig) {
+      self.input.set_config(config);
+  }
+
+  /// Reads the connected gamepads for the next `update`.
+  fn poll_gamepads(&mut self) {
+      self.gamepads.update();
+  }
+
+  /// Uses `pads` as the connected gamepads for the next `update` instead of
+  /// polling them, for replays.
+  fn set_gamepads(&mut self, pads: Vec<gamepad::GamepadState>) {
+      self.gamepads.set_states(pads);
+  }
+
+  /// The gamepads the next `update` reads.
+  fn gamepads(&self) -> &[gamepad::GamepadState] {
+      self.gamepads.states()
+  }
+
+  /// True once the `quit` action has been triggered.
+  pub fn quit_requested(&self) -> bool {
+      self.quit_requested
+  }
+
   pub fn window(&self) -> &Window {
       &self.window
   }
 
+  /// Uploads a rigged model and starts playing its first animation clip.
+  pub fn add_skinned_model(&mut self, model: skinning::SkinnedModel, position: cgmath::Vector3<f32>) {
+    let instance = Instance {
+        position,
+        rotation: cgmath::Quaternion::one(),
+        scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+    };
+    let Some(skinning) = &self.skinning else {
+        log::warn!("Skinned meshes can't be drawn here, skipping one");
+        return;
+    };
+    self.skinned_instances.push(skinning::SkinnedInstance::new(&self.device, &skinning.joint_bind_group_layout, model, instance));
+  }
+
   pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
     if new_size.width > 0 && new_size.height > 0 {
         self.size = new_size;
         self.config.width = new_size.width;
         self.config.height = new_size.height;
+        for view in self.views.iter_mut() {
+            view.resize(new_size);
+        }
         self.surface.configure(&self.device, &self.config);
         self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+        self.picker.resize(&self.device, &self.config);
+        self.outline.resize(&self.device, &self.config);
     }
   }
 
   fn input(&mut self, event: &WindowEvent) -> bool {
-    if self.camera_controller.process_events(event) {
-      true
-    } else {
-      match event {
-          WindowEvent::CursorMoved { position, .. } => {
-              // Normalize the cursor position to 0.0 - 1.0
-              let x = position.x as f64 / self.size.width as f64;
-              let y = position.y as f64 / self.size.height as f64;
-
-              // Update clear color based on the position
-              self.clear_color = wgpu::Color {
-                  r: x,
-                  g: y,
-                  b: (x+y)/2.0, // Example: fixed blue component
-                  a: 1.0,
-              };
-
-              true
-          }
-          WindowEvent::KeyboardInput {
-            input:
-                KeyboardInput {
-                    state: ElementState::Pressed,
-                    virtual_keycode: Some(VirtualKeyCode::Space),
-                    ..
-                },
-            ..
-          } => {
-              // Add logic to toggle between pipelines
-              self.pipeline_toggle = !self.pipeline_toggle;
-              self.spacebar_toggle = !self.spacebar_toggle;
-              println!("Spacebar pressed, toggle value: {}", self.spacebar_toggle);
-              true
-          }
-          _ => false,
+    if let WindowEvent::Focused(false) = event {
+        // Don't keep the cursor grabbed while the user is elsewhere
+        self.camera_controller.fly.set_mouse_look(false);
+        self.update_cursor_grab();
+    }
+    if let WindowEvent::CursorMoved { position, .. } = event {
+        // Normalize the cursor position to 0.0 - 1.0
+        let x = position.x / self.size.width as f64;
+        let y = position.y / self.size.height as f64;
+
+        // Update clear color based on the position
+        self.clear_color = wgpu::Color {
+            r: x,
+            g: y,
+            b: (x+y)/2.0, // Example: fixed blue component
+            a: 1.0,
+        };
+    }
+    self.input.process_event(event)
+  }
+
+  fn device_input(&mut self, event: &DeviceEvent) {
+    self.input.process_device_event(event);
+  }
+
+  // Runs the one-shot actions triggered this frame.
+  fn handle_actions(&mut self) {
+    if self.input.pressed("quit") {
+        self.quit_requested = true;
+    }
+    if self.input.pressed("toggle_texture") {
+        // Add logic to toggle between pipelines
+        let before = self.spacebar_toggle;
+        self.execute(Box::new(history::SetTextureToggle { before, after: !before }));
+        println!("Spacebar pressed, toggle value: {}", self.spacebar_toggle);
+    }
+    // Each debug view's key switches to it, or back when it's showing
+    for view in debug_view::DebugView::DEBUG.into_iter().chain([debug_view::DebugView::Shaded]) {
+        if self.input.pressed(view.action()) {
+            let view = if view == self.debug_view { debug_view::DebugView::Shaded } else { view };
+            match self.set_debug_view(view) {
+                Ok(()) => log::info!("Debug view: {:?}", view),
+                Err(e) => log::error!("{:?}", e),
+            }
+        }
+    }
+    if self.input.pressed("toggle_camera_mode") {
+        self.camera_controller.toggle_mode(&self.views[0].camera);
+        log::info!("Camera mode: {:?}", self.camera_controller.mode());
+    }
+    if self.input.pressed("record_keyframe") {
+        // Record the current camera as the next keyframe, two seconds on
+        self.camera_path.path.push_camera(&self.views[0].camera, 2.0);
+        log::info!("Recorded camera keyframe {}", self.camera_path.path.keyframes.len());
+    }
+    if self.input.pressed("play_camera_path") {
+        if self.camera_path.is_playing() {
+            self.camera_path.stop();
+            self.finish_camera_path();
+        } else {
+            self.finish_camera_move();
+            self.camera_path_start = Some(camera::CameraPose::from_camera(&self.views[0].camera));
+            self.camera_path.play();
+        }
+    }
+    if self.input.pressed("save_camera_path") {
+        match self.camera_path.path.save(&self.camera_path_file) {
+            Ok(()) => log::info!("Saved camera path to {}", self.camera_path_file.display()),
+            Err(e) => log::error!("{:?}", e),
+        }
+    }
+    if self.input.pressed("save_scene") {
+        match self.scene_description().save(&self.scene_file) {
+            Ok(()) => log::info!("Saved scene to {}", self.scene_file.display()),
+            Err(e) => log::error!("{:?}", e),
+        }
+    }
+    if !self.gizmo.is_dragging() {
+        if self.input.pressed("gizmo_translate") {
+            self.gizmo.mode = gizmo::GizmoMode::Translate;
+        }
+        if self.input.pressed("gizmo_rotate") {
+            self.gizmo.mode = gizmo::GizmoMode::Rotate;
+        }
+        if self.input.pressed("gizmo_scale") {
+            self.gizmo.mode = gizmo::GizmoMode::Scale;
+        }
+        if self.input.pressed("undo") {
+            if let Some(name) = self.with_history(|history, state| history.undo(state)) {
+                log::info!("Undo {}", name);
+            }
+        }
+        if self.input.pressed("redo") {
+            if let Some(name) = self.with_history(|history, state| history.redo(state)) {
+                log::info!("Redo {}", name);
+            }
+        }
+    }
+    let gizmo_has_mouse = self.update_gizmo();
+    // The fly camera uses clicks for mouse look
+    if self.input.pressed("pick") && !gizmo_has_mouse && self.camera_controller.mode() != camera::CameraMode::Fly {
+        if let Some(position) = self.input.cursor_position() {
+            self.picker.request(position);
+            self.pick_extends_selection = self.input.held("extend_selection");
+        }
+    }
+    if self.input.pressed("cycle_view_layout") {
+        self.set_view_layout(self.view_layout.next());
+        log::info!("View layout: {:?}", self.view_layout);
+    }
+  }
+
+  // The selected instances the gizmo transforms, the last one being the
+  // pivot it's drawn on. None while flying, as clicks are for mouse look.
+  fn gizmo_targets(&self) -> Vec<InstanceHandle> {
+    if self.camera_controller.mode() == camera::CameraMode::Fly {
+      return Vec::new();
+    }
+    self.selection.iter()
+        .copied()
+        .filter(|handle| handle.0 < self.instances.len())
+        .collect()
+  }
+
+  // Hovers, grabs and drags the gizmo. Returns true while it has the mouse,
+  // so the same click doesn't also pick or orbit.
+  fn update_gizmo(&mut self) -> bool {
+    let targets = self.gizmo_targets();
+    let main_view = &self.views[0];
+    let ray = self.input.cursor_position()
+        .filter(|position| main_view.viewport.contains(self.size, *position))
+        .map(|position| raycast::Ray::from_pixel(&main_view.camera, main_view.viewport, self.size, position));
+
+    if self.gizmo.is_dragging() {
+      if self.input.pressed("gizmo_cancel") {
+        // Put everything back where the drag started
+        self.gizmo.end_drag();
+        self.with_history(|history, state| history.cancel_transaction(state));
+        self.input.consume_cursor_delta();
+        return true;
       }
+      if self.input.held("gizmo_drag") && !targets.is_empty() {
+        let changes = ray.as_ref().and_then(|ray| self.gizmo.drag(ray, self.input.held("gizmo_snap")));
+        if let Some(changes) = changes {
+          let changes = changes.into_iter()
+              .map(|(handle, after)| history::InstanceChange {
+                instance: handle,
+                before: self.instances[handle.0].clone(),
+                after,
+              })
+              .collect();
+          // Every frame of the drag merges into a single undo step
+          let merge_id = self.gizmo.drag_id();
+          self.execute(Box::new(history::SetInstances { changes, merge_id }));
+        }
+        self.input.consume_cursor_delta();
+        return true;
+      }
+      self.gizmo.end_drag();
+      self.history.commit_transaction();
     }
+
+    let pivot = targets.last().map(|handle| &self.instances[handle.0]);
+    self.gizmo.hover(&self.views[0].camera, pivot, ray.as_ref());
+    if let Some(ray) = ray.filter(|_| self.input.pressed("gizmo_drag")) {
+      let targets = targets.iter()
+          .map(|handle| (*handle, self.instances[handle.0].clone()))
+          .collect();
+      if self.gizmo.begin_drag(&self.views[0].camera, &ray, targets) {
+        self.history.begin_transaction("transform instances");
+        self.input.consume_cursor_delta();
+        return true;
+      }
+    }
+    false
+  }
+
+  // Runs `f` with the history moved out of `self`, so its commands can edit
+  // `self` as their `EditTarget`.
+  fn with_history<R>(&mut self, f: impl FnOnce(&mut history::History, &mut Self) -> R) -> R {
+    let mut history = std::mem::take(&mut self.history);
+    let result = f(&mut history, self);
+    self.history = history;
+    result
   }
 
-  fn update(&mut self) {
-    let delta_rotation = cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(1.0));
+  fn execute(&mut self, command: Box<dyn history::Command>) {
+    self.with_history(|history, state| history.execute(command, state));
+  }
+
+  // Hands the camera back to the controller once a path stops, recording
+  // where the path took it as one undo step.
+  fn finish_camera_path(&mut self) {
+    self.camera_controller.sync_with(&self.views[0].camera);
+    let after = camera::CameraPose::from_camera(&self.views[0].camera);
+    if let Some(before) = self.camera_path_start.take().filter(|before| *before != after) {
+      self.history.record(Box::new(history::SetCamera { before, after }));
+    }
+  }
+
+  // Notes the start of a move when the controller moved the camera from
+  // `before` this step, and ends the move once the camera has come to rest
+  // with nothing dragging it. Easing towards the goal creeps on for a while,
+  // so steps too small to see count as resting.
+  fn track_camera_move(&mut self, before: camera::CameraPose) {
+    const REST_EPSILON: f32 = 1e-4;
+    let after = camera::CameraPose::from_camera(&self.views[0].camera);
+    if !after.is_near(&before, REST_EPSILON) {
+      self.camera_move_start.get_or_insert(before);
+    } else if !self.camera_controller.is_dragging() {
+      self.finish_camera_move();
+    }
+  }
+
+  // Records the camera move in progress as one undo step.
+  fn finish_camera_move(&mut self) {
+    let after = camera::CameraPose::from_camera(&self.views[0].camera);
+    if let Some(before) = self.camera_move_start.take().filter(|before| *before != after) {
+      self.history.record(Box::new(history::SetCamera { before, after }));
+    }
+  }
+
+  // Applies a pick to the selection. Extending toggles the picked instance
+  // and keeps the rest, otherwise it replaces the selection. Picking nothing
+  // clears it unless extending.
+  fn select(&mut self, picked: Option<InstanceHandle>, extend: bool) {
+    match (picked, extend) {
+      (Some(handle), true) => {
+        if let Some(index) = self.selection.iter().position(|selected| *selected == handle) {
+          self.selection.remove(index);
+        } else {
+          self.selection.push(handle);
+        }
+      }
+      (Some(handle), false) => self.selection = vec![handle],
+      (None, true) => {}
+      (None, false) => self.selection.clear(),
+    }
+  }
+
+  // Grabs and hides the cursor while the fly camera is in mouse look.
+  fn update_cursor_grab(&mut self) {
+    use winit::window::CursorGrabMode;
+
+    let grab = self.camera_controller.wants_cursor_grab();
+    if grab == self.cursor_grabbed {
+      return;
+    }
+    let result = if grab {
+      // Not every platform supports locking, confining is close enough
+      // as we only read raw device motion.
+      self.window.set_cursor_grab(CursorGrabMode::Locked)
+          .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined))
+    } else {
+      self.window.set_cursor_grab(CursorGrabMode::None)
+    };
+    if let Err(e) = result {
+      log::warn!("Couldn't change cursor grab: {}", e);
+    }
+    self.window.set_cursor_visible(!grab);
+    self.cursor_grabbed = grab;
+  }
+
+  // Swaps placeholders for the textures that have loaded since.
+  fn update_texture_bind_groups(&mut self) {
+    self.diffuse_bind_group = create_texture_bind_group(
+        &self.device,
+        &self.texture_bind_group_layout,
+        self.assets.texture(&self.diffuse_texture),
+        "diffuse_bind_group",
+    );
+    self.noise_bind_group = create_texture_bind_group(
+        &self.device,
+        &self.texture_bind_group_layout,
+        self.assets.texture(&self.noise_texture),
+        "noise_bind_group",
+    );
+    for material in self.materials.iter_mut() {
+        material.bind_group = create_texture_bind_group(
+            &self.device,
+            &self.texture_bind_group_layout,
+            self.assets.texture(&material.texture),
+            &material.name,
+        );
+    }
+  }
+
+  // Remakes the pipelines with the current version of their shaders,
+  // keeping the previous pipelines when the shaders no longer fit.
+  fn recreate_pipelines(&mut self) {
+    let builder = scene_pipeline_builder(&self.render_pipeline_layout, self.assets.shader(&self.shader), self.config.format);
+    match scene_pipelines(&self.device, &mut self.pipelines, &builder, scene::BlendMode::Opaque, false) {
+        Ok(pipeline) => {
+            self.render_pipeline = pipeline;
+            // Every material's variant fits if the opaque one does
+            for material in self.materials.iter_mut() {
+                match scene_pipelines(&self.device, &mut self.pipelines, &builder, material.blend, material.double_sided) {
+                    Ok(pipeline) => material.pipeline = pipeline,
+                    Err(e) => log::error!("{:?}", e),
+                }
+            }
+        }
+        Err(e) => log::error!("{:?}", e),
+    }
+    if let Some(skinning) = &self.skinning {
+        let builder = skinned_pipeline_builder(&skinning.pipeline_layout, self.assets.shader(&skinning.shader), self.config.format);
+        match scene_pipelines(&self.device, &mut self.pipelines, &builder, scene::BlendMode::Opaque, false) {
+            Ok(pipeline) => self.skinned_pipeline = Some(pipeline),
+            Err(e) => log::error!("{:?}", e),
+        }
+    }
+    if self.debug_view != debug_view::DebugView::Shaded {
+        match self.create_debug_pipelines(self.debug_view) {
+            Ok(pipelines) => self.debug_pipelines = Some(pipelines),
+            Err(e) => log::error!("{:?}", e),
+        }
+    }
+    // Pipelines made from previous versions of the shaders are only kept
+    // alive by whoever still draws with them
+    self.pipelines.retain_shaders(self.assets.shaders());
+  }
+
+  fn create_debug_pipelines(&mut self, view: debug_view::DebugView) -> anyhow::Result<DebugPipelines> {
+    let shaders = self.debug_shaders.iter()
+        .find(|shaders| shaders.view == view)
+        .expect("Every debug view has shaders");
+    let create = |pipelines: &mut pipeline::PipelineCache, builder: pipeline::PipelineBuilder| {
+        let builder = view.pipeline(builder, self.line_polygon_mode);
+        DepthVariants::try_new(|depth_compare| {
+            pipelines.get(&self.device, &builder.clone().depth_compare(view.depth_compare(depth_compare)))
+        })
+    };
+    let scene = create(
+        &mut self.pipelines,
+        scene_pipeline_builder(&self.render_pipeline_layout, self.assets.shader(&shaders.scene), self.config.format),
+    )?;
+    let skinned = match (&shaders.skinned, &self.skinning) {
+        (Some(shader), Some(skinning)) => Some(create(
+            &mut self.pipelines,
+            skinned_pipeline_builder(&skinning.pipeline_layout, self.assets.shader(shader), self.config.format),
+        )?),
+        _ => None,
+    };
+    Ok(DebugPipelines { scene, skinned })
+  }
 
+  /// Draws the scene as `view` from the next frame on. The pipelines for a
+  /// view are made the first time it's shown; if they can't be, the error
+  /// is returned and the view stays as it was.
+  pub fn set_debug_view(&mut self, view: debug_view::DebugView) -> anyhow::Result<()> {
+    self.debug_pipelines = match view {
+        debug_view::DebugView::Shaded => None,
+        view => Some(self.create_debug_pipelines(view)?),
+    };
+    self.debug_view = view;
+    self.make_corner_buffers();
+    Ok(())
+  }
+
+  // Gives every mesh the buffer wireframes are drawn from when the shader
+  // draws them, once they're shown. Meshes loaded or added while they're
+  // shown get theirs on the next update.
+  fn make_corner_buffers(&mut self) {
+    if self.debug_view != debug_view::DebugView::Wireframe || self.line_polygon_mode {
+        return;
+    }
+    for mesh in self.assets.meshes_mut() {
+        mesh.make_corner_buffer(&self.device);
+    }
+    self.hexagon.make_corner_buffer(&self.device);
+    for skinned in self.skinned_instances.iter_mut() {
+        skinned.make_corner_buffer(&self.device);
+    }
+  }
+
+  // Shows loading progress in the window title, the closest thing to a
+  // loading screen there is.
+  fn update_loading_progress(&mut self) {
+    let progress = self.assets.progress();
+    if progress == self.shown_progress {
+      return;
+    }
+    if progress.is_done() {
+      self.window.set_title(&self.title);
+      log::info!("Loaded {} assets, {} failed", progress.total, progress.failed);
+    } else {
+      self.window.set_title(&format!(
+        "{} (loading {}/{}, {:.0}%)",
+        self.title,
+        progress.finished,
+        progress.total,
+        progress.fraction() * 100.0,
+      ));
+    }
+    self.shown_progress = progress;
+  }
+
+  fn update(&mut self, time: &mut time::Time) {
+    let changes = self.assets.poll(&self.device, &self.queue);
+    if changes.textures > 0 {
+        self.update_texture_bind_groups();
+    }
+    if changes.shaders > 0 {
+        self.recreate_pipelines();
+    }
+    self.make_corner_buffers();
+    self.update_loading_progress();
+    if let Some(pick) = self.picker.poll(&self.device) {
+        match pick {
+            Some(pick) => log::info!("Picked instance {} at {:?}", pick.instance.0, pick.position),
+            None => log::info!("Picked nothing"),
+        }
+        self.select(pick.map(|pick| pick.instance), self.pick_extends_selection);
+    }
+    self.input.update_gamepads(self.gamepads.states());
+    self.handle_actions();
+    self.camera_controller.process_input(&self.input);
+    self.update_cursor_grab();
+    self.input.end_frame(time.delta());
+
+    while time.consume_fixed_step() {
+        self.fixed_update(time.fixed_timestep());
+    }
+
+    for view in self.views.iter_mut() {
+        view.update(&self.queue);
+    }
+    self.globals_uniform.update(time, self.size);
+    self.queue.write_buffer(&self.globals_buffer, 0, bytemuck::cast_slice(&[self.globals_uniform]));
+    let alpha = time.alpha();
+    let instance_data = self.previous_instances.iter()
+        .zip(self.instances.iter())
+        .map(|(previous, current)| previous.interpolate(current, alpha).to_raw())
+        .collect::<Vec<_>>();
+    self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+    self.outline.update(&self.queue);
+    let pivot = self.gizmo_targets().last().map(|handle| self.instances[handle.0].clone());
+    self.gizmo.update(&self.device, &self.queue, &self.views[0].camera, pivot.as_ref());
+    for skinned in self.skinned_instances.iter_mut() {
+        skinned.update(&self.queue, time.delta());
+    }
+  }
+
+  // Advances the simulation by exactly `dt` seconds.
+  fn fixed_update(&mut self, dt: f32) {
+    // degrees per second
+    const ROTATION_SPEED: f32 = 60.0;
+    let delta_rotation = cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(ROTATION_SPEED * dt));
+
+    self.previous_instances.clone_from_slice(&self.instances);
     for instance in self.instances.iter_mut() {
         // Rotate the instance around the Z-axis
         instance.rotation = instance.rotation * delta_rotation;
     }
 
-    self.camera_controller.update_camera(&mut self.camera);
-    self.camera_uniform.update_view_proj(&self.camera);
-    self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
-    let instance_data = self.instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-    self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+    // A playing camera path takes over from the controller
+    if self.camera_path.is_playing() {
+        self.camera_path.update(&mut self.views[0].camera, dt);
+        if !self.camera_path.is_playing() {
+            self.finish_camera_path();
+        }
+    } else {
+        let before = camera::CameraPose::from_camera(&self.views[0].camera);
+        self.camera_controller.update_camera(&mut self.views[0].camera, dt);
+        self.track_camera_move(before);
+    }
   }
 
   fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -802,6 +1590,23 @@ let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupL
     let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
         label: Some("Render Encoder"),
     });
+    // The toggle draws everything as textured hexagons, otherwise each batch
+    // uses its own mesh and material
+    // The whole frame starts out cleared for the main camera, views using
+    // the other depth convention clear their own viewport
+    let frame_depth = self.camera().projection.depth_clear_value();
+    let draws = if self.spacebar_toggle {
+      vec![(&self.render_pipeline, &self.diffuse_bind_group, self.hexagon.draw(0..self.instances.len() as u32))]
+    } else {
+      self.batches.iter()
+          .map(|batch| {
+            let mesh = self.assets.mesh(&self.meshes[batch.mesh].mesh);
+            let material = &self.materials[batch.material];
+            let draw = MeshDraw { double_sided: material.double_sided, ..mesh.draw(batch.instances.clone()) };
+            (&material.pipeline, &material.bind_group, draw)
+          })
+          .collect::<Vec<_>>()
+    };
     {
       let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
           label: Some("Render Pass"),
@@ -816,7 +1621,7 @@ let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupL
           depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
             view: &self.depth_texture.view,
             depth_ops: Some(wgpu::Operations {
-                load: wgpu::LoadOp::Clear(1.0),
+                load: wgpu::LoadOp::Clear(frame_depth),
                 store: wgpu::StoreOp::Store,
             }),
             stencil_ops: None,
@@ -825,39 +1630,115 @@ let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupL
           timestamp_writes: None,
       });
 
-      let pipeline = &self.render_pipeline;/*if self.spacebar_toggle {
-        &self.color_pipeline
-      } else {
-        &self.render_pipeline
-      };*/
+      // Views are drawn in order, later ones on top of earlier ones.
+      for view in self.views.iter() {
+        view.begin(&mut render_pass, &self.view_clearer, self.size, frame_depth);
 
-      let (vertex_buffer, index_buffer, num_indices) = if self.spacebar_toggle {
-        (&self.hexagon_vertex_buffer, &self.hexagon_index_buffer, self.hexagon_num_indices)
-      } else {
-        (&self.square_vertex_buffer, &self.square_index_buffer, self.square_num_indices)
-      };
-
-      let current_bind_group = self.get_texture_bind_group();
+        let reverse_z = view.camera.projection.is_reverse_z();
+        render_pass.set_bind_group(1, view.camera_bind_group(), &[]);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        if let Some(debug_pipelines) = &self.debug_pipelines {
+          // Debug views draw every batch the same way, ignoring materials
+          render_pass.set_pipeline(debug_pipelines.scene.get(reverse_z));
+          for (_, bind_group, draw) in draws.iter() {
+            render_pass.set_bind_group(0, bind_group, &[]);
+            if self.debug_view == debug_view::DebugView::Wireframe && !self.line_polygon_mode {
+              draw.draw_corners(&mut render_pass);
+            } else {
+              draw.draw(&mut render_pass);
+            }
+          }
+        } else {
+          // Materials drawn the same way share a pipeline, so it's only
+          // switched when the next batch needs a different one
+          let mut current_pipeline = self.render_pipeline.get(reverse_z);
+          render_pass.set_pipeline(current_pipeline);
+          for (pipeline, bind_group, draw) in draws.iter() {
+            let pipeline = pipeline.get(reverse_z);
+            if !Arc::ptr_eq(pipeline, current_pipeline) {
+              render_pass.set_pipeline(pipeline);
+              current_pipeline = pipeline;
+            }
+            render_pass.set_bind_group(0, bind_group, &[]);
+            draw.draw(&mut render_pass);
+          }
+        }
 
-      // Use the selected pipeline
-      render_pass.set_pipeline(pipeline);
-      render_pass.set_bind_group(0, current_bind_group, &[]);
-      render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-      render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-      render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-      render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-      render_pass.draw_indexed(0..num_indices, 0, 0..self.instances.len() as _);
-      //render_pass.draw(0..self.num_vertices, 0..1);
+        let skinned_pipeline = self.debug_pipelines.as_ref()
+            .and_then(|debug_pipelines| debug_pipelines.skinned.as_ref())
+            .or(self.skinned_pipeline.as_ref());
+        if let Some(skinned_pipeline) = skinned_pipeline {
+            render_pass.set_pipeline(skinned_pipeline.get(reverse_z));
+            render_pass.set_bind_group(0, self.get_texture_bind_group(), &[]);
+            for skinned in self.skinned_instances.iter() {
+                if self.debug_view == debug_view::DebugView::Wireframe && !self.line_polygon_mode {
+                    skinned.draw_corners(&mut render_pass);
+                } else {
+                    skinned.draw(&mut render_pass);
+                }
+            }
+        }
+        //render_pass.draw(0..self.num_vertices, 0..1);
+      }
     }
 
+    // Pick against the same meshes that were just drawn
+    let mesh_draws = draws.iter().map(|(_, _, draw)| draw.clone()).collect::<Vec<_>>();
+    self.picker.encode(&mut encoder, &self.views, self.size, picking::PickGeometry {
+        instance_buffer: &self.instance_buffer,
+        draws: &mesh_draws,
+    });
+
+    // Each selected instance is drawn on its own from its batch's mesh
+    let selected_draws = self.selection.iter()
+        .filter_map(|handle| {
+          let index = handle.0 as u32;
+          mesh_draws.iter()
+              .find(|draw| draw.instances.contains(&index))
+              .map(|draw| MeshDraw { instances: index..index + 1, ..draw.clone() })
+        })
+        .collect::<Vec<_>>();
+    self.outline.encode(&mut encoder, &view, &self.depth_texture.view, &self.views, self.size, outline::OutlineGeometry {
+        instance_buffer: &self.instance_buffer,
+        draws: &selected_draws,
+    });
+
+    self.gizmo.encode(&mut encoder, &view, &self.views[0], self.size);
+
     // submit will accept anything that implements IntoIter
     self.queue.submit(std::iter::once(encoder.finish()));
+    self.picker.after_submit();
     output.present();
 
     Ok(())
   }
 }
 
+impl history::EditTarget for State {
+  fn set_instance(&mut self, handle: InstanceHandle, instance: Instance) {
+    if handle.0 >= self.instances.len() {
+      return;
+    }
+    // Jump straight there rather than interpolating from where it was
+    self.previous_instances[handle.0] = instance.clone();
+    self.instances[handle.0] = instance;
+  }
+
+  fn set_texture_toggle(&mut self, on: bool) {
+    self.spacebar_toggle = on;
+  }
+
+  fn set_camera_pose(&mut self, pose: camera::CameraPose) {
+    // Undoing out of a flythrough takes the camera back from the path
+    self.camera_path.stop();
+    self.camera_path_start = None;
+    // The move in progress, if any, is what's being undone
+    self.camera_move_start = None;
+    pose.apply(&mut self.views[0].camera);
+    self.camera_controller.sync_with(&self.views[0].camera);
+  }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct Vertex {
@@ -865,6 +1746,7 @@ struct Vertex {
     tex_coords: [f32; 2],
 }
 
+#[allow(clippy::excessive_precision)]
 const HEXAGON_VERTICES: &[Vertex] = &[
     Vertex { position: [ 0.0,  0.0, 0.0], tex_coords: [0.5, 0.5], }, // Center
     Vertex { position: [ 0.0,  1.0, 0.0], tex_coords: [1.0, 0.5] }, // Top
@@ -896,6 +1778,104 @@ const SQUARE_INDICES: &[u16] = &[
     0, 3, 1, // Second triangle: top-left, bottom-right, top-right
 ];
 
+/// Vertices and indices of the meshes available as `builtin:<name>`.
+fn builtin_mesh(name: &str) -> Option<(&'static [Vertex], &'static [u16])> {
+    match name {
+        "hexagon" => Some((HEXAGON_VERTICES, HEXAGON_INDICES)),
+        "square" => Some((SQUARE_VERTICES, SQUARE_INDICES)),
+        _ => None,
+    }
+}
+
+// What the scene's meshes are drawn with, before materials pick their
+// blending and culling.
+fn scene_pipeline_builder<'a>(
+    layout: &'a pipeline::PipelineLayout,
+    shader: &'a assets::Shader,
+    format: wgpu::TextureFormat,
+) -> pipeline::PipelineBuilder<'a> {
+    pipeline::PipelineBuilder::new("Render Pipeline", layout, shader, format)
+        .vertex_buffers(&[Vertex::desc(), InstanceRaw::desc()])
+}
+
+fn skinned_pipeline_builder<'a>(
+    layout: &'a pipeline::PipelineLayout,
+    shader: &'a assets::Shader,
+    format: wgpu::TextureFormat,
+) -> pipeline::PipelineBuilder<'a> {
+    pipeline::PipelineBuilder::new("Skinned Pipeline", layout, shader, format)
+        .vertex_buffers(&[skinning::SkinnedVertex::desc(), InstanceRaw::desc()])
+}
+
+// The variant of a scene pipeline for a material, for both depth
+// conventions. Transparent materials are tested against the depth buffer
+// but don't write to it, so they don't hide each other.
+fn scene_pipelines(
+    device: &wgpu::Device,
+    pipelines: &mut pipeline::PipelineCache,
+    builder: &pipeline::PipelineBuilder,
+    blend: scene::BlendMode,
+    double_sided: bool,
+) -> anyhow::Result<DepthVariants<Arc<wgpu::RenderPipeline>>> {
+    let builder = builder.clone()
+        .blend(Some(blend.blend_state()))
+        .depth_write(!blend.is_transparent())
+        .cull_mode(if double_sided { None } else { Some(wgpu::Face::Back) });
+    DepthVariants::try_new(|depth_compare| pipelines.get(device, &builder.clone().depth_compare(depth_compare)))
+}
+
+fn create_texture_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &texture::Texture,
+    label: &str,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some(label),
+    })
+}
+
+// wgpu can't bind an empty buffer, so there's always room for one instance.
+fn create_instance_buffer(device: &wgpu::Device, instances: &[Instance]) -> wgpu::Buffer {
+    let mut instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+    if instance_data.is_empty() {
+        instance_data.push(bytemuck::Zeroable::zeroed());
+    }
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Instance Buffer"),
+        contents: bytemuck::cast_slice(&instance_data),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+/// CPU copy of the hexagon mesh, for ray casting without a GPU.
+pub fn hexagon_mesh() -> raycast::RaycastMesh {
+    raycast_mesh(HEXAGON_VERTICES, HEXAGON_INDICES)
+}
+
+/// CPU copy of the square mesh, for ray casting without a GPU.
+pub fn square_mesh() -> raycast::RaycastMesh {
+    raycast_mesh(SQUARE_VERTICES, SQUARE_INDICES)
+}
+
+fn raycast_mesh(vertices: &[Vertex], indices: &[u16]) -> raycast::RaycastMesh {
+    let positions = vertices.iter().map(|vertex| vertex.position).collect::<Vec<_>>();
+    let tex_coords = vertices.iter().map(|vertex| vertex.tex_coords).collect::<Vec<_>>();
+    let indices = indices.iter().map(|&index| index as u32).collect::<Vec<_>>();
+    raycast::RaycastMesh::new(&positions, &tex_coords, &indices)
+}
+
 impl Vertex {
   fn desc() -> wgpu::VertexBufferLayout<'static> {
       wgpu::VertexBufferLayout {
@@ -910,9 +1890,62 @@ impl Vertex {
               wgpu::VertexAttribute {
                   offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
                   shader_location: 1,
-                  format: wgpu::VertexFormat::Float32x3,
+                  format: wgpu::VertexFormat::Float32x2,
               }
           ]
       }
   }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The name, offset and size of each of a struct's fields
+    macro_rules! fields {
+        ($ty:ty { $($field:ident),* $(,)? }) => {
+            &[$((
+                stringify!($field),
+                std::mem::offset_of!($ty, $field),
+                reflection::field_size(|vertex: &$ty| &vertex.$field),
+            )),*]
+        };
+    }
+
+    #[test]
+    fn vertex_layouts_match_their_structs() {
+        reflection::check_vertex_layout(&Vertex::desc(), std::mem::size_of::<Vertex>(), fields!(Vertex { position, tex_coords }))
+            .unwrap();
+        reflection::check_vertex_layout(&InstanceRaw::desc(), std::mem::size_of::<InstanceRaw>(), fields!(InstanceRaw { model }))
+            .unwrap();
+        reflection::check_vertex_layout(
+            &skinning::SkinnedVertex::desc(),
+            std::mem::size_of::<skinning::SkinnedVertex>(),
+            fields!(skinning::SkinnedVertex { position, tex_coords, joints, weights }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn vertex_layout_mismatches_are_reported() {
+        #[repr(C)]
+        struct Padded {
+            position: [f32; 3],
+            flags: u32,
+            tex_coords: [f32; 2],
+        }
+        // Laid out as if there were no flags, and missing a location for them
+        let layout = wgpu::VertexBufferLayout {
+            array_stride: 20,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2],
+        };
+        let error = reflection::check_vertex_layout(&layout, std::mem::size_of::<Padded>(), fields!(Padded { position, flags, tex_coords }))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error.lines().count(), 3, "{}", error);
+        assert!(error.contains("The stride is 20 bytes but the struct is 24"), "{}", error);
+        assert!(error.contains("flags"), "{}", error);
+        assert!(error.contains("tex_coords: no attribute reads it"), "{}", error);
+    }
+}
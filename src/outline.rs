@@ -0,0 +1,283 @@
+use crate::pipeline::{PipelineBuilder, PipelineLayout};
+use crate::viewport::View;
+use crate::{DepthVariants, InstanceRaw, MeshDraw, Vertex};
+
+const MASK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+// Thicker outlines get expensive quickly, every pixel checks a square of
+// (2 * thickness + 1)^2 neighbours.
+const MAX_THICKNESS: f32 = 16.0;
+
+/// How selected instances are outlined.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OutlineSettings {
+    /// Linear RGBA, alpha blends the outline over the scene.
+    pub color: [f32; 4],
+    /// Width in pixels, clamped to 16.
+    pub thickness: f32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 0.6, 0.1, 1.0],
+            thickness: 3.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    thickness: f32,
+    _padding: [f32; 3],
+}
+
+/// The selected instances, as draws of ranges of `instance_buffer`.
+pub struct OutlineGeometry<'a> {
+    pub instance_buffer: &'a wgpu::Buffer,
+    pub draws: &'a [MeshDraw<'a>],
+}
+
+/// Draws an outline around selected instances.
+///
+/// The selected instances are drawn into a mask, then a full screen pass
+/// colors every pixel outside the mask that lies within `thickness` of it.
+/// Working in screen space keeps the outline the same width at any distance
+/// and works for flat meshes, where an inverted hull would have nothing to
+/// grow. The mask is depth tested against the scene, so only the visible
+/// parts of selected instances are outlined.
+pub struct Outline {
+    pub settings: OutlineSettings,
+    mask_pipeline: DepthVariants<wgpu::RenderPipeline>,
+    // Without back face culling, for double sided materials
+    double_sided_mask_pipeline: DepthVariants<wgpu::RenderPipeline>,
+    composite_pipeline: wgpu::RenderPipeline,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    composite_bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    mask_view: wgpu::TextureView,
+}
+
+impl Outline {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_entries: &[wgpu::BindGroupLayoutEntry],
+    ) -> anyhow::Result<Self> {
+        let mask_shader = device.create_shader_module(wgpu::include_wgsl!("outline_mask.wgsl"));
+        let mask_layout = PipelineLayout::new(
+            device,
+            "Outline Mask Pipeline Layout",
+            &[(camera_bind_group_layout, camera_entries)],
+        );
+        // Match the scene so the mask covers exactly what is visible, and
+        // pass where the scene drew the same triangle without changing its
+        // depth
+        let mask_builder = PipelineBuilder::from_module("Outline Mask Pipeline", &mask_layout, &mask_shader, MASK_FORMAT)
+            .vertex_buffers(&[Vertex::desc(), InstanceRaw::desc()])
+            .blend(None)
+            .depth_write(false);
+        let mask_pipelines = |cull_mode| {
+            DepthVariants::try_new(|depth_compare| {
+                mask_builder
+                    .clone()
+                    .cull_mode(cull_mode)
+                    .depth_compare(match depth_compare {
+                        wgpu::CompareFunction::Greater => wgpu::CompareFunction::GreaterEqual,
+                        _ => wgpu::CompareFunction::LessEqual,
+                    })
+                    .build(device)
+            })
+        };
+        let mask_pipeline = mask_pipelines(Some(wgpu::Face::Back))?;
+        let double_sided_mask_pipeline = mask_pipelines(None)?;
+
+        let composite_entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        let composite_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("outline_bind_group_layout"),
+            entries: &composite_entries,
+        });
+        let composite_shader = device.create_shader_module(wgpu::include_wgsl!("outline.wgsl"));
+        let composite_layout = PipelineLayout::new(
+            device,
+            "Outline Pipeline Layout",
+            &[(&composite_bind_group_layout, &composite_entries)],
+        );
+        let composite_pipeline = PipelineBuilder::from_module("Outline Pipeline", &composite_layout, &composite_shader, config.format)
+            .blend(Some(wgpu::BlendState::ALPHA_BLENDING))
+            .cull_mode(None)
+            .no_depth()
+            .build(device)?;
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outline Buffer"),
+            size: std::mem::size_of::<OutlineUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mask_view = create_mask(device, config);
+        let composite_bind_group =
+            create_composite_bind_group(device, &composite_bind_group_layout, &mask_view, &uniform_buffer);
+        Ok(Self {
+            settings: OutlineSettings::default(),
+            mask_pipeline,
+            double_sided_mask_pipeline,
+            composite_pipeline,
+            composite_bind_group_layout,
+            composite_bind_group,
+            uniform_buffer,
+            mask_view,
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.mask_view = create_mask(device, config);
+        self.composite_bind_group = create_composite_bind_group(
+            device,
+            &self.composite_bind_group_layout,
+            &self.mask_view,
+            &self.uniform_buffer,
+        );
+    }
+
+    /// Uploads the settings.
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let uniform = OutlineUniform {
+            color: self.settings.color,
+            thickness: self.settings.thickness.clamp(0.0, MAX_THICKNESS),
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Draws the outline on top of `target`, whose scene was drawn with the
+    /// depth buffer `depth`. Does nothing when nothing is selected.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        views: &[View],
+        size: winit::dpi::PhysicalSize<u32>,
+        geometry: OutlineGeometry,
+    ) {
+        if geometry.draws.is_empty() || self.settings.thickness <= 0.0 {
+            return;
+        }
+        {
+            let mut mask_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Outline Mask Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.mask_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            mask_pass.set_vertex_buffer(1, geometry.instance_buffer.slice(..));
+            for view in views {
+                let (x, y, width, height) = view.viewport.to_physical(size);
+                let reverse_z = view.camera.projection.is_reverse_z();
+                mask_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+                mask_pass.set_bind_group(0, view.camera_bind_group(), &[]);
+                for draw in geometry.draws {
+                    let pipeline = if draw.double_sided { &self.double_sided_mask_pipeline } else { &self.mask_pipeline };
+                    mask_pass.set_pipeline(pipeline.get(reverse_z));
+                    draw.draw(&mut mask_pass);
+                }
+            }
+        }
+        let mut composite_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        composite_pass.set_pipeline(&self.composite_pipeline);
+        composite_pass.set_bind_group(0, &self.composite_bind_group, &[]);
+        composite_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_mask(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("outline_mask_texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: MASK_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_composite_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    mask_view: &wgpu::TextureView,
+    uniform_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("outline_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(mask_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
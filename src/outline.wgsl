@@ -0,0 +1,44 @@
+// Draws an outline around the selection mask by looking for covered pixels
+// within `thickness` of each uncovered one.
+
+struct OutlineUniform {
+    color: vec4<f32>,
+    thickness: f32,
+};
+@group(0) @binding(0)
+var mask: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> outline: OutlineUniform;
+
+// A single triangle covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let center = vec2<i32>(position.xy);
+    // Only draw around the selection, never over it
+    if textureLoad(mask, center, 0).r > 0.5 {
+        discard;
+    }
+    let last = vec2<i32>(textureDimensions(mask)) - 1;
+    let radius = i32(ceil(outline.thickness));
+    var coverage = 0.0;
+    for (var y = -radius; y <= radius; y += 1) {
+        for (var x = -radius; x <= radius; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y));
+            if length(offset) > outline.thickness {
+                continue;
+            }
+            let texel = clamp(center + vec2<i32>(x, y), vec2<i32>(0), last);
+            coverage = max(coverage, textureLoad(mask, texel, 0).r);
+        }
+    }
+    if coverage == 0.0 {
+        discard;
+    }
+    return vec4<f32>(outline.color.rgb, outline.color.a * coverage);
+}
@@ -0,0 +1,286 @@
+use std::sync::{Arc, Mutex};
+
+use cgmath::prelude::*;
+
+use crate::pipeline::{PipelineBuilder, PipelineLayout};
+use crate::viewport::View;
+use crate::{texture, DepthVariants, InstanceHandle, InstanceRaw, MeshDraw, Vertex};
+
+const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+/// What was under the cursor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PickResult {
+    pub instance: InstanceHandle,
+    /// Where the ray through the pixel hit the instance, in world space.
+    pub position: cgmath::Point3<f32>,
+}
+
+/// The instances to pick from, as draws of ranges of `instance_buffer`.
+/// Picked handles are indices into the instance buffer.
+pub struct PickGeometry<'a> {
+    pub instance_buffer: &'a wgpu::Buffer,
+    pub draws: &'a [MeshDraw<'a>],
+}
+
+// A pick that has been drawn and is waiting for its readback.
+struct InFlight {
+    inverse_view_proj: cgmath::Matrix4<f32>,
+    ndc: cgmath::Vector2<f32>,
+    // Set by the map_async callback: Some(true) once the buffer is readable
+    mapped: Arc<Mutex<Option<bool>>>,
+    map_requested: bool,
+}
+
+/// Finds the instance under a pixel by drawing instance ids into an
+/// `R32Uint` target with the same depth test as the scene and reading the
+/// pixel back without stalling the GPU.
+///
+/// `request` a pick, `encode` it into the frame's command encoder, call
+/// `after_submit` once the frame is submitted and `poll` every frame until it
+/// returns the result, usually a frame or two later.
+pub struct Picker {
+    pipeline: DepthVariants<wgpu::RenderPipeline>,
+    // Without back face culling, for double sided materials
+    double_sided_pipeline: DepthVariants<wgpu::RenderPipeline>,
+    id_texture: wgpu::Texture,
+    id_view: wgpu::TextureView,
+    depth_bits_texture: wgpu::Texture,
+    depth_bits_view: wgpu::TextureView,
+    depth_texture: texture::Texture,
+    // The picked id followed by the depth bits
+    readback_buffer: wgpu::Buffer,
+    request: Option<winit::dpi::PhysicalPosition<f64>>,
+    in_flight: Option<InFlight>,
+}
+
+impl Picker {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_entries: &[wgpu::BindGroupLayoutEntry],
+    ) -> anyhow::Result<Self> {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("picking.wgsl"));
+        let layout = PipelineLayout::new(device, "Picking Pipeline Layout", &[(camera_bind_group_layout, camera_entries)]);
+        let target = wgpu::ColorTargetState {
+            format: ID_FORMAT,
+            // Integer targets can't be blended
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        };
+        // Must match the scene pipeline so exactly the visible faces are picked
+        let builder = PipelineBuilder::from_module("Picking Pipeline", &layout, &shader, ID_FORMAT)
+            .vertex_buffers(&[Vertex::desc(), InstanceRaw::desc()])
+            .color_targets(&[target.clone(), target]);
+        let pipeline = DepthVariants::try_new(|depth_compare| builder.clone().depth_compare(depth_compare).build(device))?;
+        let double_sided_pipeline = DepthVariants::try_new(|depth_compare| {
+            builder.clone().cull_mode(None).depth_compare(depth_compare).build(device)
+        })?;
+        let (id_texture, id_view) = create_id_texture(device, config, "picking_id_texture");
+        let (depth_bits_texture, depth_bits_view) = create_id_texture(device, config, "picking_depth_bits_texture");
+        let depth_texture = texture::Texture::create_depth_texture(device, config, "picking_depth_texture");
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Picking Readback Buffer"),
+            size: 8,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Ok(Self {
+            pipeline,
+            double_sided_pipeline,
+            id_texture,
+            id_view,
+            depth_bits_texture,
+            depth_bits_view,
+            depth_texture,
+            readback_buffer,
+            request: None,
+            in_flight: None,
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        (self.id_texture, self.id_view) = create_id_texture(device, config, "picking_id_texture");
+        (self.depth_bits_texture, self.depth_bits_view) =
+            create_id_texture(device, config, "picking_depth_bits_texture");
+        self.depth_texture = texture::Texture::create_depth_texture(device, config, "picking_depth_texture");
+        // Any queued position may now be off screen
+        self.request = None;
+    }
+
+    /// Picks whatever is under `position` (in pixels) on the next frame.
+    /// Replaces a request that hasn't been drawn yet.
+    pub fn request(&mut self, position: winit::dpi::PhysicalPosition<f64>) {
+        self.request = Some(position);
+    }
+
+    /// Draws the requested pick into `encoder`, using the topmost view under
+    /// the cursor. Does nothing without a request, or while the previous
+    /// pick is still being read back.
+    pub fn encode(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        views: &[View],
+        size: winit::dpi::PhysicalSize<u32>,
+        geometry: PickGeometry,
+    ) {
+        if self.in_flight.is_some() {
+            return;
+        }
+        let Some(position) = self.request.take() else {
+            return;
+        };
+        let Some(view) = views.iter().rev().find(|view| view.viewport.contains(size, position)) else {
+            return;
+        };
+        let (x, y, width, height) = view.viewport.to_physical(size);
+        let (pixel_x, pixel_y) = (position.x as u32, position.y as u32);
+        let depth_clear_value = view.camera.projection.depth_clear_value();
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Picking Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &self.id_view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &self.depth_bits_view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color {
+                                r: depth_clear_value.to_bits() as f64,
+                                g: 0.0,
+                                b: 0.0,
+                                a: 0.0,
+                            }),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(depth_clear_value),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+            // Only the pixel under the cursor matters
+            render_pass.set_scissor_rect(pixel_x, pixel_y, 1, 1);
+            let reverse_z = view.camera.projection.is_reverse_z();
+            render_pass.set_bind_group(0, view.camera_bind_group(), &[]);
+            render_pass.set_vertex_buffer(1, geometry.instance_buffer.slice(..));
+            for draw in geometry.draws {
+                let pipeline = if draw.double_sided { &self.double_sided_pipeline } else { &self.pipeline };
+                render_pass.set_pipeline(pipeline.get(reverse_z));
+                draw.draw(&mut render_pass);
+            }
+        }
+        for (texture, offset) in [(&self.id_texture, 0), (&self.depth_bits_texture, 4)] {
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: pixel_x, y: pixel_y, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &self.readback_buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset,
+                        bytes_per_row: None,
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+            );
+        }
+
+        self.in_flight = Some(InFlight {
+            inverse_view_proj: view.camera.build_view_projection_matrix().invert().unwrap_or(cgmath::Matrix4::identity()),
+            ndc: view.viewport.pixel_to_ndc(size, position),
+            mapped: Arc::new(Mutex::new(None)),
+            map_requested: false,
+        });
+    }
+
+    /// Starts reading back a pick encoded this frame. Buffers can only be
+    /// mapped once the commands writing them have been submitted.
+    pub fn after_submit(&mut self) {
+        let Some(in_flight) = self.in_flight.as_mut() else {
+            return;
+        };
+        if in_flight.map_requested {
+            return;
+        }
+        in_flight.map_requested = true;
+        let mapped = in_flight.mapped.clone();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            *mapped.lock().unwrap() = Some(result.is_ok());
+        });
+    }
+
+    /// Returns the finished pick, if any: `Some(None)` when nothing was under
+    /// the cursor.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Option<PickResult>> {
+        let in_flight = self.in_flight.as_ref()?;
+        if !in_flight.map_requested {
+            return None;
+        }
+        // Drives map_async on native, the browser does this on its own
+        device.poll(wgpu::Maintain::Poll);
+        let mapped = (*in_flight.mapped.lock().unwrap())?;
+        let in_flight = self.in_flight.take()?;
+        if !mapped {
+            log::error!("Couldn't read back the picking buffer");
+            return Some(None);
+        }
+        let (id, depth) = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let words: &[u32] = bytemuck::cast_slice(&data);
+            (words[0], f32::from_bits(words[1]))
+        };
+        self.readback_buffer.unmap();
+        if id == 0 {
+            return Some(None);
+        }
+        let clip = in_flight.inverse_view_proj * cgmath::Vector4::new(in_flight.ndc.x, in_flight.ndc.y, depth, 1.0);
+        Some(Some(PickResult {
+            instance: InstanceHandle(id as usize - 1),
+            position: cgmath::Point3::from_homogeneous(clip),
+        }))
+    }
+}
+
+fn create_id_texture(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    label: &str,
+) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: ID_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    (texture, view)
+}
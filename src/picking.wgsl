@@ -1,3 +1,5 @@
+// Renders which instance covers each pixel, for mouse picking.
+
 struct InstanceInput {
     @location(5) model_matrix_0: vec4<f32>,
     @location(6) model_matrix_1: vec4<f32>,
@@ -8,23 +10,20 @@ struct InstanceInput {
 struct CameraUniform {
     view_proj: mat4x4<f32>,
 };
-@group(1) @binding(0)
+@group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-};
-
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
+    @invariant @builtin(position) clip_position: vec4<f32>,
+    // Instance index + 1, so 0 means nothing was drawn
+    @location(0) @interpolate(flat) id: u32,
 };
 
 @vertex
 fn vs_main(
-    model: VertexInput,
+    @location(0) position: vec3<f32>,
     instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32,
 ) -> VertexOutput {
     let model_matrix = mat4x4<f32>(
         instance.model_matrix_0,
@@ -33,18 +32,22 @@ fn vs_main(
         instance.model_matrix_3,
     );
     var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(position, 1.0);
+    out.id = instance_index + 1u;
     return out;
 }
 
-@group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(0) @binding(1)
-var s_diffuse: sampler;
+struct PickOutput {
+    @location(0) id: u32,
+    // Depth buffers can't be read back everywhere (WebGL2), so the depth
+    // is written out as the bits of an f32 as well.
+    @location(1) depth: u32,
+};
 
-// Fragment shader
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+fn fs_main(in: VertexOutput) -> PickOutput {
+    var out: PickOutput;
+    out.id = in.id;
+    out.depth = bitcast<u32>(in.clip_position.z);
+    return out;
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use crate::assets::Shader;
+use crate::texture;
+
+// wgpu only exposes ids for its objects behind a feature, so shaders and
+// layouts number themselves for the cache to tell them apart.
+pub(crate) fn unique_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A pipeline layout along with the entries of its bind group layouts, so
+/// pipelines made with it can check their shader uses the same bindings.
+pub struct PipelineLayout {
+    pub layout: wgpu::PipelineLayout,
+    /// The entries of each bind group layout, in group order.
+    pub bind_groups: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+    id: u64,
+}
+
+impl PipelineLayout {
+    /// Makes a layout from bind group layouts and the entries they were
+    /// made from, in group order.
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        bind_groups: &[(&wgpu::BindGroupLayout, &[wgpu::BindGroupLayoutEntry])],
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &bind_groups.iter().map(|(layout, _)| *layout).collect::<Vec<_>>(),
+            push_constant_ranges: &[],
+        });
+        Self {
+            layout,
+            bind_groups: bind_groups.iter().map(|(_, entries)| entries.to_vec()).collect(),
+            id: unique_id(),
+        }
+    }
+}
+
+// Everything a render pipeline is made from, which is what the cache is
+// keyed by. Vertex buffer layouts borrow their attributes, so they're
+// copied into `(stride, step mode, attributes)`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    shader: u64,
+    layout: u64,
+    vertex_entry: String,
+    fragment_entry: String,
+    buffers: Vec<(wgpu::BufferAddress, wgpu::VertexStepMode, Vec<wgpu::VertexAttribute>)>,
+    targets: Vec<wgpu::ColorTargetState>,
+    primitive: wgpu::PrimitiveState,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    sample_count: u32,
+}
+
+// What a pipeline is made from: a loaded shader, which it's checked
+// against, or a module made some other way, which it can't be.
+#[derive(Clone, Copy)]
+enum PipelineShader<'a> {
+    Asset(&'a Shader),
+    Module(&'a wgpu::ShaderModule),
+}
+
+/// Describes a render pipeline, starting from what most of ours use:
+///
+/// - `vs_main` and `fs_main` as entry points
+/// - a triangle list with counter clockwise front faces and back faces culled
+/// - one color target, its colors replacing what's in it
+/// - a `texture::Texture::DEPTH_FORMAT` depth buffer, written to and tested
+///   with `Less`
+/// - no multisampling
+///
+/// so only what's different needs saying, e.g. a double-sided transparent
+/// variant is `.cull_mode(None).blend(Some(wgpu::BlendState::ALPHA_BLENDING)).depth_write(false)`.
+/// Build it directly, or get it from a `PipelineCache` to share pipelines
+/// between everything that asks for the same one.
+#[derive(Clone)]
+pub struct PipelineBuilder<'a> {
+    label: &'a str,
+    layout: &'a PipelineLayout,
+    shader: PipelineShader<'a>,
+    buffers: Vec<wgpu::VertexBufferLayout<'a>>,
+    key: PipelineKey,
+}
+
+impl<'a> PipelineBuilder<'a> {
+    pub fn new(
+        label: &'a str,
+        layout: &'a PipelineLayout,
+        shader: &'a Shader,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        Self::with_shader(label, layout, PipelineShader::Asset(shader), shader.id, format)
+    }
+
+    /// Describes a pipeline made from a module that isn't a loaded shader,
+    /// like one built into the binary. There's no reflection to check it
+    /// against, so mismatches are only caught by wgpu when it's built.
+    ///
+    /// The module has no id to tell it apart by, so a `PipelineCache` only
+    /// shares pipelines between this builder and its clones, and
+    /// `retain_shaders` always drops them.
+    pub fn from_module(
+        label: &'a str,
+        layout: &'a PipelineLayout,
+        module: &'a wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        Self::with_shader(label, layout, PipelineShader::Module(module), unique_id(), format)
+    }
+
+    fn with_shader(
+        label: &'a str,
+        layout: &'a PipelineLayout,
+        shader: PipelineShader<'a>,
+        id: u64,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        Self {
+            label,
+            layout,
+            shader,
+            buffers: Vec::new(),
+            key: PipelineKey {
+                shader: id,
+                layout: layout.id,
+                vertex_entry: "vs_main".into(),
+                fragment_entry: "fs_main".into(),
+                buffers: Vec::new(),
+                targets: vec![wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: Some(wgpu::Face::Back),
+                    // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    // Requires Features::DEPTH_CLIP_CONTROL
+                    unclipped_depth: false,
+                    // Requires Features::CONSERVATIVE_RASTERIZATION
+                    conservative: false,
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                sample_count: 1,
+            },
+        }
+    }
+
+    pub fn entry_points(mut self, vertex: &str, fragment: &str) -> Self {
+        self.key.vertex_entry = vertex.into();
+        self.key.fragment_entry = fragment.into();
+        self
+    }
+
+    pub fn vertex_buffers(mut self, buffers: &[wgpu::VertexBufferLayout<'a>]) -> Self {
+        self.key.buffers = buffers
+            .iter()
+            .map(|buffer| (buffer.array_stride, buffer.step_mode, buffer.attributes.to_vec()))
+            .collect();
+        self.buffers = buffers.to_vec();
+        self
+    }
+
+    /// Replaces the color targets, for pipelines drawing to more than one.
+    pub fn color_targets(mut self, targets: &[wgpu::ColorTargetState]) -> Self {
+        self.key.targets = targets.to_vec();
+        self
+    }
+
+    /// How colors are blended with the targets, `None` to overwrite them.
+    pub fn blend(mut self, blend: Option<wgpu::BlendState>) -> Self {
+        for target in self.key.targets.iter_mut() {
+            target.blend = blend;
+        }
+        self
+    }
+
+    /// Which channels of the targets are written to.
+    pub fn write_mask(mut self, write_mask: wgpu::ColorWrites) -> Self {
+        for target in self.key.targets.iter_mut() {
+            target.write_mask = write_mask;
+        }
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.key.primitive.topology = topology;
+        self
+    }
+
+    /// Which faces aren't drawn, `None` for double-sided.
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.key.primitive.cull_mode = cull_mode;
+        self
+    }
+
+    /// Anything but `Fill` needs `wgpu::Features::NON_FILL_POLYGON_MODE`.
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.key.primitive.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn depth_compare(mut self, compare: wgpu::CompareFunction) -> Self {
+        if let Some(depth) = &mut self.key.depth_stencil {
+            depth.depth_compare = compare;
+        }
+        self
+    }
+
+    /// Whether what's drawn hides what's drawn after it. Transparent
+    /// surfaces are usually tested against the depth buffer but don't write
+    /// to it.
+    pub fn depth_write(mut self, write: bool) -> Self {
+        if let Some(depth) = &mut self.key.depth_stencil {
+            depth.depth_write_enabled = write;
+        }
+        self
+    }
+
+    /// Draws without a depth buffer, into passes that don't have one.
+    pub fn no_depth(mut self) -> Self {
+        self.key.depth_stencil = None;
+        self
+    }
+
+    pub fn sample_count(mut self, count: u32) -> Self {
+        self.key.sample_count = count;
+        self
+    }
+
+    /// Makes the pipeline. A loaded shader is checked against the vertex
+    /// buffers and the layout first, so a mismatch is reported in full.
+    /// Whatever else wgpu rejects, like a feature the device doesn't have,
+    /// is returned as an error natively, but only logged on the web, where
+    /// it arrives after this has returned.
+    pub fn build(&self, device: &wgpu::Device) -> Result<wgpu::RenderPipeline> {
+        let key = &self.key;
+        let module = match self.shader {
+            PipelineShader::Asset(shader) => {
+                let problems = || format!("{} doesn't fit {}", shader.path.display(), self.label);
+                let reflection = &shader.reflection;
+                reflection.check_vertex_buffers(&key.vertex_entry, &self.buffers).with_context(problems)?;
+                reflection.check_bind_groups(&self.layout.bind_groups).with_context(problems)?;
+                &shader.module
+            }
+            PipelineShader::Module(module) => module,
+        };
+        let targets = key.targets.iter().cloned().map(Some).collect::<Vec<_>>();
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(self.label),
+            layout: Some(&self.layout.layout),
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: &key.vertex_entry,
+                buffers: &self.buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module,
+                entry_point: &key.fragment_entry,
+                targets: &targets,
+            }),
+            primitive: key.primitive,
+            depth_stencil: key.depth_stencil.clone(),
+            multisample: wgpu::MultisampleState {
+                count: key.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+        let error = device.pop_error_scope();
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let label = self.label.to_string();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Some(error) = error.await {
+                        log::error!("Couldn't make {}: {}", label, error);
+                    }
+                });
+            } else {
+                // Popping error scopes never actually waits natively
+                if let Some(error) = pollster::block_on(error) {
+                    bail!("Couldn't make {}: {}", self.label, error);
+                }
+            }
+        }
+        Ok(pipeline)
+    }
+}
+
+/// Render pipelines made on demand and kept for whoever asks for the same
+/// one next, so variants like wireframe or transparent versions of a
+/// pipeline only cost anything once they're used.
+///
+/// Pipelines are keyed by what they're made from, including which version
+/// of the shader, so a reloaded shader gets new pipelines. `retain_shaders`
+/// drops the ones made from shaders that are gone.
+#[derive(Default)]
+pub struct PipelineCache {
+    pipelines: HashMap<PipelineKey, Arc<wgpu::RenderPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The pipeline `builder` describes, made the first time it's asked for.
+    /// Pipelines that fail to build aren't kept, so asking again retries.
+    pub fn get(&mut self, device: &wgpu::Device, builder: &PipelineBuilder) -> Result<Arc<wgpu::RenderPipeline>> {
+        if let Some(pipeline) = self.pipelines.get(&builder.key) {
+            return Ok(pipeline.clone());
+        }
+        let pipeline = Arc::new(builder.build(device)?);
+        self.pipelines.insert(builder.key.clone(), pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /// Drops the pipelines made from shaders other than `shaders`. Pipelines
+    /// still in use elsewhere live on until they're let go of.
+    pub fn retain_shaders<'s>(&mut self, shaders: impl IntoIterator<Item = &'s Shader>) {
+        let ids = shaders.into_iter().map(|shader| shader.id).collect::<Vec<_>>();
+        self.pipelines.retain(|key, _| ids.contains(&key.shader));
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+}
@@ -0,0 +1,447 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::assets::{load_bytes, normalize_path};
+
+/// The names defined for one variant of a shader, with their values.
+///
+/// `#ifdef NAME` checks whether a name is defined and `{{NAME}}` is
+/// replaced with its value, so the same file can be compiled into several
+/// variants, e.g. with and without skinning.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ShaderDefs {
+    values: BTreeMap<String, String>,
+}
+
+impl ShaderDefs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines `name`, for `#ifdef`.
+    pub fn define(self, name: impl Into<String>) -> Self {
+        self.set(name, "1")
+    }
+
+    /// Defines `name` as `value`, for `{{name}}`.
+    pub fn set(mut self, name: impl Into<String>, value: impl ToString) -> Self {
+        self.values.insert(name.into(), value.to_string());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Tells variants apart, the same for the same definitions in any order.
+    pub fn key(&self) -> String {
+        let mut key = String::new();
+        for (name, value) in self.values.iter() {
+            if !key.is_empty() {
+                key.push(';');
+            }
+            let _ = write!(key, "{}={}", name, value);
+        }
+        key
+    }
+}
+
+/// A shader variant ready to compile, with where each of its lines came
+/// from.
+pub struct Preprocessed {
+    pub source: String,
+    /// The file that was preprocessed followed by every file it included.
+    pub files: Vec<PathBuf>,
+    // An index into `files` and a 1-based line for every line of `source`
+    lines: Vec<(usize, usize)>,
+}
+
+impl Preprocessed {
+    /// The file and line that 1-based `line` of `source` came from.
+    pub fn origin(&self, line: usize) -> Option<(&Path, usize)> {
+        let &(file, number) = self.lines.get(line.checked_sub(1)?)?;
+        Some((&self.files[file], number))
+    }
+
+    /// Describes an error in `source` like a compiler would, pointing each
+    /// labelled byte range back at the file and line it came from.
+    pub fn diagnostic<'a>(&self, message: &str, labels: impl IntoIterator<Item = (Range<usize>, &'a str)>) -> String {
+        let mut text = format!("error: {}", message);
+        for (range, label) in labels {
+            let start = range.start.min(self.source.len());
+            let before = &self.source[..start];
+            let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+            let code = self.source[line_start..].lines().next().unwrap_or_default();
+            let column = start - line_start;
+            let length = range.end.saturating_sub(start).clamp(1, code.len().saturating_sub(column).max(1));
+            let _ = match self.origin(before.matches('\n').count() + 1) {
+                Some((file, number)) => write!(text, "\n  --> {}:{}:{}", file.display(), number, column + 1),
+                None => write!(text, "\n  --> end of {}", self.files[0].display()),
+            };
+            let _ = write!(
+                text,
+                "\n   |\n   | {}\n   | {}{} {}",
+                code,
+                " ".repeat(column),
+                "^".repeat(length),
+                label
+            );
+        }
+        text
+    }
+}
+
+enum Directive<'a> {
+    Include(&'a str),
+    Define(&'a str, &'a str),
+    Ifdef(&'a str),
+    Ifndef(&'a str),
+    Else,
+    Endif,
+}
+
+// None for lines that aren't directives. WGSL never uses `#`, so any line
+// starting with one is meant as a directive.
+fn parse_directive<'a>(line: &'a str) -> Option<std::result::Result<Directive<'a>, String>> {
+    let line = line.trim().strip_prefix('#')?;
+    let line = strip_comment(line).trim();
+    let (directive, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let name = |rest: &'a str| -> std::result::Result<&'a str, String> {
+        if is_identifier(rest) {
+            Ok(rest)
+        } else {
+            Err(format!("#{} needs a name, not \"{}\"", directive, rest))
+        }
+    };
+    Some(match directive {
+        "include" => rest
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+            .map(Directive::Include)
+            .ok_or_else(|| format!("#include needs a quoted path, not {}", rest)),
+        "define" => {
+            let (defined, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, "1"));
+            name(defined).map(|defined| Directive::Define(defined, value.trim()))
+        }
+        "ifdef" => name(rest).map(Directive::Ifdef),
+        "ifndef" => name(rest).map(Directive::Ifndef),
+        "else" if rest.is_empty() => Ok(Directive::Else),
+        "endif" if rest.is_empty() => Ok(Directive::Endif),
+        _ => Err(format!("Unknown directive #{}", line)),
+    })
+}
+
+// Cuts off a trailing `//` comment, leaving any `//` in quotes, like in an
+// include path, alone.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '/' if !quoted && line[index..].starts_with("//") => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Includes are relative to the including file, and normalized so a file is
+// only included once however it's reached.
+fn resolve(from: &Path, include: &str) -> PathBuf {
+    normalize_path(&from.parent().unwrap_or(Path::new("")).join(include))
+}
+
+/// WGSL files and the files they include, preprocessed into variants.
+///
+/// Files can use these directives, each on a line of its own:
+///
+/// - `#include "path"` pastes in another file, relative to this one. Every
+///   file is included at most once per variant, so files can include what
+///   they use without worrying about including it twice.
+/// - `#define NAME` or `#define NAME value` defines a name for the rest of
+///   the variant, unless the `ShaderDefs` it's compiled with define it
+///   already. Files can define defaults that way.
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` keep or drop the
+///   lines between them, and nest.
+///
+/// Anywhere else `{{NAME}}` is replaced with the value of `NAME`, e.g.
+/// `const MAX_LIGHTS: u32 = {{MAX_LIGHTS}}u;`.
+#[derive(Default)]
+pub struct ShaderLibrary {
+    files: HashMap<PathBuf, String>,
+}
+
+impl ShaderLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file from memory.
+    pub fn add(&mut self, path: impl Into<PathBuf>, source: impl Into<String>) {
+        self.files.insert(path.into(), source.into());
+    }
+
+    /// Loads `path` and everything it includes, skipping files that are
+    /// already loaded.
+    pub async fn load(&mut self, path: &Path) -> Result<()> {
+        let mut pending: Vec<(PathBuf, Option<(PathBuf, usize)>)> = vec![(path.to_path_buf(), None)];
+        while let Some((path, included_from)) = pending.pop() {
+            if self.files.contains_key(&path) {
+                continue;
+            }
+            let source = load_bytes(&path).await.and_then(|bytes| {
+                String::from_utf8(bytes).with_context(|| format!("{} isn't UTF-8", path.display()))
+            });
+            let source = match (source, included_from) {
+                (Ok(source), _) => source,
+                (Err(e), Some((from, line))) => {
+                    return Err(e.context(format!("{}:{}: Couldn't include {}", from.display(), line, path.display())))
+                }
+                (Err(e), None) => return Err(e),
+            };
+            // Includes in branches that end up dropped are loaded too, so
+            // any variant can be made from what's loaded
+            for (number, line) in source.lines().enumerate() {
+                if let Some(Ok(Directive::Include(include))) = parse_directive(line) {
+                    pending.push((resolve(&path, include), Some((path.clone(), number + 1))));
+                }
+            }
+            self.files.insert(path, source);
+        }
+        Ok(())
+    }
+
+    /// Drops a file so the next `load` reads it again.
+    pub fn forget(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// Makes the variant of `path` for `defs` from the loaded files. Errors
+    /// say which file and line they're at.
+    pub fn preprocess(&self, path: &Path, defs: &ShaderDefs) -> Result<Preprocessed> {
+        let mut expansion = Expansion {
+            library: self,
+            defs: defs.values.clone(),
+            fixed: defs,
+            out: Preprocessed {
+                source: String::new(),
+                files: Vec::new(),
+                lines: Vec::new(),
+            },
+        };
+        expansion.expand(path)?;
+        Ok(expansion.out)
+    }
+}
+
+struct Expansion<'a> {
+    library: &'a ShaderLibrary,
+    defs: BTreeMap<String, String>,
+    // What the caller defined, which `#define` leaves alone
+    fixed: &'a ShaderDefs,
+    out: Preprocessed,
+}
+
+// An `#ifdef` or `#ifndef` being expanded.
+struct Condition {
+    active: bool,
+    seen_else: bool,
+    line: usize,
+}
+
+impl Expansion<'_> {
+    fn expand(&mut self, path: &Path) -> Result<()> {
+        if self.out.files.iter().any(|file| file == path) {
+            return Ok(());
+        }
+        let source = self
+            .library
+            .files
+            .get(path)
+            .with_context(|| format!("{} isn't loaded", path.display()))?;
+        let file = self.out.files.len();
+        self.out.files.push(path.to_path_buf());
+        let mut conditions: Vec<Condition> = Vec::new();
+        for (number, line) in source.lines().enumerate() {
+            let number = number + 1;
+            let at = || format!("{}:{}", path.display(), number);
+            let active = conditions.iter().all(|condition| condition.active);
+            let directive = match parse_directive(line) {
+                None => {
+                    if active {
+                        let line = self.substitute(line).map_err(|e| anyhow::anyhow!("{}: {}", at(), e))?;
+                        self.out.source.push_str(&line);
+                        self.out.source.push('\n');
+                        self.out.lines.push((file, number));
+                    }
+                    continue;
+                }
+                Some(Err(e)) => bail!("{}: {}", at(), e),
+                Some(Ok(directive)) => directive,
+            };
+            match directive {
+                Directive::Ifdef(name) | Directive::Ifndef(name) => {
+                    let defined = self.defs.contains_key(name);
+                    conditions.push(Condition {
+                        active: defined == matches!(directive, Directive::Ifdef(_)),
+                        seen_else: false,
+                        line: number,
+                    });
+                }
+                Directive::Else => {
+                    let Some(condition) = conditions.last_mut() else {
+                        bail!("{}: #else without #ifdef", at());
+                    };
+                    if condition.seen_else {
+                        bail!("{}: Second #else for the #ifdef on line {}", at(), condition.line);
+                    }
+                    condition.active = !condition.active;
+                    condition.seen_else = true;
+                }
+                Directive::Endif => {
+                    if conditions.pop().is_none() {
+                        bail!("{}: #endif without #ifdef", at());
+                    }
+                }
+                Directive::Define(name, value) if active => {
+                    if !self.fixed.values.contains_key(name) {
+                        self.defs.insert(name.to_string(), value.to_string());
+                    }
+                }
+                Directive::Include(include) if active => {
+                    self.expand(&resolve(path, include)).with_context(|| format!("{}: Included here", at()))?;
+                }
+                Directive::Define(..) | Directive::Include(_) => {}
+            }
+        }
+        if let Some(condition) = conditions.last() {
+            bail!("{}:{}: #ifdef without #endif", path.display(), condition.line);
+        }
+        Ok(())
+    }
+
+    // Replaces every `{{NAME}}` in `line`.
+    fn substitute(&self, line: &str) -> std::result::Result<String, String> {
+        let mut out = String::new();
+        let mut rest = line;
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else {
+                break;
+            };
+            let name = rest[start + 2..start + end].trim();
+            let value = self.defs.get(name).ok_or_else(|| format!("{{{{{}}}}} isn't defined", name))?;
+            out.push_str(&rest[..start]);
+            out.push_str(value);
+            rest = &rest[start + end + 2..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library(files: &[(&str, &str)]) -> ShaderLibrary {
+        let mut library = ShaderLibrary::new();
+        for (path, source) in files {
+            library.add(*path, *source);
+        }
+        library
+    }
+
+    fn preprocess(files: &[(&str, &str)], defs: &ShaderDefs) -> Result<String> {
+        library(files).preprocess(Path::new(files[0].0), defs).map(|out| out.source)
+    }
+
+    #[test]
+    fn includes_are_pasted_in_once() {
+        let files = [
+            ("shaders/main.wgsl", "#include \"common.wgsl\"\n#include \"lib/../common.wgsl\"\nmain\n"),
+            ("shaders/common.wgsl", "common\n"),
+        ];
+        let out = library(&files).preprocess(Path::new("shaders/main.wgsl"), &ShaderDefs::new()).unwrap();
+        assert_eq!(out.source, "common\nmain\n");
+        assert_eq!(out.origin(1), Some((Path::new("shaders/common.wgsl"), 1)));
+        assert_eq!(out.origin(2), Some((Path::new("shaders/main.wgsl"), 3)));
+    }
+
+    #[test]
+    fn include_paths_can_contain_slashes() {
+        let files = [
+            ("main.wgsl", "#include \"lib//common.wgsl\" // shared code\nmain\n"),
+            ("lib/common.wgsl", "common\n"),
+        ];
+        assert_eq!(preprocess(&files, &ShaderDefs::new()).unwrap(), "common\nmain\n");
+    }
+
+    #[test]
+    fn include_cycles_stop() {
+        let files = [
+            ("a.wgsl", "#include \"b.wgsl\"\na\n"),
+            ("b.wgsl", "#include \"a.wgsl\"\nb\n"),
+        ];
+        assert_eq!(preprocess(&files, &ShaderDefs::new()).unwrap(), "b\na\n");
+    }
+
+    #[test]
+    fn missing_includes_say_where_they_were_included() {
+        let files = [("main.wgsl", "\n#include \"missing.wgsl\"\n")];
+        let message = format!("{:#}", preprocess(&files, &ShaderDefs::new()).unwrap_err());
+        assert!(message.contains("main.wgsl:2: Included here"), "{}", message);
+        assert!(message.contains("missing.wgsl isn't loaded"), "{}", message);
+    }
+
+    #[test]
+    fn conditions_nest() {
+        let source = "\
+#ifdef A
+a
+#ifndef B
+not b
+#else
+b
+#endif
+#else
+not a
+#endif
+";
+        let files = [("main.wgsl", source)];
+        let variant = |defs: ShaderDefs| preprocess(&files, &defs).unwrap();
+        assert_eq!(variant(ShaderDefs::new()), "not a\n");
+        assert_eq!(variant(ShaderDefs::new().define("A")), "a\nnot b\n");
+        assert_eq!(variant(ShaderDefs::new().define("A").define("B")), "a\nb\n");
+        // B on its own is only looked at inside A
+        assert_eq!(variant(ShaderDefs::new().define("B")), "not a\n");
+    }
+
+    #[test]
+    fn unbalanced_conditions_are_errors() {
+        for source in ["#ifdef A\n", "#endif\n", "#else\n", "#ifdef A\n#else\n#else\n#endif\n"] {
+            assert!(preprocess(&[("main.wgsl", source)], &ShaderDefs::new()).is_err(), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn defines_are_defaults() {
+        let source = "#define COUNT 4 // lights\n#define FLAG\n#ifdef FLAG\nlet count = {{COUNT}}u;\n#endif\n";
+        let files = [("main.wgsl", source)];
+        assert_eq!(preprocess(&files, &ShaderDefs::new()).unwrap(), "let count = 4u;\n");
+        // What the variant is made with wins
+        assert_eq!(preprocess(&files, &ShaderDefs::new().set("COUNT", 8)).unwrap(), "let count = 8u;\n");
+        // Defines in dropped branches don't count
+        let files = [("main.wgsl", "#ifdef NEVER\n#define X 1\n#endif\n{{X}}\n")];
+        assert!(preprocess(&files, &ShaderDefs::new()).is_err());
+    }
+}
@@ -0,0 +1,613 @@
+use cgmath::prelude::*;
+
+use crate::camera::Camera;
+use crate::viewport::Viewport;
+use crate::{Instance, InstanceHandle};
+
+type Point = cgmath::Point3<f32>;
+type Vector = cgmath::Vector3<f32>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Point,
+    /// Normalized, so distances along the ray are in world units.
+    pub direction: Vector,
+}
+
+impl Ray {
+    pub fn new(origin: Point, direction: Vector) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    /// The ray through a point in normalized device coordinates (-1..1, y
+    /// up), starting on the camera's near plane.
+    pub fn from_ndc(camera: &Camera, ndc: cgmath::Vector2<f32>) -> Self {
+        let inverse = camera
+            .build_view_projection_matrix()
+            .invert()
+            .unwrap_or(cgmath::Matrix4::identity());
+        let unproject = |depth: f32| Point::from_homogeneous(inverse * cgmath::Vector4::new(ndc.x, ndc.y, depth, 1.0));
+        // Reverse-Z puts the far plane at infinity, so aim at a point halfway
+        // through the depth range rather than at the far plane.
+        let near_depth = 1.0 - camera.projection.depth_clear_value();
+        let near = unproject(near_depth);
+        let middle = unproject(0.5);
+        Self::new(near, middle - near)
+    }
+
+    /// The ray through the pixel at `position` of a view drawing into
+    /// `viewport` of a target of `size`.
+    pub fn from_pixel(
+        camera: &Camera,
+        viewport: Viewport,
+        size: winit::dpi::PhysicalSize<u32>,
+        position: winit::dpi::PhysicalPosition<f64>,
+    ) -> Self {
+        Self::from_ndc(camera, viewport.pixel_to_ndc(size, position))
+    }
+
+    pub fn at(&self, distance: f32) -> Point {
+        self.origin + self.direction * distance
+    }
+
+    /// Distance along the ray to where it crosses the plane through `point`
+    /// with `normal`, None if it runs parallel or the plane is behind it.
+    pub fn intersect_plane(&self, point: Point, normal: Vector) -> Option<f32> {
+        let denominator = normal.dot(self.direction);
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+        let distance = normal.dot(point - self.origin) / denominator;
+        (distance >= 0.0).then_some(distance)
+    }
+
+    /// The closest approach between the ray and the infinite line through
+    /// `point` along the normalized `direction`, as (distance along the ray,
+    /// distance along the line). None if they're parallel.
+    pub fn closest_to_line(&self, point: Point, direction: Vector) -> Option<(f32, f32)> {
+        let offset = self.origin - point;
+        let b = self.direction.dot(direction);
+        let denominator = 1.0 - b * b;
+        if denominator < 1e-6 {
+            return None;
+        }
+        let d = self.direction.dot(offset);
+        let e = direction.dot(offset);
+        Some(((b * e - d) / denominator, (e - b * d) / denominator))
+    }
+
+    /// The same ray in the space `matrix` maps into. Distances along the
+    /// result are no longer in world units if the matrix scales.
+    pub fn transform(&self, matrix: &cgmath::Matrix4<f32>) -> Self {
+        Self {
+            origin: matrix.transform_point(self.origin),
+            direction: matrix.transform_vector(self.direction),
+        }
+    }
+}
+
+/// Axis aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub const EMPTY: Aabb = Aabb {
+        min: Point::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+        max: Point::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+    };
+
+    pub fn from_points(points: impl IntoIterator<Item = Point>) -> Self {
+        points.into_iter().fold(Self::EMPTY, |aabb, point| aabb.grow(point))
+    }
+
+    pub fn grow(&self, point: Point) -> Self {
+        Self {
+            min: Point::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z)),
+            max: Point::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z)),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        self.grow(other.min).grow(other.max)
+    }
+
+    pub fn center(&self) -> Point {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn corners(&self) -> [Point; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point::new(a.x, a.y, a.z),
+            Point::new(b.x, a.y, a.z),
+            Point::new(a.x, b.y, a.z),
+            Point::new(b.x, b.y, a.z),
+            Point::new(a.x, a.y, b.z),
+            Point::new(b.x, a.y, b.z),
+            Point::new(a.x, b.y, b.z),
+            Point::new(b.x, b.y, b.z),
+        ]
+    }
+
+    /// The box around this one after transforming it by `matrix`.
+    pub fn transform(&self, matrix: &cgmath::Matrix4<f32>) -> Self {
+        Self::from_points(self.corners().map(|corner| matrix.transform_point(corner)))
+    }
+
+    /// Distance along `ray` to where it enters the box (0 if it starts
+    /// inside), if it hits the box before `max_distance`.
+    pub fn intersect(&self, ray: &Ray, max_distance: f32) -> Option<f32> {
+        let mut near = 0.0_f32;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            // Dividing by a zero direction gives infinities, which the
+            // comparisons below handle as "parallel to this slab".
+            let inverse = 1.0 / ray.direction[axis];
+            let t0 = (self.min[axis] - ray.origin[axis]) * inverse;
+            let t1 = (self.max[axis] - ray.origin[axis]) * inverse;
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+}
+
+/// Where a ray hit a mesh, in the mesh's own space.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MeshHit {
+    /// Distance along the ray, in units of the ray's direction.
+    pub distance: f32,
+    pub position: Point,
+    /// Index of the triangle, i.e. into the index buffer divided by 3.
+    pub triangle: usize,
+    /// Weights of the triangle's three vertices at the hit point.
+    pub barycentrics: [f32; 3],
+    /// Texture coordinates interpolated at the hit point.
+    pub tex_coords: [f32; 2],
+}
+
+#[derive(Clone, Debug)]
+struct BvhNode {
+    bounds: Aabb,
+    // Leaves cover triangles[start..start + count], inner nodes have count 0
+    // and their children at `start` and `start + 1`.
+    start: usize,
+    count: usize,
+}
+
+// Triangles per leaf; splitting further costs more in box tests than it saves.
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+/// A CPU copy of a triangle mesh with a bounding volume hierarchy for fast
+/// ray casts. Triangles are hit from both sides.
+#[derive(Clone, Debug)]
+pub struct RaycastMesh {
+    positions: Vec<Point>,
+    tex_coords: Vec<[f32; 2]>,
+    // Vertex indices of each triangle, reordered while building the BVH
+    triangles: Vec<[u32; 3]>,
+    // Position of each triangle in the original index buffer
+    triangle_ids: Vec<usize>,
+    nodes: Vec<BvhNode>,
+}
+
+impl RaycastMesh {
+    /// `indices` is a triangle list, like the index buffers drawn with
+    /// `PrimitiveTopology::TriangleList`.
+    pub fn new(positions: &[[f32; 3]], tex_coords: &[[f32; 2]], indices: &[u32]) -> Self {
+        let mut mesh = Self {
+            positions: positions.iter().map(|&position| position.into()).collect(),
+            tex_coords: tex_coords.to_vec(),
+            triangles: indices.chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]).collect(),
+            triangle_ids: (0..indices.len() / 3).collect(),
+            nodes: Vec::new(),
+        };
+        if !mesh.triangles.is_empty() {
+            mesh.nodes.push(BvhNode {
+                bounds: Aabb::EMPTY,
+                start: 0,
+                count: mesh.triangles.len(),
+            });
+            mesh.build(0);
+        }
+        mesh
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map(|root| root.bounds).unwrap_or(Aabb::EMPTY)
+    }
+
+    fn triangle_bounds(&self, triangle: usize) -> Aabb {
+        Aabb::from_points(self.triangles[triangle].map(|index| self.positions[index as usize]))
+    }
+
+    fn build(&mut self, node: usize) {
+        let (start, count) = (self.nodes[node].start, self.nodes[node].count);
+        let bounds = (start..start + count).fold(Aabb::EMPTY, |bounds, triangle| {
+            bounds.union(&self.triangle_bounds(triangle))
+        });
+        self.nodes[node].bounds = bounds;
+        if count <= MAX_LEAF_TRIANGLES {
+            return;
+        }
+
+        // Split at the median centroid along the longest axis
+        let extent = bounds.max - bounds.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let mut order = (start..start + count)
+            .map(|triangle| (self.triangle_bounds(triangle).center()[axis], triangle))
+            .collect::<Vec<_>>();
+        order.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let triangles = order.iter().map(|&(_, triangle)| self.triangles[triangle]).collect::<Vec<_>>();
+        let ids = order.iter().map(|&(_, triangle)| self.triangle_ids[triangle]).collect::<Vec<_>>();
+        self.triangles[start..start + count].copy_from_slice(&triangles);
+        self.triangle_ids[start..start + count].copy_from_slice(&ids);
+
+        let half = count / 2;
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounds: Aabb::EMPTY,
+            start,
+            count: half,
+        });
+        self.nodes.push(BvhNode {
+            bounds: Aabb::EMPTY,
+            start: start + half,
+            count: count - half,
+        });
+        self.nodes[node].start = left;
+        self.nodes[node].count = 0;
+        self.build(left);
+        self.build(left + 1);
+    }
+
+    /// The closest hit along `ray`, in the mesh's space.
+    pub fn raycast(&self, ray: &Ray) -> Option<MeshHit> {
+        let mut closest: Option<MeshHit> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let max_distance = closest.map(|hit| hit.distance).unwrap_or(f32::INFINITY);
+            if node.bounds.intersect(ray, max_distance).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(node.start + 1);
+                continue;
+            }
+            for triangle in node.start..node.start + node.count {
+                if let Some(hit) = self.intersect_triangle(ray, triangle) {
+                    if hit.distance < max_distance && closest.is_none_or(|closest| hit.distance < closest.distance) {
+                        closest = Some(hit);
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    // Möller–Trumbore
+    fn intersect_triangle(&self, ray: &Ray, triangle: usize) -> Option<MeshHit> {
+        const EPSILON: f32 = 1e-7;
+        let indices = self.triangles[triangle].map(|index| index as usize);
+        let [a, b, c] = indices.map(|index| self.positions[index]);
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = ray.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < EPSILON {
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let s = ray.origin - a;
+        let u = s.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = ray.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = edge2.dot(q) * inverse;
+        if distance < 0.0 {
+            return None;
+        }
+        let barycentrics = [1.0 - u - v, u, v];
+        let mut tex_coords = [0.0; 2];
+        for (index, weight) in indices.iter().zip(barycentrics) {
+            let uv = self.tex_coords.get(*index).copied().unwrap_or_default();
+            tex_coords[0] += uv[0] * weight;
+            tex_coords[1] += uv[1] * weight;
+        }
+        Some(MeshHit {
+            distance,
+            position: ray.at(distance),
+            triangle: self.triangle_ids[triangle],
+            barycentrics,
+            tex_coords,
+        })
+    }
+}
+
+/// Where a ray hit one of many instances of a mesh.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InstanceHit {
+    pub instance: InstanceHandle,
+    /// Distance along the ray in world units.
+    pub distance: f32,
+    /// The hit point in world space.
+    pub position: Point,
+    /// The hit in the mesh's own space.
+    pub mesh_hit: MeshHit,
+}
+
+/// Casts `ray` (in world space) against every instance of `mesh` and returns
+/// the closest hit. Instances whose bounding box the ray misses, or enters
+/// beyond the closest hit so far, are skipped without testing triangles.
+pub fn raycast_instances(ray: &Ray, mesh: &RaycastMesh, instances: &[Instance]) -> Option<InstanceHit> {
+    let mut closest: Option<InstanceHit> = None;
+    for (index, instance) in instances.iter().enumerate() {
+        let model = instance.model_matrix();
+        let max_distance = closest.map(|hit| hit.distance).unwrap_or(f32::INFINITY);
+        if mesh.bounds().transform(&model).intersect(ray, max_distance).is_none() {
+            continue;
+        }
+        let Some(inverse) = model.invert() else {
+            continue;
+        };
+        let Some(mesh_hit) = mesh.raycast(&ray.transform(&inverse)) else {
+            continue;
+        };
+        let position = model.transform_point(mesh_hit.position);
+        let distance = (position - ray.origin).dot(ray.direction);
+        if distance < max_distance {
+            closest = Some(InstanceHit {
+                instance: InstanceHandle(index),
+                distance,
+                position,
+                mesh_hit,
+            });
+        }
+    }
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> RaycastMesh {
+        RaycastMesh::new(
+            &[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            &[[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]],
+            &[0, 1, 2],
+        )
+    }
+
+    // A bumpy 16x16 grid of quads, enough triangles for a few BVH levels
+    fn grid() -> RaycastMesh {
+        const SIZE: u32 = 16;
+        let mut positions = Vec::new();
+        for y in 0..=SIZE {
+            for x in 0..=SIZE {
+                let height = ((x * 7 + y * 13) % 5) as f32 * 0.1;
+                positions.push([x as f32, y as f32, height]);
+            }
+        }
+        let mut indices = Vec::new();
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let corner = y * (SIZE + 1) + x;
+                indices.extend([corner, corner + 1, corner + SIZE + 2, corner, corner + SIZE + 2, corner + SIZE + 1]);
+            }
+        }
+        RaycastMesh::new(&positions, &[], &indices)
+    }
+
+    #[test]
+    fn ray_hits_triangle() {
+        let ray = Ray::new(Point::new(0.25, 0.25, 2.0), Vector::new(0.0, 0.0, -1.0));
+        let hit = triangle().raycast(&ray).expect("ray should hit the triangle");
+        assert!((hit.distance - 2.0).abs() < 1e-5);
+        assert!((hit.position - Point::new(0.25, 0.25, 0.0)).magnitude() < 1e-5);
+        assert_eq!(hit.triangle, 0);
+        for (barycentric, expected) in hit.barycentrics.iter().zip([0.5, 0.25, 0.25]) {
+            assert!((barycentric - expected).abs() < 1e-5);
+        }
+        assert!((hit.tex_coords[0] - 0.25).abs() < 1e-5 && (hit.tex_coords[1] - 0.25).abs() < 1e-5);
+
+        // From behind too
+        let ray = Ray::new(Point::new(0.25, 0.25, -1.0), Vector::new(0.0, 0.0, 1.0));
+        assert!(triangle().raycast(&ray).is_some());
+    }
+
+    #[test]
+    fn ray_misses_triangle() {
+        let beside = Ray::new(Point::new(0.75, 0.75, 2.0), Vector::new(0.0, 0.0, -1.0));
+        assert_eq!(triangle().raycast(&beside), None);
+        let away = Ray::new(Point::new(0.25, 0.25, 2.0), Vector::new(0.0, 0.0, 1.0));
+        assert_eq!(triangle().raycast(&away), None);
+    }
+
+    #[test]
+    fn parallel_ray_misses_triangle() {
+        let ray = Ray::new(Point::new(-1.0, 0.25, 0.0), Vector::new(1.0, 0.0, 0.0));
+        assert_eq!(triangle().raycast(&ray), None);
+    }
+
+    #[test]
+    fn bvh_agrees_with_brute_force() {
+        let mesh = grid();
+        assert!(mesh.nodes.len() > 1, "the grid should need more than one BVH node");
+        for i in 0..200 {
+            // Spread the rays over the grid and a bit past its edges
+            let t = i as f32;
+            let origin = Point::new((t * 0.37) % 18.0 - 1.0, (t * 0.61) % 18.0 - 1.0, 3.0);
+            let direction = Vector::new((t * 0.13).sin() * 0.5, (t * 0.29).cos() * 0.5, -1.0);
+            let ray = Ray::new(origin, direction);
+            let brute_force = (0..mesh.triangles.len())
+                .filter_map(|triangle| mesh.intersect_triangle(&ray, triangle))
+                .min_by(|a, b| a.distance.total_cmp(&b.distance));
+            let hit = mesh.raycast(&ray);
+            assert_eq!(hit.map(|hit| hit.triangle), brute_force.map(|hit| hit.triangle), "ray {:?}", ray);
+            if let (Some(hit), Some(brute_force)) = (hit, brute_force) {
+                assert!((hit.distance - brute_force.distance).abs() < 1e-5);
+            }
+        }
+    }
+
+    fn camera(projection: crate::camera::Projection) -> Camera {
+        Camera {
+            eye: Point::new(0.0, 0.0, 5.0),
+            target: Point::new(0.0, 0.0, 0.0),
+            up: Vector::unit_y(),
+            aspect: 2.0,
+            projection,
+            znear: 0.5,
+            zfar: 100.0,
+        }
+    }
+
+    fn assert_near(a: Vector, b: Vector) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_point_near(a: Point, b: Point) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn the_centre_pixel_looks_straight_ahead() {
+        use crate::camera::Projection;
+        // Odd sizes put a pixel centre exactly in the middle
+        let size = winit::dpi::PhysicalSize::new(801, 401);
+        let centre = winit::dpi::PhysicalPosition::new(400.5, 200.5);
+        for projection in [
+            Projection::Perspective { fovy: cgmath::Deg(60.0) },
+            Projection::ReverseZ { fovy: cgmath::Deg(60.0) },
+            Projection::Orthographic { height: 4.0 },
+            Projection::OffAxis { left: -0.5, right: 0.5, bottom: -0.25, top: 0.25 },
+        ] {
+            let ray = Ray::from_pixel(&camera(projection), Viewport::FULL, size, centre);
+            assert_near(ray.direction, -Vector::unit_z());
+            // Starting on the near plane
+            assert_point_near(ray.origin, Point::new(0.0, 0.0, 4.5));
+        }
+
+        // In the middle of the right half of a split screen
+        let camera = camera(Projection::Perspective { fovy: cgmath::Deg(60.0) });
+        let right = Viewport::new(0.5, 0.0, 0.5, 1.0);
+        let size = winit::dpi::PhysicalSize::new(802, 401);
+        let ray = Ray::from_pixel(&camera, right, size, winit::dpi::PhysicalPosition::new(601.0, 200.0));
+        assert_near(ray.direction, -Vector::unit_z());
+    }
+
+    #[test]
+    fn perspective_rays_spread_with_the_field_of_view() {
+        use crate::camera::Projection;
+        for projection in [Projection::Perspective { fovy: cgmath::Deg(90.0) }, Projection::ReverseZ { fovy: cgmath::Deg(90.0) }] {
+            let camera = camera(projection);
+            // 45 degrees up at the top edge, and twice as far across at the
+            // right edge for an aspect of 2
+            let top = Ray::from_ndc(&camera, cgmath::Vector2::new(0.0, 1.0));
+            assert_near(top.direction, Vector::new(0.0, 1.0, -1.0).normalize());
+            assert_point_near(top.origin, Point::new(0.0, 0.5, 4.5));
+            let right = Ray::from_ndc(&camera, cgmath::Vector2::new(1.0, 0.0));
+            assert_near(right.direction, Vector::new(2.0, 0.0, -1.0).normalize());
+        }
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let camera = camera(crate::camera::Projection::Orthographic { height: 4.0 });
+        let corner = Ray::from_ndc(&camera, cgmath::Vector2::new(1.0, -1.0));
+        assert_near(corner.direction, -Vector::unit_z());
+        // Half the height down and half the width across
+        assert_point_near(corner.origin, Point::new(4.0, -2.0, 4.5));
+        let other = Ray::from_ndc(&camera, cgmath::Vector2::new(-0.5, 0.5));
+        assert_near(other.direction, -Vector::unit_z());
+        assert_point_near(other.origin, Point::new(-2.0, 1.0, 4.5));
+    }
+
+    #[test]
+    fn off_axis_rays_go_through_the_near_plane_corners() {
+        let camera = camera(crate::camera::Projection::OffAxis { left: -0.1, right: 0.3, bottom: -0.2, top: 0.1 });
+        let ray = Ray::from_ndc(&camera, cgmath::Vector2::new(-1.0, 1.0));
+        assert_point_near(ray.origin, Point::new(-0.1, 0.1, 4.5));
+        assert_near(ray.direction, Vector::new(-0.1, 0.1, -0.5).normalize());
+        let ray = Ray::from_ndc(&camera, cgmath::Vector2::new(1.0, -1.0));
+        assert_near(ray.direction, Vector::new(0.3, -0.2, -0.5).normalize());
+    }
+
+    #[test]
+    fn raycast_instances_finds_the_closest_transformed_instance() {
+        // A unit square around the origin, facing +z
+        let square = RaycastMesh::new(
+            &[[-0.5, -0.5, 0.0], [0.5, -0.5, 0.0], [0.5, 0.5, 0.0], [-0.5, 0.5, 0.0]],
+            &[],
+            &[0, 1, 2, 0, 2, 3],
+        );
+        let facing_x = cgmath::Quaternion::from_angle_y(cgmath::Deg(90.0));
+        let instances = [
+            // Behind the second one along the ray
+            Instance {
+                position: Vector::new(0.0, 0.0, 0.0),
+                rotation: facing_x,
+                scale: Vector::new(1.0, 1.0, 1.0),
+            },
+            // Scaled so it reaches up to y = 1, and turned to face +x
+            Instance {
+                position: Vector::new(3.0, 0.0, 0.0),
+                rotation: facing_x,
+                scale: Vector::new(2.0, 2.0, 1.0),
+            },
+            // Off to the side
+            Instance {
+                position: Vector::new(6.0, 5.0, 0.0),
+                rotation: facing_x,
+                scale: Vector::new(1.0, 1.0, 1.0),
+            },
+        ];
+        let ray = Ray::new(Point::new(10.0, 0.75, 0.25), -Vector::unit_x());
+        let hit = raycast_instances(&ray, &square, &instances).expect("ray should hit the scaled instance");
+        assert_eq!(hit.instance, InstanceHandle(1));
+        // In world units, not the instance's scaled ones
+        assert!((hit.distance - 7.0).abs() < 1e-4, "{}", hit.distance);
+        assert_point_near(hit.position, Point::new(3.0, 0.75, 0.25));
+        assert_point_near(hit.mesh_hit.position, Point::new(-0.125, 0.375, 0.0));
+
+        // Above the unscaled instance, only the scaled one is hit
+        let ray = Ray::new(Point::new(-10.0, 0.75, 0.25), Vector::unit_x());
+        let hit = raycast_instances(&ray, &square, &instances).expect("ray should hit the scaled instance");
+        assert_eq!(hit.instance, InstanceHandle(1));
+        assert!((hit.distance - 13.0).abs() < 1e-4, "{}", hit.distance);
+        // Lower down the first instance is in front
+        let ray = Ray::new(Point::new(-10.0, 0.25, 0.25), Vector::unit_x());
+        let hit = raycast_instances(&ray, &square, &instances).expect("ray should hit the first instance");
+        assert_eq!(hit.instance, InstanceHandle(0));
+        assert!((hit.distance - 10.0).abs() < 1e-4, "{}", hit.distance);
+
+        let ray = Ray::new(Point::new(10.0, 3.0, 0.0), -Vector::unit_x());
+        assert_eq!(raycast_instances(&ray, &square, &instances), None);
+    }
+}
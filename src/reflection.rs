@@ -0,0 +1,420 @@
+use anyhow::{bail, Result};
+use naga::{AddressSpace, Binding, ImageClass, ImageDimension, ScalarKind, ShaderStage, TypeInner};
+
+/// What a WGSL module expects to be bound and fed, read from naga's IR.
+///
+/// Bind group layouts can be made from it instead of written out by hand,
+/// and pipelines can check their vertex buffers and bind group layouts
+/// against it before wgpu gets to reject them with a less helpful message.
+pub struct ShaderReflection {
+    module: naga::Module,
+    info: naga::valid::ModuleInfo,
+}
+
+// A vertex input of an entry point.
+struct VertexInput {
+    location: u32,
+    name: String,
+    kind: ScalarKind,
+    components: u32,
+}
+
+impl ShaderReflection {
+    /// Takes a module and the info naga's validator returned for it.
+    pub fn new(module: naga::Module, info: naga::valid::ModuleInfo) -> Self {
+        Self { module, info }
+    }
+
+    /// The stages of the entry points that use a global.
+    fn stages_using(&self, global: naga::Handle<naga::GlobalVariable>) -> wgpu::ShaderStages {
+        let mut stages = wgpu::ShaderStages::NONE;
+        for (index, entry_point) in self.module.entry_points.iter().enumerate() {
+            if !self.info.get_entry_point(index)[global].is_empty() {
+                stages |= shader_stages(entry_point.stage);
+            }
+        }
+        stages
+    }
+
+    fn all_stages(&self) -> wgpu::ShaderStages {
+        self.module
+            .entry_points
+            .iter()
+            .fold(wgpu::ShaderStages::NONE, |stages, entry_point| stages | shader_stages(entry_point.stage))
+    }
+
+    /// The entries of a layout for bind group `group`, sorted by binding.
+    ///
+    /// Entries are visible to the stages that use them. Bindings no entry
+    /// point uses yet are visible to every stage the module has, so that
+    /// other shaders sharing the layout can use them. Float textures are
+    /// assumed to be filterable, as there's no telling from the shader.
+    pub fn bind_group_layout_entries(&self, group: u32) -> Result<Vec<wgpu::BindGroupLayoutEntry>> {
+        let mut entries = Vec::new();
+        for (handle, global) in self.module.global_variables.iter() {
+            let Some(binding) = global.binding.as_ref().filter(|binding| binding.group == group) else {
+                continue;
+            };
+            let mut visibility = self.stages_using(handle);
+            if visibility.is_empty() {
+                visibility = self.all_stages();
+            }
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: binding.binding,
+                visibility,
+                ty: self.binding_type(global)?,
+                count: None,
+            });
+        }
+        entries.sort_by_key(|entry| entry.binding);
+        Ok(entries)
+    }
+
+    /// Makes the layout of bind group `group`, also returning its entries
+    /// to check other shaders against.
+    pub fn create_bind_group_layout(
+        &self,
+        device: &wgpu::Device,
+        group: u32,
+        label: &str,
+    ) -> Result<(wgpu::BindGroupLayout, Vec<wgpu::BindGroupLayoutEntry>)> {
+        let entries = self.bind_group_layout_entries(group)?;
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &entries,
+        });
+        Ok((layout, entries))
+    }
+
+    fn binding_type(&self, global: &naga::GlobalVariable) -> Result<wgpu::BindingType> {
+        let name = global.name.as_deref().unwrap_or("?");
+        let buffer = |ty| wgpu::BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+        Ok(match (global.space, &self.module.types[global.ty].inner) {
+            (AddressSpace::Uniform, _) => buffer(wgpu::BufferBindingType::Uniform),
+            (AddressSpace::Storage { access }, _) => buffer(wgpu::BufferBindingType::Storage {
+                read_only: !access.contains(naga::StorageAccess::STORE),
+            }),
+            (AddressSpace::Handle, TypeInner::Sampler { comparison }) => wgpu::BindingType::Sampler(if *comparison {
+                wgpu::SamplerBindingType::Comparison
+            } else {
+                wgpu::SamplerBindingType::Filtering
+            }),
+            (AddressSpace::Handle, TypeInner::Image { dim, arrayed, class }) => {
+                let view_dimension = match (dim, arrayed) {
+                    (ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+                    (ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                    (ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                    (ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+                    (ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                    (ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+                };
+                match class {
+                    ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+                        sample_type: match kind {
+                            ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                            ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                            _ => wgpu::TextureSampleType::Float { filterable: !multi },
+                        },
+                        view_dimension,
+                        multisampled: *multi,
+                    },
+                    ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension,
+                        multisampled: *multi,
+                    },
+                    ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+                        access: if !access.contains(naga::StorageAccess::LOAD) {
+                            wgpu::StorageTextureAccess::WriteOnly
+                        } else if !access.contains(naga::StorageAccess::STORE) {
+                            wgpu::StorageTextureAccess::ReadOnly
+                        } else {
+                            wgpu::StorageTextureAccess::ReadWrite
+                        },
+                        format: match format {
+                            naga::StorageFormat::Rgba8Unorm => wgpu::TextureFormat::Rgba8Unorm,
+                            naga::StorageFormat::Rgba16Float => wgpu::TextureFormat::Rgba16Float,
+                            naga::StorageFormat::Rgba32Float => wgpu::TextureFormat::Rgba32Float,
+                            naga::StorageFormat::R32Float => wgpu::TextureFormat::R32Float,
+                            naga::StorageFormat::R32Uint => wgpu::TextureFormat::R32Uint,
+                            naga::StorageFormat::R32Sint => wgpu::TextureFormat::R32Sint,
+                            format => bail!("{} is a storage texture of format {:?}, which isn't supported", name, format),
+                        },
+                        view_dimension,
+                    },
+                }
+            }
+            (space, ty) => bail!("{} is a {:?} in {:?}, which can't be bound", name, ty, space),
+        })
+    }
+
+    /// Checks that the layouts of bind groups 0, 1, ... have an entry of the
+    /// right type, visible to the right stages, for everything the shader
+    /// uses, reporting every mismatch.
+    pub fn check_bind_groups(&self, groups: &[Vec<wgpu::BindGroupLayoutEntry>]) -> Result<()> {
+        let mut problems = Vec::new();
+        for (handle, global) in self.module.global_variables.iter() {
+            let Some(binding) = global.binding.as_ref() else {
+                continue;
+            };
+            let stages = self.stages_using(handle);
+            if stages.is_empty() {
+                continue;
+            }
+            let name = format!(
+                "@group({}) @binding({}) {}",
+                binding.group,
+                binding.binding,
+                global.name.as_deref().unwrap_or("?")
+            );
+            let Some(entries) = groups.get(binding.group as usize) else {
+                problems.push(format!("{} is in a group the pipeline has no layout for", name));
+                continue;
+            };
+            let Some(entry) = entries.iter().find(|entry| entry.binding == binding.binding) else {
+                problems.push(format!("{} isn't in the layout", name));
+                continue;
+            };
+            match self.binding_type(global) {
+                Ok(ty) if !compatible(&ty, &entry.ty) => {
+                    problems.push(format!("{} is a {:?} in the shader but a {:?} in the layout", name, ty, entry.ty))
+                }
+                Ok(_) => {}
+                Err(e) => problems.push(e.to_string()),
+            }
+            if !entry.visibility.contains(stages) {
+                problems.push(format!(
+                    "{} is used in {:?} but the layout only makes it visible to {:?}",
+                    name, stages, entry.visibility
+                ));
+            }
+        }
+        if !problems.is_empty() {
+            bail!("{}", problems.join("\n"));
+        }
+        Ok(())
+    }
+
+    fn vertex_inputs(&self, entry_point: &naga::EntryPoint) -> Vec<VertexInput> {
+        let mut inputs = Vec::new();
+        let mut add = |binding: Option<&Binding>, name: Option<&String>, ty: naga::Handle<naga::Type>| {
+            let Some(&Binding::Location { location, .. }) = binding else {
+                return;
+            };
+            let (kind, components) = match self.module.types[ty].inner {
+                TypeInner::Scalar { kind, .. } => (kind, 1),
+                TypeInner::Vector { size, kind, .. } => (kind, size as u32),
+                // Validation only allows numbers and vectors of them
+                _ => return,
+            };
+            inputs.push(VertexInput {
+                location,
+                name: name.cloned().unwrap_or_default(),
+                kind,
+                components,
+            });
+        };
+        for argument in entry_point.function.arguments.iter() {
+            match &self.module.types[argument.ty].inner {
+                TypeInner::Struct { members, .. } => {
+                    for member in members {
+                        add(member.binding.as_ref(), member.name.as_ref(), member.ty);
+                    }
+                }
+                _ => add(argument.binding.as_ref(), argument.name.as_ref(), argument.ty),
+            }
+        }
+        inputs
+    }
+
+    /// Checks vertex buffer layouts against the inputs of vertex entry
+    /// point `entry_point`: every input needs an attribute of the same
+    /// scalar type and number of components, and every attribute has to
+    /// fit in its buffer's stride. Reports every mismatch.
+    pub fn check_vertex_buffers(&self, entry_point: &str, buffers: &[wgpu::VertexBufferLayout]) -> Result<()> {
+        let Some(entry) = self
+            .module
+            .entry_points
+            .iter()
+            .find(|entry| entry.name == entry_point && entry.stage == ShaderStage::Vertex)
+        else {
+            bail!("There's no vertex entry point called {}", entry_point);
+        };
+        let mut problems = Vec::new();
+        for (index, buffer) in buffers.iter().enumerate() {
+            for attribute in buffer.attributes.iter() {
+                let end = attribute.offset + attribute.format.size();
+                if buffer.array_stride != 0 && end > buffer.array_stride {
+                    problems.push(format!(
+                        "Vertex buffer {}: the {:?} at location {} ends at byte {}, past the {} byte stride",
+                        index, attribute.format, attribute.shader_location, end, buffer.array_stride
+                    ));
+                }
+            }
+        }
+        for input in self.vertex_inputs(entry) {
+            let attributes = buffers
+                .iter()
+                .enumerate()
+                .flat_map(|(index, buffer)| buffer.attributes.iter().map(move |attribute| (index, attribute)))
+                .filter(|(_, attribute)| attribute.shader_location == input.location)
+                .collect::<Vec<_>>();
+            let expected = describe(input.kind, input.components);
+            match attributes.as_slice() {
+                [] => problems.push(format!(
+                    "{}: location {} ({}: {}) isn't in any vertex buffer",
+                    entry_point, input.location, input.name, expected
+                )),
+                [(index, attribute)] => {
+                    if vertex_format_type(attribute.format) != (input.kind, input.components) {
+                        problems.push(format!(
+                            "{}: location {} ({}) is a {} in the shader but a {:?} in vertex buffer {}",
+                            entry_point, input.location, input.name, expected, attribute.format, index
+                        ));
+                    }
+                }
+                _ => problems.push(format!(
+                    "{}: location {} ({}) is in more than one vertex buffer attribute",
+                    entry_point, input.location, input.name
+                )),
+            }
+        }
+        if !problems.is_empty() {
+            bail!("{}", problems.join("\n"));
+        }
+        Ok(())
+    }
+}
+
+/// A field of a vertex struct: its name, offset and size in bytes.
+pub type VertexField = (&'static str, usize, usize);
+
+/// Checks that a vertex buffer layout matches the struct it's made for:
+/// the stride has to be the struct's size, and the attributes have to
+/// cover every field exactly, back to back, with none left over. A field
+/// can take more than one attribute, like the columns of a matrix.
+/// Reports every mismatch.
+pub fn check_vertex_layout(layout: &wgpu::VertexBufferLayout, size: usize, fields: &[VertexField]) -> Result<()> {
+    let mut problems = Vec::new();
+    if layout.array_stride != size as wgpu::BufferAddress {
+        problems.push(format!("The stride is {} bytes but the struct is {}", layout.array_stride, size));
+    }
+    let mut used = vec![false; layout.attributes.len()];
+    for &(name, offset, size) in fields {
+        let (start, end) = (offset as wgpu::BufferAddress, (offset + size) as wgpu::BufferAddress);
+        let mut covered = start;
+        let mut attributes = layout
+            .attributes
+            .iter()
+            .enumerate()
+            .filter(|(_, attribute)| (start..end).contains(&attribute.offset))
+            .collect::<Vec<_>>();
+        attributes.sort_by_key(|(_, attribute)| attribute.offset);
+        for (index, attribute) in attributes {
+            used[index] = true;
+            if attribute.offset != covered {
+                problems.push(format!(
+                    "{}: location {} is at byte {}, not {}",
+                    name, attribute.shader_location, attribute.offset, covered
+                ));
+            }
+            covered = attribute.offset + attribute.format.size();
+        }
+        if covered == start {
+            problems.push(format!("{}: no attribute reads it", name));
+        } else if covered != end {
+            problems.push(format!(
+                "{}: the attributes cover bytes {}..{} of the field's {}..{}",
+                name, start, covered, start, end
+            ));
+        }
+    }
+    for (attribute, _) in layout.attributes.iter().zip(used).filter(|(_, used)| !used) {
+        problems.push(format!(
+            "Location {} at byte {} isn't in any field",
+            attribute.shader_location, attribute.offset
+        ));
+    }
+    if !problems.is_empty() {
+        bail!("{}", problems.join("\n"));
+    }
+    Ok(())
+}
+
+/// The size of the field `field` returns, for [`check_vertex_layout`].
+pub fn field_size<T, F>(_field: impl Fn(&T) -> &F) -> usize {
+    std::mem::size_of::<F>()
+}
+
+fn shader_stages(stage: ShaderStage) -> wgpu::ShaderStages {
+    match stage {
+        ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+        ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+        ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+    }
+}
+
+// Whether a layout entry can be used for what the shader declares.
+// Reflection can't tell whether textures get filtered, so filterability
+// doesn't count, and neither does the minimum size of buffers.
+fn compatible(shader: &wgpu::BindingType, layout: &wgpu::BindingType) -> bool {
+    use wgpu::BindingType as B;
+    use wgpu::TextureSampleType as T;
+    match (shader, layout) {
+        (B::Buffer { ty: a, .. }, B::Buffer { ty: b, .. }) => a == b,
+        (B::Sampler(a), B::Sampler(b)) => {
+            (*a == wgpu::SamplerBindingType::Comparison) == (*b == wgpu::SamplerBindingType::Comparison)
+        }
+        (
+            B::Texture { sample_type: a, view_dimension: a_dimension, multisampled: a_multi },
+            B::Texture { sample_type: b, view_dimension: b_dimension, multisampled: b_multi },
+        ) => {
+            let same_type = matches!((a, b), (T::Float { .. }, T::Float { .. })) || a == b;
+            same_type && a_dimension == b_dimension && a_multi == b_multi
+        }
+        (a, b) => a == b,
+    }
+}
+
+// The scalar type and number of components a vertex format reads as in a
+// shader.
+fn vertex_format_type(format: wgpu::VertexFormat) -> (ScalarKind, u32) {
+    use wgpu::VertexFormat as F;
+    match format {
+        F::Float32 | F::Float64 => (ScalarKind::Float, 1),
+        F::Uint32 => (ScalarKind::Uint, 1),
+        F::Sint32 => (ScalarKind::Sint, 1),
+        F::Unorm8x2 | F::Snorm8x2 | F::Unorm16x2 | F::Snorm16x2 | F::Float16x2 | F::Float32x2 | F::Float64x2 => {
+            (ScalarKind::Float, 2)
+        }
+        F::Uint8x2 | F::Uint16x2 | F::Uint32x2 => (ScalarKind::Uint, 2),
+        F::Sint8x2 | F::Sint16x2 | F::Sint32x2 => (ScalarKind::Sint, 2),
+        F::Float32x3 | F::Float64x3 => (ScalarKind::Float, 3),
+        F::Uint32x3 => (ScalarKind::Uint, 3),
+        F::Sint32x3 => (ScalarKind::Sint, 3),
+        F::Unorm8x4 | F::Snorm8x4 | F::Unorm16x4 | F::Snorm16x4 | F::Float16x4 | F::Float32x4 | F::Float64x4 => {
+            (ScalarKind::Float, 4)
+        }
+        F::Uint8x4 | F::Uint16x4 | F::Uint32x4 => (ScalarKind::Uint, 4),
+        F::Sint8x4 | F::Sint16x4 | F::Sint32x4 => (ScalarKind::Sint, 4),
+    }
+}
+
+// The WGSL name of a scalar or vector type.
+fn describe(kind: ScalarKind, components: u32) -> String {
+    let scalar = match kind {
+        ScalarKind::Sint => "i32",
+        ScalarKind::Uint => "u32",
+        ScalarKind::Float => "f32",
+        ScalarKind::Bool => "bool",
+    };
+    if components == 1 {
+        scalar.to_string()
+    } else {
+        format!("vec{}<{}>", components, scalar)
+    }
+}
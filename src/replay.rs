@@ -0,0 +1,441 @@
+use std::path::Path;
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use winit::event::*;
+
+use crate::gamepad::GamepadState;
+
+/// The subset of window and device events that drive `State::input`, in a
+/// form that can be written to disk.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RecordedEvent {
+    Keyboard(KeyboardInput),
+    MouseInput { button: MouseButton, state: ElementState },
+    MouseWheel(MouseScrollDelta),
+    CursorMoved { x: f64, y: f64 },
+    CursorLeft,
+    Focused(bool),
+    Touch { id: u64, phase: TouchPhase, x: f64, y: f64 },
+    /// Raw device motion, used for mouse look.
+    MouseMotion { dx: f64, dy: f64 },
+}
+
+/// What to feed back into the app when replaying a `RecordedEvent`.
+pub enum ReplayEvent {
+    Window(WindowEvent<'static>),
+    Device(DeviceEvent),
+}
+
+impl RecordedEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        Some(match event {
+            WindowEvent::KeyboardInput { input, .. } => RecordedEvent::Keyboard(*input),
+            WindowEvent::MouseInput { button, state, .. } => RecordedEvent::MouseInput {
+                button: *button,
+                state: *state,
+            },
+            WindowEvent::MouseWheel { delta, .. } => RecordedEvent::MouseWheel(*delta),
+            WindowEvent::CursorMoved { position, .. } => RecordedEvent::CursorMoved {
+                x: position.x,
+                y: position.y,
+            },
+            WindowEvent::CursorLeft { .. } => RecordedEvent::CursorLeft,
+            WindowEvent::Focused(focused) => RecordedEvent::Focused(*focused),
+            WindowEvent::Touch(touch) => RecordedEvent::Touch {
+                id: touch.id,
+                phase: touch.phase,
+                x: touch.location.x,
+                y: touch.location.y,
+            },
+            _ => return None,
+        })
+    }
+
+    pub fn from_device_event(event: &DeviceEvent) -> Option<Self> {
+        match event {
+            DeviceEvent::MouseMotion { delta: (dx, dy) } => Some(RecordedEvent::MouseMotion { dx: *dx, dy: *dy }),
+            _ => None,
+        }
+    }
+
+    /// Rebuilds the original event. Device ids aren't recorded, so every
+    /// event gets the same placeholder id.
+    #[allow(deprecated)]
+    pub fn to_event(&self) -> ReplayEvent {
+        // SAFETY: the id is only compared by our own input handling, it's
+        // never passed back into winit.
+        let device_id = unsafe { DeviceId::dummy() };
+        let modifiers = ModifiersState::empty();
+        ReplayEvent::Window(match self {
+            RecordedEvent::Keyboard(input) => WindowEvent::KeyboardInput {
+                device_id,
+                input: *input,
+                is_synthetic: false,
+            },
+            RecordedEvent::MouseInput { button, state } => WindowEvent::MouseInput {
+                device_id,
+                state: *state,
+                button: *button,
+                modifiers,
+            },
+            RecordedEvent::MouseWheel(delta) => WindowEvent::MouseWheel {
+                device_id,
+                delta: *delta,
+                phase: TouchPhase::Moved,
+                modifiers,
+            },
+            RecordedEvent::CursorMoved { x, y } => WindowEvent::CursorMoved {
+                device_id,
+                position: winit::dpi::PhysicalPosition::new(*x, *y),
+                modifiers,
+            },
+            RecordedEvent::CursorLeft => WindowEvent::CursorLeft { device_id },
+            RecordedEvent::Focused(focused) => WindowEvent::Focused(*focused),
+            RecordedEvent::Touch { id, phase, x, y } => WindowEvent::Touch(Touch {
+                device_id,
+                phase: *phase,
+                location: winit::dpi::PhysicalPosition::new(*x, *y),
+                force: None,
+                id: *id,
+            }),
+            RecordedEvent::MouseMotion { dx, dy } => {
+                return ReplayEvent::Device(DeviceEvent::MouseMotion { delta: (*dx, *dy) });
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimedEvent {
+    /// Wall clock seconds since recording started. Only informative,
+    /// replay goes by frame.
+    pub time: f64,
+    pub event: RecordedEvent,
+}
+
+/// The events that arrived before a frame, the gamepads connected during
+/// it, and how long that frame lasted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub delta: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<TimedEvent>,
+    /// Gamepads are polled rather than sent as events, so their whole state
+    /// is kept for every frame.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gamepads: Vec<GamepadState>,
+}
+
+/// A recorded interactive session, stored as RON or JSON.
+///
+/// Replaying feeds each frame's events back in and advances the clock by the
+/// recorded delta, so with the same fixed timestep and window size the
+/// session produces exactly the same frames.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputRecording {
+    pub fixed_timestep: f32,
+    /// Physical window size when recording started.
+    pub size: [u32; 2],
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl InputRecording {
+    /// Reads a recording, picking the format from the file extension
+    /// (`.json`, anything else is treated as RON).
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+        if is_json(path) {
+            serde_json::from_str(&text).with_context(|| format!("Invalid input recording {}", path.display()))
+        } else {
+            ron::from_str(&text).with_context(|| format!("Invalid input recording {}", path.display()))
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?
+        };
+        std::fs::write(path, text).with_context(|| format!("Couldn't write {}", path.display()))
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+/// Collects events as they arrive and groups them into frames.
+pub struct InputRecorder {
+    recording: InputRecording,
+    pending: Vec<TimedEvent>,
+    start: f64,
+}
+
+impl InputRecorder {
+    pub fn new(fixed_timestep: f32, size: winit::dpi::PhysicalSize<u32>) -> Self {
+        Self {
+            recording: InputRecording {
+                fixed_timestep,
+                size: [size.width, size.height],
+                frames: Vec::new(),
+            },
+            pending: Vec::new(),
+            start: crate::time::now_seconds(),
+        }
+    }
+
+    pub fn record_window_event(&mut self, event: &WindowEvent) {
+        if let Some(event) = RecordedEvent::from_window_event(event) {
+            self.push(event);
+        }
+    }
+
+    pub fn record_device_event(&mut self, event: &DeviceEvent) {
+        if let Some(event) = RecordedEvent::from_device_event(event) {
+            self.push(event);
+        }
+    }
+
+    fn push(&mut self, event: RecordedEvent) {
+        self.pending.push(TimedEvent {
+            time: crate::time::now_seconds() - self.start,
+            event,
+        });
+    }
+
+    /// Closes the current frame. Call once per frame after `Time::tick`,
+    /// with the (clamped) delta the frame is simulated with and the gamepads
+    /// polled for it.
+    pub fn end_frame(&mut self, delta: f32, gamepads: &[GamepadState]) {
+        self.recording.frames.push(RecordedFrame {
+            delta,
+            events: std::mem::take(&mut self.pending),
+            gamepads: gamepads.to_vec(),
+        });
+    }
+
+    pub fn recording(&self) -> &InputRecording {
+        &self.recording
+    }
+}
+
+/// Hands out the frames of a recording one at a time.
+pub struct InputPlayer {
+    recording: InputRecording,
+    frame: usize,
+}
+
+impl InputPlayer {
+    pub fn new(recording: InputRecording) -> Self {
+        Self { recording, frame: 0 }
+    }
+
+    pub fn recording(&self) -> &InputRecording {
+        &self.recording
+    }
+
+    /// The next frame to replay, or None once the recording has finished.
+    pub fn next_frame(&mut self) -> Option<&RecordedFrame> {
+        let frame = self.recording.frames.get(self.frame)?;
+        self.frame += 1;
+        Some(frame)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.recording.frames.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamepad::{GamepadAxis, GamepadButton};
+    use crate::input::{InputConfig, InputMap};
+
+    // A file in the temp directory, removed again when dropped
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("replay-test-{}-{}", std::process::id(), name)))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    // What a live session sends, a frame at a time: window events, device
+    // events and the gamepads polled
+    #[allow(deprecated)]
+    fn session() -> Vec<(Vec<ReplayEvent>, Vec<GamepadState>)> {
+        // SAFETY: only compared by the input map, never passed to winit
+        let device_id = unsafe { DeviceId::dummy() };
+        let modifiers = ModifiersState::empty();
+        let key = |keycode, state| {
+            ReplayEvent::Window(WindowEvent::KeyboardInput {
+                device_id,
+                input: KeyboardInput {
+                    scancode: 17,
+                    state,
+                    virtual_keycode: Some(keycode),
+                    modifiers,
+                },
+                is_synthetic: false,
+            })
+        };
+        let touch = |id, phase, x| {
+            ReplayEvent::Window(WindowEvent::Touch(Touch {
+                device_id,
+                phase,
+                location: winit::dpi::PhysicalPosition::new(x, 100.0),
+                force: None,
+                id,
+            }))
+        };
+        let mut pad = GamepadState::new(0, "Test pad");
+        pad.buttons.insert(GamepadButton::North);
+        pad.set_axis(GamepadAxis::LeftStickX, 0.8);
+        vec![
+            (
+                vec![
+                    key(VirtualKeyCode::W, ElementState::Pressed),
+                    ReplayEvent::Window(WindowEvent::CursorMoved {
+                        device_id,
+                        position: winit::dpi::PhysicalPosition::new(10.0, 20.0),
+                        modifiers,
+                    }),
+                ],
+                Vec::new(),
+            ),
+            (
+                vec![
+                    ReplayEvent::Window(WindowEvent::MouseInput {
+                        device_id,
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        modifiers,
+                    }),
+                    ReplayEvent::Window(WindowEvent::CursorMoved {
+                        device_id,
+                        position: winit::dpi::PhysicalPosition::new(15.5, 18.0),
+                        modifiers,
+                    }),
+                    ReplayEvent::Device(DeviceEvent::MouseMotion { delta: (5.5, -2.0) }),
+                ],
+                vec![pad.clone()],
+            ),
+            (
+                vec![
+                    key(VirtualKeyCode::W, ElementState::Released),
+                    ReplayEvent::Window(WindowEvent::MouseWheel {
+                        device_id,
+                        delta: MouseScrollDelta::LineDelta(0.0, -2.0),
+                        phase: TouchPhase::Moved,
+                        modifiers,
+                    }),
+                ],
+                vec![pad],
+            ),
+            (vec![touch(1, TouchPhase::Started, 50.0), touch(1, TouchPhase::Ended, 51.0)], Vec::new()),
+            (vec![ReplayEvent::Window(WindowEvent::Focused(false))], Vec::new()),
+        ]
+    }
+
+    // Everything the app reads from the input during a frame
+    fn snapshot(input: &InputMap) -> Vec<String> {
+        let config = input.config();
+        let mut state = config
+            .actions
+            .keys()
+            .map(|action| format!("{} {} {} {}", action, input.pressed(action), input.held(action), input.released(action)))
+            .collect::<Vec<_>>();
+        state.extend(config.axes.keys().map(|axis| format!("{} {}", axis, input.axis(axis))));
+        state.push(format!("{:?} {:?}", input.cursor_delta(), input.mouse_motion()));
+        state
+    }
+
+    fn feed(input: &mut InputMap, event: &ReplayEvent) {
+        match event {
+            ReplayEvent::Window(event) => {
+                input.process_event(event);
+            }
+            ReplayEvent::Device(event) => input.process_device_event(event),
+        }
+    }
+
+    fn record() -> (InputRecording, Vec<Vec<String>>) {
+        let mut recorder = InputRecorder::new(1.0 / 60.0, winit::dpi::PhysicalSize::new(800, 600));
+        let mut input = InputMap::new(InputConfig::defaults());
+        let mut states = Vec::new();
+        for (events, gamepads) in session() {
+            for event in events.iter() {
+                match event {
+                    ReplayEvent::Window(event) => recorder.record_window_event(event),
+                    ReplayEvent::Device(event) => recorder.record_device_event(event),
+                }
+                feed(&mut input, event);
+            }
+            input.update_gamepads(&gamepads);
+            states.push(snapshot(&input));
+            input.end_frame(0.016);
+            recorder.end_frame(0.016, &gamepads);
+        }
+        (recorder.recording().clone(), states)
+    }
+
+    #[test]
+    fn recordings_round_trip_through_ron_and_json() {
+        let (recording, _) = record();
+        assert_eq!(recording.frames.len(), 5);
+        assert_eq!(recording.frames[1].events.len(), 3);
+        for name in ["session.ron", "session.JSON"] {
+            let file = TempFile::new(name);
+            recording.save(&file.0).unwrap();
+            assert_eq!(InputRecording::load(&file.0).unwrap(), recording);
+        }
+    }
+
+    #[test]
+    fn replaying_gives_the_same_input_as_the_live_session() {
+        let (recording, live) = record();
+        let mut input = InputMap::new(InputConfig::defaults());
+        let mut player = InputPlayer::new(recording);
+        let mut replayed = Vec::new();
+        while let Some(frame) = player.next_frame() {
+            for timed in frame.events.iter() {
+                feed(&mut input, &timed.event.to_event());
+            }
+            input.update_gamepads(&frame.gamepads);
+            replayed.push(snapshot(&input));
+            input.end_frame(frame.delta);
+        }
+        assert!(player.is_finished());
+        assert_eq!(replayed, live);
+        // The session did something worth comparing
+        assert!(live[0].contains(&"move_forward 1".to_string()));
+        assert!(live[1].contains(&"toggle_camera_mode true true false".to_string()));
+        assert!(live[2].contains(&"zoom -2".to_string()));
+        assert!(live[3].contains(&"toggle_texture true false true".to_string()));
+    }
+
+    #[test]
+    fn gamepad_frames_are_recorded() {
+        let (recording, _) = record();
+        let gamepads = recording.frames.iter().map(|frame| frame.gamepads.len()).collect::<Vec<_>>();
+        assert_eq!(gamepads, [0, 1, 1, 0, 0]);
+        assert!(recording.frames[1].gamepads[0].buttons.contains(&GamepadButton::North));
+        // Frames without gamepads leave them out of the file, and older
+        // recordings without them still load
+        let text = ron::ser::to_string(&recording.frames[0]).unwrap();
+        assert!(!text.contains("gamepads"), "{}", text);
+        let frame: RecordedFrame = ron::from_str("(delta: 0.5)").unwrap();
+        assert!(frame.events.is_empty() && frame.gamepads.is_empty());
+    }
+}
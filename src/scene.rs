@@ -0,0 +1,651 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use cgmath::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::{Camera, Projection};
+use crate::viewport::Viewport;
+
+/// The format version `save` writes. Bump it when the format changes in a
+/// way older builds can't read, and upgrade older files in `load`.
+pub const SCENE_VERSION: u32 = 1;
+
+/// Marks resources that come with the app rather than the scene, e.g.
+/// `builtin:hexagon`.
+pub const BUILTIN_PREFIX: &str = "builtin:";
+
+/// Meshes available as `builtin:<name>`.
+pub const BUILTIN_MESHES: &[&str] = &["hexagon", "square"];
+/// Textures available as `builtin:<name>`, loaded from the asset directory.
+pub const BUILTIN_TEXTURES: &[&str] = &["happy-tree.png", "layered-simplex-noise.png"];
+
+/// Everything in a scene, stored as RON or JSON.
+///
+/// Resources are listed once by name and referenced by that name, e.g. an
+/// instance names its mesh and material. File paths are relative to the
+/// scene file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    pub version: u32,
+    #[serde(default)]
+    pub meshes: Vec<MeshDescription>,
+    #[serde(default)]
+    pub textures: Vec<TextureDescription>,
+    #[serde(default)]
+    pub materials: Vec<MaterialDescription>,
+    #[serde(default)]
+    pub instances: Vec<InstanceDescription>,
+    /// Stored and saved with the scene, the renderer doesn't light anything
+    /// yet.
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    /// The first camera drives the main view, any others get views of
+    /// their own.
+    #[serde(default)]
+    pub cameras: Vec<CameraDescription>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeshDescription {
+    pub name: String,
+    /// A `.gltf` or `.glb` file, or one of the built in meshes.
+    pub path: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextureDescription {
+    pub name: String,
+    /// An image file, or one of the built in textures.
+    pub path: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDescription {
+    pub name: String,
+    /// Name of the texture the material is drawn with.
+    pub texture: String,
+    #[serde(default)]
+    pub blend: BlendMode,
+    /// Draws the back faces too, for things like leaves that are a single
+    /// layer of triangles.
+    #[serde(default)]
+    pub double_sided: bool,
+}
+
+/// How a material's colors combine with what's behind it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BlendMode {
+    /// Covers what's behind.
+    #[default]
+    Opaque,
+    /// Mixes with what's behind by the texture's alpha.
+    Alpha,
+    /// Adds to what's behind, for glows and sparks.
+    Additive,
+}
+
+impl BlendMode {
+    pub fn blend_state(self) -> wgpu::BlendState {
+        match self {
+            BlendMode::Opaque => wgpu::BlendState::REPLACE,
+            BlendMode::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+            BlendMode::Additive => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::OVER,
+            },
+        }
+    }
+
+    /// Whether it shows what's behind, and so is drawn after everything
+    /// opaque without hiding what's drawn after it.
+    pub fn is_transparent(self) -> bool {
+        self != BlendMode::Opaque
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstanceDescription {
+    pub mesh: String,
+    pub material: String,
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub rotation: Rotation,
+    #[serde(default = "one")]
+    pub scale: [f32; 3],
+}
+
+fn one() -> [f32; 3] {
+    [1.0; 3]
+}
+
+/// An orientation, in whichever form is easiest to write by hand.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Rotation {
+    /// `[x, y, z, w]`, normalized on load.
+    Quaternion([f32; 4]),
+    /// Degrees around x, then y, then z.
+    Euler([f32; 3]),
+    AxisAngle { axis: [f32; 3], degrees: f32 },
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation::Quaternion([0.0, 0.0, 0.0, 1.0])
+    }
+}
+
+impl Rotation {
+    pub fn to_quaternion(&self) -> cgmath::Quaternion<f32> {
+        match *self {
+            Rotation::Quaternion([x, y, z, w]) => cgmath::Quaternion::new(w, x, y, z).normalize(),
+            Rotation::Euler([x, y, z]) => {
+                cgmath::Quaternion::from_angle_z(cgmath::Deg(z))
+                    * cgmath::Quaternion::from_angle_y(cgmath::Deg(y))
+                    * cgmath::Quaternion::from_angle_x(cgmath::Deg(x))
+            }
+            Rotation::AxisAngle { axis, degrees } => {
+                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::from(axis).normalize(), cgmath::Deg(degrees))
+            }
+        }
+    }
+
+    pub fn from_quaternion(rotation: cgmath::Quaternion<f32>) -> Self {
+        Rotation::Quaternion([rotation.v.x, rotation.v.y, rotation.v.z, rotation.s])
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightDescription {
+    pub name: String,
+    pub kind: LightKind,
+    /// Linear RGB.
+    #[serde(default = "one")]
+    pub color: [f32; 3],
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LightKind {
+    Directional { direction: [f32; 3] },
+    Point { position: [f32; 3], range: f32 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub name: String,
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    #[serde(default = "unit_y")]
+    pub up: [f32; 3],
+    pub projection: ProjectionDescription,
+    pub znear: f32,
+    pub zfar: f32,
+    /// `[x, y, width, height]` of the window, 0 to 1 from the top left.
+    /// The whole window if left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub viewport: Option<[f32; 4]>,
+}
+
+fn unit_y() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+/// `camera::Projection` with plain numbers, angles in degrees.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ProjectionDescription {
+    Perspective { fovy: f32 },
+    ReverseZ { fovy: f32 },
+    Orthographic { height: f32 },
+    OffAxis { left: f32, right: f32, bottom: f32, top: f32 },
+}
+
+impl From<ProjectionDescription> for Projection {
+    fn from(projection: ProjectionDescription) -> Self {
+        match projection {
+            ProjectionDescription::Perspective { fovy } => Projection::Perspective { fovy: cgmath::Deg(fovy) },
+            ProjectionDescription::ReverseZ { fovy } => Projection::ReverseZ { fovy: cgmath::Deg(fovy) },
+            ProjectionDescription::Orthographic { height } => Projection::Orthographic { height },
+            ProjectionDescription::OffAxis { left, right, bottom, top } => {
+                Projection::OffAxis { left, right, bottom, top }
+            }
+        }
+    }
+}
+
+impl From<Projection> for ProjectionDescription {
+    fn from(projection: Projection) -> Self {
+        match projection {
+            Projection::Perspective { fovy } => ProjectionDescription::Perspective { fovy: fovy.0 },
+            Projection::ReverseZ { fovy } => ProjectionDescription::ReverseZ { fovy: fovy.0 },
+            Projection::Orthographic { height } => ProjectionDescription::Orthographic { height },
+            Projection::OffAxis { left, right, bottom, top } => {
+                ProjectionDescription::OffAxis { left, right, bottom, top }
+            }
+        }
+    }
+}
+
+impl CameraDescription {
+    pub fn from_camera(name: impl Into<String>, camera: &Camera, viewport: Viewport) -> Self {
+        Self {
+            name: name.into(),
+            eye: camera.eye.into(),
+            target: camera.target.into(),
+            up: camera.up.into(),
+            projection: camera.projection.into(),
+            znear: camera.znear,
+            zfar: camera.zfar,
+            viewport: (viewport != Viewport::FULL)
+                .then_some([viewport.x, viewport.y, viewport.width, viewport.height]),
+        }
+    }
+
+    /// The camera, with its aspect ratio still to be set from the viewport.
+    pub fn to_camera(&self) -> Camera {
+        Camera {
+            eye: self.eye.into(),
+            target: self.target.into(),
+            up: self.up.into(),
+            aspect: 1.0,
+            projection: self.projection.into(),
+            znear: self.znear,
+            zfar: self.zfar,
+        }
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        match self.viewport {
+            Some([x, y, width, height]) => Viewport::new(x, y, width, height),
+            None => Viewport::FULL,
+        }
+    }
+}
+
+/// Where a resource path points.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResourcePath<'a> {
+    Builtin(&'a str),
+    File(PathBuf),
+}
+
+impl<'a> ResourcePath<'a> {
+    /// Resolves `path` from a scene in `base_dir`.
+    pub fn resolve(path: &'a str, base_dir: &Path) -> Self {
+        match path.strip_prefix(BUILTIN_PREFIX) {
+            Some(name) => ResourcePath::Builtin(name),
+            None => ResourcePath::File(base_dir.join(path)),
+        }
+    }
+}
+
+// Just enough of a scene to check its version before parsing the rest,
+// which may not match this build's format. Named like the full scene, as RON
+// checks struct names.
+#[derive(Deserialize)]
+#[serde(rename = "SceneDescription")]
+struct VersionOnly {
+    version: u32,
+}
+
+impl SceneDescription {
+    /// The scene the app starts with when no scene file is given.
+    pub fn builtin() -> Self {
+        const NUM_INSTANCES_PER_ROW: u32 = 10;
+        const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> =
+            cgmath::Vector3::new(NUM_INSTANCES_PER_ROW as f32 * 0.5, 0.0, NUM_INSTANCES_PER_ROW as f32 * 0.5);
+
+        let builtin = |name: &str| format!("{}{}", BUILTIN_PREFIX, name);
+        let instances = (0..NUM_INSTANCES_PER_ROW)
+            .flat_map(|z| {
+                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
+                    let position = cgmath::Vector3::new(x as f32, 0.0, z as f32) - INSTANCE_DISPLACEMENT;
+                    let rotation = if position.is_zero() {
+                        Rotation::default()
+                    } else {
+                        Rotation::AxisAngle {
+                            axis: position.normalize().into(),
+                            degrees: 45.0,
+                        }
+                    };
+                    InstanceDescription {
+                        mesh: "square".into(),
+                        material: "noise".into(),
+                        position: position.into(),
+                        rotation,
+                        scale: one(),
+                    }
+                })
+            })
+            .collect();
+        Self {
+            version: SCENE_VERSION,
+            meshes: BUILTIN_MESHES
+                .iter()
+                .map(|name| MeshDescription {
+                    name: name.to_string(),
+                    path: builtin(name),
+                })
+                .collect(),
+            textures: vec![
+                TextureDescription {
+                    name: "happy-tree".into(),
+                    path: builtin("happy-tree.png"),
+                },
+                TextureDescription {
+                    name: "noise".into(),
+                    path: builtin("layered-simplex-noise.png"),
+                },
+            ],
+            materials: vec![
+                MaterialDescription {
+                    name: "happy-tree".into(),
+                    texture: "happy-tree".into(),
+                    blend: BlendMode::Opaque,
+                    double_sided: false,
+                },
+                MaterialDescription {
+                    name: "noise".into(),
+                    texture: "noise".into(),
+                    blend: BlendMode::Opaque,
+                    double_sided: false,
+                },
+            ],
+            instances,
+            lights: Vec::new(),
+            cameras: vec![CameraDescription {
+                name: "main".into(),
+                // 1 unit up and 2 units back, +z is out of the screen
+                eye: [0.0, 1.0, 2.0],
+                target: [0.0, 0.0, 0.0],
+                up: unit_y(),
+                projection: ProjectionDescription::Perspective { fovy: 45.0 },
+                znear: 0.1,
+                zfar: 100.0,
+                viewport: None,
+            }],
+        }
+    }
+
+    /// Reads and validates a scene, picking the format from the file
+    /// extension (`.json`, anything else is treated as RON).
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+        let json = is_json(path);
+        let VersionOnly { version } = if json {
+            serde_json::from_str(&text).map_err(Error::from)
+        } else {
+            ron::from_str(&text).map_err(Error::from)
+        }
+        .with_context(|| format!("{} isn't a scene file, it needs a `version` number", path.display()))?;
+        if version == 0 || version > SCENE_VERSION {
+            bail!(
+                "{} is scene format version {}, but this build only reads versions 1 to {}",
+                path.display(),
+                version,
+                SCENE_VERSION
+            );
+        }
+        let scene: Self = if json {
+            serde_json::from_str(&text).map_err(Error::from)
+        } else {
+            ron::from_str(&text).map_err(Error::from)
+        }
+        .with_context(|| format!("Invalid scene {}", path.display()))?;
+        scene.validate().with_context(|| format!("Invalid scene {}", path.display()))?;
+        Ok(scene)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?
+        };
+        std::fs::write(path, text).with_context(|| format!("Couldn't write {}", path.display()))
+    }
+
+    /// Checks that names are unique, references point at something, built
+    /// in resources exist and rotations and projections make sense. Reports
+    /// every problem at once.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let meshes = unique("mesh", self.meshes.iter().map(|mesh| &mesh.name), &mut problems);
+        let textures = unique("texture", self.textures.iter().map(|texture| &texture.name), &mut problems);
+        let materials = unique("material", self.materials.iter().map(|material| &material.name), &mut problems);
+        unique("light", self.lights.iter().map(|light| &light.name), &mut problems);
+        unique("camera", self.cameras.iter().map(|camera| &camera.name), &mut problems);
+
+        for mesh in self.meshes.iter() {
+            if let Some(name) = mesh.path.strip_prefix(BUILTIN_PREFIX) {
+                if !BUILTIN_MESHES.contains(&name) {
+                    problems.push(format!(
+                        "mesh \"{}\" uses {} but the built in meshes are {}",
+                        mesh.name,
+                        mesh.path,
+                        list_builtins(BUILTIN_MESHES)
+                    ));
+                }
+            }
+        }
+        for texture in self.textures.iter() {
+            if let Some(name) = texture.path.strip_prefix(BUILTIN_PREFIX) {
+                if !BUILTIN_TEXTURES.contains(&name) {
+                    problems.push(format!(
+                        "texture \"{}\" uses {} but the built in textures are {}",
+                        texture.name,
+                        texture.path,
+                        list_builtins(BUILTIN_TEXTURES)
+                    ));
+                }
+            }
+        }
+        for material in self.materials.iter() {
+            if !textures.contains(&material.texture) {
+                problems.push(format!(
+                    "material \"{}\" uses texture \"{}\", which isn't in the scene's textures",
+                    material.name, material.texture
+                ));
+            }
+        }
+        for (index, instance) in self.instances.iter().enumerate() {
+            match instance.rotation {
+                Rotation::AxisAngle { axis, .. } if !is_direction(axis) => problems.push(format!(
+                    "instance {} rotates around the axis {:?}, which needs a finite, non-zero length",
+                    index, axis
+                )),
+                Rotation::Quaternion(quaternion) if !is_direction(quaternion) => problems.push(format!(
+                    "instance {} has the quaternion {:?}, which needs a finite, non-zero length",
+                    index, quaternion
+                )),
+                _ => {}
+            }
+            if !meshes.contains(&instance.mesh) {
+                problems.push(format!(
+                    "instance {} uses mesh \"{}\", which isn't in the scene's meshes",
+                    index, instance.mesh
+                ));
+            }
+            if !materials.contains(&instance.material) {
+                problems.push(format!(
+                    "instance {} uses material \"{}\", which isn't in the scene's materials",
+                    index, instance.material
+                ));
+            }
+        }
+        for camera in self.cameras.iter() {
+            if camera.znear <= 0.0 || camera.zfar <= camera.znear {
+                problems.push(format!(
+                    "camera \"{}\" needs 0 < znear < zfar, got znear {} and zfar {}",
+                    camera.name, camera.znear, camera.zfar
+                ));
+            }
+            match camera.projection {
+                ProjectionDescription::Perspective { fovy } | ProjectionDescription::ReverseZ { fovy }
+                    if !(fovy > 0.0 && fovy < 180.0) =>
+                {
+                    problems.push(format!(
+                        "camera \"{}\" needs a fovy between 0 and 180 degrees, got {}",
+                        camera.name, fovy
+                    ))
+                }
+                ProjectionDescription::Orthographic { height } if !(height > 0.0 && height.is_finite()) => {
+                    problems.push(format!(
+                        "camera \"{}\" needs a positive, finite orthographic height, got {}",
+                        camera.name, height
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        match problems.len() {
+            0 => Ok(()),
+            1 => bail!("{}", problems[0]),
+            count => bail!("{} problems:\n  {}", count, problems.join("\n  ")),
+        }
+    }
+}
+
+// Whether the vector can be normalized.
+fn is_direction<const N: usize>(vector: [f32; N]) -> bool {
+    let length = vector.iter().map(|value| value * value).sum::<f32>();
+    length > 0.0 && length.is_finite()
+}
+
+// Collects the names, noting any that appear more than once.
+fn unique<'a>(kind: &str, names: impl Iterator<Item = &'a String>, problems: &mut Vec<String>) -> HashSet<&'a String> {
+    let mut seen = HashSet::new();
+    for name in names {
+        if !seen.insert(name) {
+            problems.push(format!("there's more than one {} called \"{}\"", kind, name));
+        }
+    }
+    seen
+}
+
+fn list_builtins(names: &[&str]) -> String {
+    names
+        .iter()
+        .map(|name| format!("{}{}", BUILTIN_PREFIX, name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file in the temp directory, removed again when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, text: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("scene-test-{}-{}", std::process::id(), name));
+            std::fs::write(&path, text).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn error(result: Result<SceneDescription>) -> String {
+        format!("{:#}", result.expect_err("the scene should be rejected"))
+    }
+
+    #[test]
+    fn builtin_scene_is_valid() {
+        SceneDescription::builtin().validate().unwrap();
+    }
+
+    #[test]
+    fn round_trips_through_ron_and_json() {
+        let scene = SceneDescription::builtin();
+        for name in ["round-trip.ron", "round-trip.json"] {
+            let file = TempFile::new(name, "");
+            scene.save(&file.0).unwrap();
+            assert_eq!(SceneDescription::load(&file.0).unwrap(), scene, "{}", name);
+        }
+    }
+
+    #[test]
+    fn version_is_checked_before_the_rest() {
+        // Fields a newer version might have added don't matter, the version
+        // is what's reported
+        let file = TempFile::new("newer.ron", "(version: 99, shiny_new_field: true)");
+        assert!(error(SceneDescription::load(&file.0)).contains("version 99"));
+        let file = TempFile::new("zero.json", r#"{"version": 0}"#);
+        assert!(error(SceneDescription::load(&file.0)).contains("version 0"));
+        let file = TempFile::new("unversioned.ron", "(meshes: [])");
+        assert!(error(SceneDescription::load(&file.0)).contains("needs a `version` number"));
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let file = TempFile::new("truncated.ron", "(version: 1, meshes: [");
+        assert!(SceneDescription::load(&file.0).is_err());
+        let file = TempFile::new("wrong-type.ron", "(version: 1, meshes: 5)");
+        assert!(error(SceneDescription::load(&file.0)).contains("Invalid scene"));
+        let file = TempFile::new("unknown-field.json", r#"{"version": 1, "colour": "red"}"#);
+        assert!(error(SceneDescription::load(&file.0)).contains("Invalid scene"));
+        let missing = std::env::temp_dir().join("scene-test-missing.ron");
+        assert!(error(SceneDescription::load(missing)).contains("Couldn't read"));
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let mut scene = SceneDescription::builtin();
+        scene.meshes.push(scene.meshes[0].clone());
+        scene.materials[0].texture = "missing".into();
+        scene.instances[0].mesh = "missing".into();
+        scene.instances[1].rotation = Rotation::AxisAngle { axis: [0.0; 3], degrees: 90.0 };
+        scene.instances[2].rotation = Rotation::Quaternion([0.0; 4]);
+        scene.cameras[0].projection = ProjectionDescription::Perspective { fovy: 0.0 };
+        let mut camera = scene.cameras[0].clone();
+        camera.name = "top".into();
+        camera.projection = ProjectionDescription::Orthographic { height: f32::NAN };
+        scene.cameras.push(camera);
+
+        let message = scene.validate().unwrap_err().to_string();
+        assert!(message.starts_with("7 problems"), "{}", message);
+        for expected in [
+            "more than one mesh called \"hexagon\"",
+            "uses texture \"missing\"",
+            "instance 0 uses mesh \"missing\"",
+            "instance 1 rotates around the axis",
+            "instance 2 has the quaternion",
+            "camera \"main\" needs a fovy",
+            "camera \"top\" needs a positive, finite orthographic height",
+        ] {
+            assert!(message.contains(expected), "{:?} isn't in {}", expected, message);
+        }
+    }
+}
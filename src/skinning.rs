@@ -0,0 +1,732 @@
+use std::path::Path;
+
+use anyhow::*;
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use crate::Instance;
+
+// Vertex format used by rigged meshes. The first two attributes line up with
+// `Vertex` so the same texture bindings and instance layout can be reused.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinnedVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+impl SkinnedVertex {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<SkinnedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                // Locations 2 and 3 are the slots InstanceRaw leaves free for vertex data.
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Local transform of a single joint, relative to its parent.
+#[derive(Copy, Clone, Debug)]
+pub struct JointTransform {
+    pub translation: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+impl JointTransform {
+    pub fn to_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.translation)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl Default for JointTransform {
+    fn default() -> Self {
+        Self {
+            translation: cgmath::Vector3::zero(),
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+pub struct Joint {
+    pub name: String,
+    /// Index of the parent joint within the same skeleton, `None` for roots.
+    pub parent: Option<usize>,
+    /// The transforms of the nodes between the parent joint (or the scene
+    /// root) and this joint that aren't joints themselves, which animations
+    /// don't move.
+    pub offset: cgmath::Matrix4<f32>,
+    pub rest: JointTransform,
+}
+
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    pub inverse_bind_matrices: Vec<cgmath::Matrix4<f32>>,
+    /// The inverse of the skinned mesh node's global transform. Joints are
+    /// posed in the scene's space, and this brings them back into the
+    /// mesh's, which the instance transform then places.
+    pub mesh_inverse: cgmath::Matrix4<f32>,
+}
+
+impl Skeleton {
+    pub fn rest_pose(&self) -> Vec<JointTransform> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+
+    /// Turns a local pose into the matrices the vertex shader blends with:
+    /// the joint's model-space transform times its inverse bind matrix.
+    pub fn joint_matrices(&self, pose: &[JointTransform]) -> Vec<cgmath::Matrix4<f32>> {
+        let mut globals: Vec<Option<cgmath::Matrix4<f32>>> = vec![None; self.joints.len()];
+        for index in 0..self.joints.len() {
+            self.resolve_global(index, pose, &mut globals);
+        }
+        globals
+            .into_iter()
+            .zip(self.inverse_bind_matrices.iter())
+            .map(|(global, inverse_bind)| self.mesh_inverse * global.unwrap() * inverse_bind)
+            .collect()
+    }
+
+    // glTF doesn't guarantee parents come before children in the joint list,
+    // so parents are resolved on demand.
+    fn resolve_global(
+        &self,
+        index: usize,
+        pose: &[JointTransform],
+        globals: &mut [Option<cgmath::Matrix4<f32>>],
+    ) -> cgmath::Matrix4<f32> {
+        if let Some(global) = globals[index] {
+            return global;
+        }
+        let joint = &self.joints[index];
+        let local = joint.offset * pose[index].to_matrix();
+        let global = match joint.parent {
+            Some(parent) => self.resolve_global(parent, pose, globals) * local,
+            None => local,
+        };
+        globals[index] = Some(global);
+        global
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+}
+
+pub enum ChannelValues {
+    Translation(Vec<cgmath::Vector3<f32>>),
+    Rotation(Vec<cgmath::Quaternion<f32>>),
+    Scale(Vec<cgmath::Vector3<f32>>),
+}
+
+impl ChannelValues {
+    pub fn len(&self) -> usize {
+        match self {
+            ChannelValues::Translation(values) => values.len(),
+            ChannelValues::Rotation(values) => values.len(),
+            ChannelValues::Scale(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Keyframes for one property of one joint. `times` are finite, not
+/// negative and increasing, and `values` has one per time, which loading
+/// checks.
+pub struct Channel {
+    pub joint: usize,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+impl Channel {
+    // Returns the two keyframes surrounding `time` and the blend factor between them.
+    fn keyframes(&self, time: f32) -> (usize, usize, f32) {
+        let last = self.times.len() - 1;
+        if time <= self.times[0] {
+            return (0, 0, 0.0);
+        }
+        if time >= self.times[last] {
+            return (last, last, 0.0);
+        }
+        let next = self.times.partition_point(|t| *t <= time);
+        let prev = next - 1;
+        let span = self.times[next] - self.times[prev];
+        let t = if span > 0.0 { (time - self.times[prev]) / span } else { 0.0 };
+        match self.interpolation {
+            Interpolation::Step => (prev, prev, 0.0),
+            Interpolation::Linear => (prev, next, t),
+        }
+    }
+
+    fn apply(&self, time: f32, transform: &mut JointTransform) {
+        if self.times.is_empty() {
+            return;
+        }
+        let (a, b, t) = self.keyframes(time);
+        match &self.values {
+            ChannelValues::Translation(values) => transform.translation = values[a].lerp(values[b], t),
+            ChannelValues::Rotation(values) => transform.rotation = values[a].nlerp(values[b], t),
+            ChannelValues::Scale(values) => transform.scale = values[a].lerp(values[b], t),
+        }
+    }
+}
+
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    /// Samples the clip at `time` (looping) on top of the skeleton's rest pose.
+    pub fn sample(&self, skeleton: &Skeleton, time: f32) -> Vec<JointTransform> {
+        let time = if self.duration > 0.0 { time.rem_euclid(self.duration) } else { 0.0 };
+        let mut pose = skeleton.rest_pose();
+        for channel in self.channels.iter() {
+            channel.apply(time, &mut pose[channel.joint]);
+        }
+        pose
+    }
+}
+
+/// CPU side data for a rigged mesh, as imported from a glTF file.
+pub struct SkinnedModel {
+    pub vertices: Vec<SkinnedVertex>,
+    pub indices: Vec<u32>,
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+}
+
+impl SkinnedModel {
+    /// Loads the first skinned mesh in a `.gltf` or `.glb` file along with
+    /// every animation that targets its joints.
+    pub fn load_gltf(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+        Self::from_gltf_slice(&bytes, path.parent())
+    }
+
+    /// Parses glTF data already in memory. `base` is the directory external
+    /// buffers are resolved against; pass `None` for self contained `.glb` files.
+    pub fn from_gltf_slice(bytes: &[u8], base: Option<&Path>) -> Result<Self> {
+        let gltf = gltf::Gltf::from_slice(bytes)?;
+        // Reading files never actually waits natively
+        let buffers = pollster::block_on(crate::assets::load_gltf_buffers(&gltf, base))?;
+
+        let node = gltf
+            .nodes()
+            .find(|node| node.mesh().is_some() && node.skin().is_some())
+            .context("glTF file has no skinned mesh")?;
+        let mesh = node.mesh().unwrap();
+        let skin = node.skin().unwrap();
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let base_vertex = vertices.len() as u32;
+            let positions = reader.read_positions().context("Skinned primitive has no positions")?;
+            let mut tex_coords = reader.read_tex_coords(0).map(|coords| coords.into_f32());
+            let mut joints = reader.read_joints(0).map(|joints| joints.into_u16());
+            let mut weights = reader.read_weights(0).map(|weights| weights.into_f32());
+            for position in positions {
+                vertices.push(SkinnedVertex {
+                    position,
+                    tex_coords: tex_coords.as_mut().and_then(Iterator::next).unwrap_or_default(),
+                    joints: joints
+                        .as_mut()
+                        .and_then(Iterator::next)
+                        .map(|j| j.map(u32::from))
+                        .unwrap_or_default(),
+                    weights: weights.as_mut().and_then(Iterator::next).unwrap_or([1.0, 0.0, 0.0, 0.0]),
+                });
+            }
+            let vertex_count = vertices.len() as u32 - base_vertex;
+            indices.extend(crate::assets::gltf_primitive_indices(reader.read_indices(), base_vertex, vertex_count)?);
+        }
+
+        // Map glTF node indices to joint slots so parents and animation
+        // targets can be expressed in skeleton space.
+        let joint_nodes = skin.joints().map(|joint| joint.index()).collect::<Vec<_>>();
+        let joint_slot = |node: usize| joint_nodes.iter().position(|n| *n == node);
+        let mut node_parents = vec![None; gltf.nodes().len()];
+        for parent in gltf.nodes() {
+            for child in parent.children() {
+                node_parents[child.index()] = Some(parent.index());
+            }
+        }
+        let local_matrices = gltf
+            .nodes()
+            .map(|node| cgmath::Matrix4::from(node.transform().matrix()))
+            .collect::<Vec<_>>();
+        // Walks up from `node` to the nearest joint above it, gathering the
+        // transforms of the nodes on the way
+        let parent_joint = |node: usize| {
+            let mut offset = cgmath::Matrix4::identity();
+            let mut current = node_parents[node];
+            while let Some(parent) = current {
+                if let Some(slot) = joint_slot(parent) {
+                    return (Some(slot), offset);
+                }
+                offset = local_matrices[parent] * offset;
+                current = node_parents[parent];
+            }
+            (None, offset)
+        };
+        let (_, mesh_parents) = parent_joint(node.index());
+        let mesh_global = mesh_parents * local_matrices[node.index()];
+        let mesh_inverse = mesh_global.invert().context("The skinned mesh's node transform can't be inverted")?;
+        let joints = skin
+            .joints()
+            .map(|node| {
+                let (parent, offset) = parent_joint(node.index());
+                let (translation, rotation, scale) = node.transform().decomposed();
+                Joint {
+                    name: node.name().unwrap_or_default().to_string(),
+                    parent,
+                    offset,
+                    rest: JointTransform {
+                        translation: translation.into(),
+                        rotation: cgmath::Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2]),
+                        scale: scale.into(),
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let reader = skin.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+        let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+            Some(matrices) => matrices.map(cgmath::Matrix4::from).collect(),
+            None => vec![cgmath::Matrix4::identity(); joints.len()],
+        };
+        if inverse_bind_matrices.len() != joints.len() {
+            bail!("The skin has {} joints but {} inverse bind matrices", joints.len(), inverse_bind_matrices.len());
+        }
+
+        let mut clips = Vec::new();
+        for animation in gltf.animations() {
+            let mut channels = Vec::new();
+            let mut duration = 0.0f32;
+            for channel in animation.channels() {
+                let Some(joint) = joint_slot(channel.target().node().index()) else {
+                    continue;
+                };
+                let reader = channel.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+                let times = reader.read_inputs().context("Animation channel has no keyframe times")?.collect::<Vec<_>>();
+                let interpolation = match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::Step => Interpolation::Step,
+                    _ => Interpolation::Linear,
+                };
+                // Cubic spline samplers store in-tangent, value, out-tangent per key;
+                // only the values are kept and played back linearly.
+                let cubic = channel.sampler().interpolation() == gltf::animation::Interpolation::CubicSpline;
+                let values = match reader.read_outputs().context("Animation channel has no values")? {
+                    gltf::animation::util::ReadOutputs::Translations(values) => {
+                        ChannelValues::Translation(keep_values(values.map(Into::into), cubic))
+                    }
+                    gltf::animation::util::ReadOutputs::Rotations(values) => ChannelValues::Rotation(keep_values(
+                        values.into_f32().map(|r| cgmath::Quaternion::new(r[3], r[0], r[1], r[2])),
+                        cubic,
+                    )),
+                    gltf::animation::util::ReadOutputs::Scales(values) => {
+                        ChannelValues::Scale(keep_values(values.map(Into::into), cubic))
+                    }
+                    gltf::animation::util::ReadOutputs::MorphTargetWeights(_) => continue,
+                };
+                duration = times.iter().copied().fold(duration, f32::max);
+                channels.push(Channel { joint, interpolation, times, values });
+            }
+            clips.push(AnimationClip {
+                name: animation.name().unwrap_or_default().to_string(),
+                duration,
+                channels,
+            });
+        }
+
+        let model = Self {
+            vertices,
+            indices,
+            skeleton: Skeleton { joints, inverse_bind_matrices, mesh_inverse },
+            clips,
+        };
+        model.validate()?;
+        Ok(model)
+    }
+
+    /// Checks that every vertex only refers to joints of the skeleton and
+    /// every channel has one value per keyframe at increasing times, which
+    /// skinning and sampling rely on.
+    pub fn validate(&self) -> Result<()> {
+        let joint_count = self.skeleton.joints.len();
+        if let Some((index, vertex)) =
+            self.vertices.iter().enumerate().find(|(_, vertex)| vertex.joints.iter().any(|joint| *joint as usize >= joint_count))
+        {
+            bail!(
+                "Skinned vertex {} refers to joints {:?}, but the skin only has {} joints",
+                index,
+                vertex.joints,
+                joint_count
+            );
+        }
+        for clip in self.clips.iter() {
+            for channel in clip.channels.iter() {
+                let Some(joint) = self.skeleton.joints.get(channel.joint) else {
+                    bail!("Animation {} targets joint {}, but the skin only has {} joints", clip.name, channel.joint, joint_count);
+                };
+                if let Some((index, time)) = channel.times.iter().enumerate().find(|(_, time)| !(**time >= 0.0 && time.is_finite())) {
+                    bail!(
+                        "Animation {} has keyframe {} for joint {} at time {}, times must be finite and not negative",
+                        clip.name,
+                        index,
+                        joint.name,
+                        time
+                    );
+                }
+                if channel.times.windows(2).any(|pair| pair[1] <= pair[0]) {
+                    bail!(
+                        "Animation {} keyframe times for joint {} must increase from one keyframe to the next",
+                        clip.name,
+                        joint.name
+                    );
+                }
+                if channel.values.len() != channel.times.len() {
+                    bail!(
+                        "Animation {} channel for joint {} has {} keyframe times but {} values",
+                        clip.name,
+                        joint.name,
+                        channel.times.len(),
+                        channel.values.len()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn keep_values<T>(values: impl Iterator<Item = T>, cubic: bool) -> Vec<T> {
+    if cubic {
+        values.skip(1).step_by(3).collect()
+    } else {
+        values.collect()
+    }
+}
+
+/// A skinned model uploaded to the GPU together with the per instance joint
+/// matrix storage buffer and the clip it is currently playing.
+pub struct SkinnedInstance {
+    pub model: SkinnedModel,
+    pub instance: Instance,
+    pub clip: Option<usize>,
+    pub time: f32,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    instance_buffer: wgpu::Buffer,
+    joint_buffer: wgpu::Buffer,
+    joint_bind_group: wgpu::BindGroup,
+    // The vertices of every triangle in order, for wireframes drawn by the
+    // shader. Only made once they're shown.
+    corner_buffer: Option<wgpu::Buffer>,
+}
+
+impl SkinnedInstance {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        model: SkinnedModel,
+        instance: Instance,
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skinned Vertex Buffer"),
+            contents: bytemuck::cast_slice(&model.vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skinned Index Buffer"),
+            contents: bytemuck::cast_slice(&model.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skinned Instance Buffer"),
+            contents: bytemuck::cast_slice(&[instance.to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let joint_matrices = Self::raw_joint_matrices(&model, &model.skeleton.rest_pose());
+        let joint_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Joint Matrix Buffer"),
+            contents: bytemuck::cast_slice(&joint_matrices),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let joint_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: joint_buffer.as_entire_binding(),
+            }],
+            label: Some("joint_bind_group"),
+        });
+        let num_indices = model.indices.len() as u32;
+        let clip = if model.clips.is_empty() { None } else { Some(0) };
+        Self {
+            model,
+            instance,
+            clip,
+            time: 0.0,
+            vertex_buffer,
+            index_buffer,
+            num_indices,
+            instance_buffer,
+            joint_buffer,
+            joint_bind_group,
+            corner_buffer: None,
+        }
+    }
+
+    fn raw_joint_matrices(model: &SkinnedModel, pose: &[JointTransform]) -> Vec<[[f32; 4]; 4]> {
+        model.skeleton.joint_matrices(pose).into_iter().map(Into::into).collect()
+    }
+
+    /// Advances the current clip by `dt` seconds and uploads the new pose.
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32) {
+        self.time += dt;
+        let pose = match self.clip.and_then(|clip| self.model.clips.get(clip)) {
+            Some(clip) => clip.sample(&self.model.skeleton, self.time),
+            None => self.model.skeleton.rest_pose(),
+        };
+        let joint_matrices = Self::raw_joint_matrices(&self.model, &pose);
+        queue.write_buffer(&self.joint_buffer, 0, bytemuck::cast_slice(&joint_matrices));
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&[self.instance.to_raw()]));
+    }
+
+    /// Draws the instance; expects the skinned pipeline, texture and camera
+    /// bind groups to already be set.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_bind_group(2, &self.joint_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    }
+
+    /// Makes the buffer `draw_corners` draws from, if it hasn't been made.
+    pub fn make_corner_buffer(&mut self, device: &wgpu::Device) {
+        if self.corner_buffer.is_some() {
+            return;
+        }
+        let corners = self.model.indices.iter().map(|&index| self.model.vertices[index as usize]).collect::<Vec<_>>();
+        self.corner_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skinned Corner Buffer"),
+            contents: bytemuck::cast_slice(&corners),
+            usage: wgpu::BufferUsages::VERTEX,
+        }));
+    }
+
+    /// Draws every triangle from vertices of its own, so `vertex_index % 3`
+    /// tells its corners apart, or like `draw` when there's no corner buffer.
+    pub fn draw_corners<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        let Some(corner_buffer) = &self.corner_buffer else {
+            return self.draw(render_pass);
+        };
+        render_pass.set_bind_group(2, &self.joint_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, corner_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.draw(0..self.num_indices, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joint(parent: Option<usize>, translation: (f32, f32, f32), rotation: cgmath::Quaternion<f32>) -> Joint {
+        Joint {
+            name: format!("joint {:?}", parent),
+            parent,
+            offset: cgmath::Matrix4::identity(),
+            rest: JointTransform {
+                translation: translation.into(),
+                rotation,
+                ..JointTransform::default()
+            },
+        }
+    }
+
+    fn skeleton(joints: Vec<Joint>) -> Skeleton {
+        Skeleton {
+            inverse_bind_matrices: vec![cgmath::Matrix4::identity(); joints.len()],
+            joints,
+            mesh_inverse: cgmath::Matrix4::identity(),
+        }
+    }
+
+    fn origin_of(matrix: cgmath::Matrix4<f32>) -> cgmath::Point3<f32> {
+        matrix.transform_point(cgmath::Point3::origin())
+    }
+
+    fn assert_near(a: cgmath::Point3<f32>, b: cgmath::Point3<f32>) {
+        assert!(a.distance(b) < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    // A root joint at (1, 0, 0) turned a quarter turn about z, listed after
+    // its child one unit along its x axis
+    fn arm() -> Skeleton {
+        let quarter_turn = cgmath::Quaternion::from_angle_z(cgmath::Deg(90.0));
+        skeleton(vec![
+            joint(Some(1), (1.0, 0.0, 0.0), cgmath::Quaternion::one()),
+            joint(None, (1.0, 0.0, 0.0), quarter_turn),
+        ])
+    }
+
+    #[test]
+    fn joints_are_posed_relative_to_their_parents() {
+        let mut skeleton = arm();
+        let matrices = skeleton.joint_matrices(&skeleton.rest_pose());
+        assert_near(origin_of(matrices[1]), cgmath::Point3::new(1.0, 0.0, 0.0));
+        assert_near(origin_of(matrices[0]), cgmath::Point3::new(1.0, 1.0, 0.0));
+
+        // Non-joint nodes in between, the inverse bind matrices and the mesh
+        // node all apply too
+        skeleton.joints[0].offset = cgmath::Matrix4::from_translation(cgmath::Vector3::new(0.0, 0.0, 1.0));
+        skeleton.inverse_bind_matrices[0] = cgmath::Matrix4::from_translation(cgmath::Vector3::new(1.0, 0.0, 0.0));
+        skeleton.mesh_inverse = cgmath::Matrix4::from_translation(cgmath::Vector3::new(0.0, -1.0, 0.0));
+        let matrices = skeleton.joint_matrices(&skeleton.rest_pose());
+        assert_near(origin_of(matrices[1]), cgmath::Point3::new(1.0, -1.0, 0.0));
+        // The inverse bind matrix moves along the parent's turned x axis
+        assert_near(origin_of(matrices[0]), cgmath::Point3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn the_rest_pose_undoes_the_inverse_bind_matrices() {
+        use cgmath::AbsDiffEq;
+        let mut skeleton = arm();
+        let globals = skeleton.joint_matrices(&skeleton.rest_pose());
+        skeleton.inverse_bind_matrices = globals.iter().map(|global| global.invert().unwrap()).collect();
+        for matrix in skeleton.joint_matrices(&skeleton.rest_pose()) {
+            assert!(matrix.abs_diff_eq(&cgmath::Matrix4::identity(), 1e-5), "{:?}", matrix);
+        }
+
+        // Turning the root swings the child around with it
+        let mut pose = skeleton.rest_pose();
+        pose[1].rotation = cgmath::Quaternion::one();
+        let matrices = skeleton.joint_matrices(&pose);
+        assert_near(matrices[0].transform_point(cgmath::Point3::new(1.0, 1.0, 0.0)), cgmath::Point3::new(2.0, 0.0, 0.0));
+    }
+
+    fn clip(channels: Vec<Channel>) -> AnimationClip {
+        let duration = channels.iter().filter_map(|channel| channel.times.last().copied()).fold(0.0, f32::max);
+        AnimationClip { name: "clip".to_string(), duration, channels }
+    }
+
+    #[test]
+    fn sampling_blends_and_holds_keyframes() {
+        let skeleton = arm();
+        let half_turn = cgmath::Quaternion::from_angle_y(cgmath::Deg(180.0));
+        let clip = clip(vec![
+            Channel {
+                joint: 0,
+                interpolation: Interpolation::Linear,
+                times: vec![0.0, 1.0, 2.0],
+                values: ChannelValues::Translation(vec![
+                    cgmath::Vector3::new(0.0, 0.0, 0.0),
+                    cgmath::Vector3::new(2.0, 0.0, 0.0),
+                    cgmath::Vector3::new(2.0, 4.0, 0.0),
+                ]),
+            },
+            Channel {
+                joint: 0,
+                interpolation: Interpolation::Step,
+                times: vec![0.5, 1.5],
+                values: ChannelValues::Rotation(vec![cgmath::Quaternion::one(), half_turn]),
+            },
+        ]);
+        let translation = |time: f32| clip.sample(&skeleton, time)[0].translation;
+        let rotation = |time: f32| clip.sample(&skeleton, time)[0].rotation;
+        assert_eq!(translation(0.5), cgmath::Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(translation(1.75), cgmath::Vector3::new(2.0, 3.0, 0.0));
+        // Looping round after the last keyframe
+        assert_eq!(translation(2.5), cgmath::Vector3::new(1.0, 0.0, 0.0));
+        // Step keys hold until the next one, and the first holds before it
+        assert_eq!(rotation(0.25), cgmath::Quaternion::one());
+        assert_eq!(rotation(1.4), cgmath::Quaternion::one());
+        assert_eq!(rotation(1.5), half_turn);
+        assert_eq!(rotation(1.9), half_turn);
+        // Joints without channels stay in their rest pose
+        let pose = clip.sample(&skeleton, 0.7);
+        assert_eq!(pose[1].translation, skeleton.joints[1].rest.translation);
+        assert_eq!(pose[1].rotation, skeleton.joints[1].rest.rotation);
+    }
+
+    fn model(joints: [u32; 4], times: Vec<f32>) -> SkinnedModel {
+        let values = ChannelValues::Scale(vec![cgmath::Vector3::new(1.0, 1.0, 1.0); times.len()]);
+        SkinnedModel {
+            vertices: vec![SkinnedVertex { position: [0.0; 3], tex_coords: [0.0; 2], joints, weights: [1.0, 0.0, 0.0, 0.0] }],
+            indices: vec![0, 0, 0],
+            skeleton: arm(),
+            clips: vec![clip(vec![Channel { joint: 1, interpolation: Interpolation::Linear, times, values }])],
+        }
+    }
+
+    #[test]
+    fn valid_models_pass() {
+        assert!(model([0, 1, 0, 0], vec![0.0, 0.5, 2.0]).validate().is_ok());
+        assert!(model([1, 1, 1, 1], vec![]).validate().is_ok());
+    }
+
+    #[test]
+    fn bad_keyframe_times_are_rejected() {
+        for times in [vec![0.0, f32::NAN], vec![-1.0, 1.0], vec![0.0, 2.0, 1.0], vec![0.0, 1.0, 1.0], vec![0.0, f32::INFINITY]] {
+            let model = model([0; 4], times.clone());
+            assert!(model.validate().is_err(), "{:?} was accepted", times);
+        }
+    }
+
+    #[test]
+    fn missing_joints_are_rejected() {
+        let error = model([0, 0, 2, 0], vec![0.0]).validate().unwrap_err();
+        assert!(error.to_string().contains("only has 2 joints"), "{}", error);
+        let mut targets_missing_joint = model([0; 4], vec![0.0]);
+        targets_missing_joint.clips[0].channels[0].joint = 2;
+        assert!(targets_missing_joint.validate().is_err());
+        // Nor can a channel have more values than times
+        let mut extra_values = model([0; 4], vec![0.0]);
+        extra_values.clips[0].channels[0].values = ChannelValues::Scale(vec![cgmath::Vector3::new(1.0, 1.0, 1.0); 2]);
+        assert!(extra_values.validate().is_err());
+    }
+}
+
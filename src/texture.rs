@@ -1,4 +1,3 @@
-use image::GenericImageView;
 use anyhow::*;
 
 pub struct Texture {
@@ -63,9 +62,17 @@ impl Texture {
         img: &image::DynamicImage,
         label: Option<&str>
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        Ok(Self::from_rgba(device, queue, &img.to_rgba8(), label))
+    }
 
+    /// Uploads an image that's already been decoded, e.g. on another thread.
+    pub fn from_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &image::RgbaImage,
+        label: Option<&str>
+    ) -> Self {
+        let dimensions = rgba.dimensions();
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
@@ -91,7 +98,7 @@ impl Texture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            rgba,
             wgpu::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: Some(4 * dimensions.0),
@@ -113,7 +120,7 @@ impl Texture {
             }
         );
         
-        Ok(Self { texture, view, sampler })
+        Self { texture, view, sampler }
     }
 }
  
\ No newline at end of file
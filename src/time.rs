@@ -0,0 +1,147 @@
+/// Frame timing shared by the update loop and the shaders.
+///
+/// `tick` samples the real clock once per frame. Simulation code should
+/// drain `consume_fixed_step` so it advances in constant `fixed_timestep`
+/// increments regardless of the monitor's refresh rate, and render with
+/// `alpha` to interpolate between the last two simulated states.
+pub struct Time {
+    last_tick: Option<f64>,
+    delta: f32,
+    total: f64,
+    frame: u64,
+    fixed_timestep: f32,
+    accumulator: f32,
+}
+
+impl Time {
+    // Longest frame we'll try to catch up on. Anything slower (a breakpoint,
+    // a dragged window) would otherwise queue up hundreds of fixed steps.
+    const MAX_DELTA: f32 = 0.25;
+
+    pub fn new(fixed_timestep: f32) -> Self {
+        Self {
+            last_tick: None,
+            delta: 0.0,
+            total: 0.0,
+            frame: 0,
+            fixed_timestep,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Starts a new frame using the wall clock.
+    pub fn tick(&mut self) {
+        let now = now_seconds();
+        let delta = match self.last_tick {
+            Some(last) => (now - last) as f32,
+            None => 0.0,
+        };
+        self.last_tick = Some(now);
+        self.advance(delta);
+    }
+
+    /// Starts a new frame that lasted exactly `delta` seconds. Useful when
+    /// replaying recorded sessions or stepping the simulation by hand.
+    pub fn advance(&mut self, delta: f32) {
+        self.delta = delta.clamp(0.0, Self::MAX_DELTA);
+        self.total += self.delta as f64;
+        self.frame += 1;
+        self.accumulator += self.delta;
+    }
+
+    /// Returns true and removes one fixed step from the accumulator if a
+    /// full step is pending. Call in a loop until it returns false.
+    pub fn consume_fixed_step(&mut self) -> bool {
+        if self.accumulator >= self.fixed_timestep {
+            self.accumulator -= self.fixed_timestep;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Seconds since the previous frame.
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    /// Seconds since the first frame.
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    /// Number of frames started so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn fixed_timestep(&self) -> f32 {
+        self.fixed_timestep
+    }
+
+    /// How far we are between the last fixed step and the next one, in 0..1.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.fixed_timestep
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "wasm32")] {
+        // std::time::Instant isn't implemented on wasm32-unknown-unknown.
+        pub(crate) fn now_seconds() -> f64 {
+            web_sys::window()
+                .and_then(|window| window.performance())
+                .map(|performance| performance.now() / 1000.0)
+                .unwrap_or(0.0)
+        }
+    } else {
+        pub(crate) fn now_seconds() -> f64 {
+            use std::sync::OnceLock;
+            use std::time::Instant;
+            static START: OnceLock<Instant> = OnceLock::new();
+            START.get_or_init(Instant::now).elapsed().as_secs_f64()
+        }
+    }
+}
+
+// Mirrors `Globals` in the shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GlobalsUniform {
+    pub time: f32,
+    pub delta: f32,
+    pub resolution: [f32; 2],
+    pub frame: u32,
+    pub alpha: f32,
+    // Uniform buffers need to be 16 byte aligned for WebGL
+    _padding: [u32; 2],
+}
+
+impl GlobalsUniform {
+    pub fn new() -> Self {
+        Self {
+            time: 0.0,
+            delta: 0.0,
+            resolution: [0.0, 0.0],
+            frame: 0,
+            alpha: 0.0,
+            _padding: [0; 2],
+        }
+    }
+
+    pub fn update(&mut self, time: &Time, size: winit::dpi::PhysicalSize<u32>) {
+        // f32 loses sub-millisecond precision after a few hours, wrap it
+        // so shader animations stay smooth in long running sessions.
+        self.time = (time.total() % 3600.0) as f32;
+        self.delta = time.delta();
+        self.resolution = [size.width as f32, size.height as f32];
+        self.frame = time.frame() as u32;
+        self.alpha = time.alpha();
+    }
+}
+
+impl Default for GlobalsUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+use winit::event::{Touch, TouchPhase};
+
+type Point = cgmath::Vector2<f32>;
+
+struct TouchPoint {
+    start: Point,
+    position: Point,
+    start_time: f64,
+}
+
+/// Turns raw touch points into gestures: one-finger drags, two-finger pans,
+/// pinches and taps.
+///
+/// Feed it every `WindowEvent::Touch`, read the gestures during the frame
+/// and call `end_frame` afterwards, like `InputMap`. Taps are timed by the
+/// frame deltas passed to `end_frame` rather than the wall clock, so replayed
+/// input recognizes the same taps.
+pub struct TouchGestures {
+    /// Longest touch in seconds that still counts as a tap.
+    pub tap_max_duration: f64,
+    /// Furthest a finger may move in pixels during a tap.
+    pub tap_max_distance: f32,
+    touches: HashMap<u64, TouchPoint>,
+    clock: f64,
+    // The touch that would be a tap if released now
+    tap_candidate: Option<u64>,
+    drag: Point,
+    pan: Point,
+    pinch: f32,
+}
+
+impl TouchGestures {
+    pub fn new() -> Self {
+        Self {
+            tap_max_duration: 0.25,
+            tap_max_distance: 10.0,
+            touches: HashMap::new(),
+            clock: 0.0,
+            tap_candidate: None,
+            drag: Point::new(0.0, 0.0),
+            pan: Point::new(0.0, 0.0),
+            pinch: 1.0,
+        }
+    }
+
+    /// Updates gestures from a touch event. Returns true if it finished a
+    /// tap.
+    pub fn process(&mut self, touch: &Touch) -> bool {
+        let position = Point::new(touch.location.x as f32, touch.location.y as f32);
+        match touch.phase {
+            TouchPhase::Started => {
+                self.touches.insert(
+                    touch.id,
+                    TouchPoint {
+                        start: position,
+                        position,
+                        start_time: self.clock,
+                    },
+                );
+                // Putting down a second finger turns it into another gesture
+                self.tap_candidate = if self.touches.len() == 1 { Some(touch.id) } else { None };
+                false
+            }
+            TouchPhase::Moved => {
+                let before = self.pair();
+                let Some(point) = self.touches.get_mut(&touch.id) else {
+                    return false;
+                };
+                let delta = position - point.position;
+                point.position = position;
+                if (position - point.start).magnitude() > self.tap_max_distance && self.tap_candidate == Some(touch.id) {
+                    self.tap_candidate = None;
+                }
+                match (before, self.pair()) {
+                    (Some((a0, b0)), Some((a1, b1))) => {
+                        self.pan += (a1 + b1) / 2.0 - (a0 + b0) / 2.0;
+                        let (distance0, distance1) = ((b0 - a0).magnitude(), (b1 - a1).magnitude());
+                        // Fingers right on top of each other give no sensible ratio
+                        if distance0 > 1.0 && distance1 > 1.0 {
+                            self.pinch *= distance1 / distance0;
+                        }
+                    }
+                    _ if self.touches.len() == 1 => self.drag += delta,
+                    _ => {}
+                }
+                false
+            }
+            TouchPhase::Ended => {
+                let point = self.touches.remove(&touch.id);
+                let is_candidate = self.tap_candidate.take() == Some(touch.id);
+                point.is_some_and(|point| {
+                    is_candidate && self.clock - point.start_time <= self.tap_max_duration
+                })
+            }
+            TouchPhase::Cancelled => {
+                self.touches.remove(&touch.id);
+                self.tap_candidate = None;
+                false
+            }
+        }
+    }
+
+    // The two fingers that drive pans and pinches, picked by lowest id so
+    // the same pair is used from one event to the next.
+    fn pair(&self) -> Option<(Point, Point)> {
+        let mut ids = self.touches.keys().copied().collect::<Vec<_>>();
+        if ids.len() < 2 {
+            return None;
+        }
+        ids.sort_unstable();
+        Some((self.touches[&ids[0]].position, self.touches[&ids[1]].position))
+    }
+
+    /// Number of fingers currently down.
+    pub fn touch_count(&self) -> usize {
+        self.touches.len()
+    }
+
+    /// How far a single finger dragged in pixels this frame.
+    pub fn drag(&self) -> (f32, f32) {
+        (self.drag.x, self.drag.y)
+    }
+
+    /// How far the midpoint of two fingers moved in pixels this frame.
+    pub fn pan(&self) -> (f32, f32) {
+        (self.pan.x, self.pan.y)
+    }
+
+    /// How many times further apart two fingers moved this frame: above 1
+    /// when spreading them, below 1 when pinching.
+    pub fn pinch(&self) -> f32 {
+        self.pinch
+    }
+
+    /// Forgets every finger, e.g. when the window loses focus.
+    pub fn clear(&mut self) {
+        self.touches.clear();
+        self.tap_candidate = None;
+    }
+
+    /// Clears per frame gestures and advances the tap clock by `delta`
+    /// seconds.
+    pub fn end_frame(&mut self, delta: f32) {
+        self.clock += delta as f64;
+        self.drag = Point::new(0.0, 0.0);
+        self.pan = Point::new(0.0, 0.0);
+        self.pinch = 1.0;
+    }
+}
+
+impl Default for TouchGestures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(gestures: &mut TouchGestures, id: u64, phase: TouchPhase, x: f64, y: f64) -> bool {
+        gestures.process(&Touch {
+            // SAFETY: never passed back into winit
+            device_id: unsafe { winit::event::DeviceId::dummy() },
+            phase,
+            location: winit::dpi::PhysicalPosition::new(x, y),
+            force: None,
+            id,
+        })
+    }
+
+    #[test]
+    fn quick_short_touches_are_taps() {
+        let mut gestures = TouchGestures::new();
+        touch(&mut gestures, 1, TouchPhase::Started, 100.0, 100.0);
+        gestures.end_frame(0.1);
+        // Within the distance
+        touch(&mut gestures, 1, TouchPhase::Moved, 109.0, 100.0);
+        gestures.end_frame(0.1);
+        assert!(touch(&mut gestures, 1, TouchPhase::Ended, 109.0, 100.0));
+        assert_eq!(gestures.touch_count(), 0);
+    }
+
+    #[test]
+    fn moving_too_far_makes_a_drag() {
+        let mut gestures = TouchGestures::new();
+        touch(&mut gestures, 1, TouchPhase::Started, 100.0, 100.0);
+        touch(&mut gestures, 1, TouchPhase::Moved, 108.0, 100.0);
+        touch(&mut gestures, 1, TouchPhase::Moved, 108.0, 111.0);
+        assert_eq!(gestures.drag(), (8.0, 11.0));
+        // Coming back doesn't make it a tap again
+        touch(&mut gestures, 1, TouchPhase::Moved, 100.0, 100.0);
+        assert!(!touch(&mut gestures, 1, TouchPhase::Ended, 100.0, 100.0));
+    }
+
+    #[test]
+    fn holding_too_long_isnt_a_tap() {
+        let mut gestures = TouchGestures::new();
+        touch(&mut gestures, 1, TouchPhase::Started, 100.0, 100.0);
+        gestures.end_frame(0.25);
+        assert!(touch(&mut gestures, 1, TouchPhase::Ended, 100.0, 100.0));
+        touch(&mut gestures, 2, TouchPhase::Started, 100.0, 100.0);
+        gestures.end_frame(0.2);
+        gestures.end_frame(0.1);
+        assert!(!touch(&mut gestures, 2, TouchPhase::Ended, 100.0, 100.0));
+    }
+
+    #[test]
+    fn two_fingers_pinch_and_pan() {
+        let mut gestures = TouchGestures::new();
+        touch(&mut gestures, 1, TouchPhase::Started, 100.0, 100.0);
+        touch(&mut gestures, 2, TouchPhase::Started, 200.0, 100.0);
+        // Spreading from 100 to 200 pixels apart, in two steps
+        touch(&mut gestures, 2, TouchPhase::Moved, 250.0, 100.0);
+        touch(&mut gestures, 1, TouchPhase::Moved, 50.0, 100.0);
+        assert!((gestures.pinch() - 2.0).abs() < 1e-6, "{}", gestures.pinch());
+        // The midpoint stayed put
+        assert_eq!(gestures.pan(), (0.0, 0.0));
+        assert_eq!(gestures.drag(), (0.0, 0.0));
+        gestures.end_frame(0.016);
+
+        // Moving both by the same amount pans by it without pinching
+        touch(&mut gestures, 1, TouchPhase::Moved, 50.0, 130.0);
+        touch(&mut gestures, 2, TouchPhase::Moved, 250.0, 130.0);
+        let (x, y) = gestures.pan();
+        assert!(x.abs() < 1e-6 && (y - 30.0).abs() < 1e-6, "{:?}", gestures.pan());
+        assert!((gestures.pinch() - 1.0).abs() < 1e-6);
+        // Neither finger was a tap
+        assert!(!touch(&mut gestures, 1, TouchPhase::Ended, 50.0, 130.0));
+        assert!(!touch(&mut gestures, 2, TouchPhase::Ended, 250.0, 130.0));
+    }
+
+    #[test]
+    fn a_third_finger_doesnt_disturb_the_gesture() {
+        let mut gestures = TouchGestures::new();
+        touch(&mut gestures, 1, TouchPhase::Started, 100.0, 100.0);
+        touch(&mut gestures, 2, TouchPhase::Started, 200.0, 100.0);
+        touch(&mut gestures, 3, TouchPhase::Started, 150.0, 300.0);
+        assert_eq!(gestures.touch_count(), 3);
+        touch(&mut gestures, 3, TouchPhase::Moved, 400.0, 400.0);
+        assert_eq!(gestures.pan(), (0.0, 0.0));
+        assert_eq!(gestures.pinch(), 1.0);
+        assert_eq!(gestures.drag(), (0.0, 0.0));
+        // Lifting one of the pair hands the gesture to the others without a
+        // jump
+        touch(&mut gestures, 1, TouchPhase::Ended, 100.0, 100.0);
+        assert_eq!(gestures.pan(), (0.0, 0.0));
+        touch(&mut gestures, 2, TouchPhase::Moved, 210.0, 100.0);
+        assert_eq!(gestures.pan(), (5.0, 0.0));
+        // And none of them were taps, even the last one left
+        touch(&mut gestures, 2, TouchPhase::Ended, 210.0, 100.0);
+        assert!(!touch(&mut gestures, 3, TouchPhase::Ended, 400.0, 400.0));
+    }
+
+    #[test]
+    fn cancelled_touches_are_forgotten() {
+        let mut gestures = TouchGestures::new();
+        touch(&mut gestures, 1, TouchPhase::Started, 100.0, 100.0);
+        touch(&mut gestures, 1, TouchPhase::Cancelled, 100.0, 100.0);
+        assert_eq!(gestures.touch_count(), 0);
+        assert!(!touch(&mut gestures, 1, TouchPhase::Ended, 100.0, 100.0));
+        // Moves of a finger that isn't down do nothing
+        touch(&mut gestures, 1, TouchPhase::Moved, 150.0, 100.0);
+        assert_eq!(gestures.drag(), (0.0, 0.0));
+
+        // Cancelling one of two fingers ends the pinch, the other one drags
+        touch(&mut gestures, 1, TouchPhase::Started, 100.0, 100.0);
+        touch(&mut gestures, 2, TouchPhase::Started, 200.0, 100.0);
+        touch(&mut gestures, 2, TouchPhase::Cancelled, 200.0, 100.0);
+        touch(&mut gestures, 1, TouchPhase::Moved, 90.0, 100.0);
+        assert_eq!(gestures.pinch(), 1.0);
+        assert_eq!(gestures.drag(), (-10.0, 0.0));
+        assert!(!touch(&mut gestures, 1, TouchPhase::Ended, 90.0, 100.0));
+    }
+
+    #[test]
+    fn end_frame_clears_the_deltas() {
+        let mut gestures = TouchGestures::new();
+        touch(&mut gestures, 1, TouchPhase::Started, 100.0, 100.0);
+        touch(&mut gestures, 1, TouchPhase::Moved, 120.0, 100.0);
+        touch(&mut gestures, 2, TouchPhase::Started, 200.0, 100.0);
+        touch(&mut gestures, 2, TouchPhase::Moved, 300.0, 110.0);
+        assert_ne!(gestures.drag(), (0.0, 0.0));
+        assert_ne!(gestures.pan(), (0.0, 0.0));
+        assert_ne!(gestures.pinch(), 1.0);
+        gestures.end_frame(0.016);
+        assert_eq!(gestures.drag(), (0.0, 0.0));
+        assert_eq!(gestures.pan(), (0.0, 0.0));
+        assert_eq!(gestures.pinch(), 1.0);
+        // The fingers are still down
+        assert_eq!(gestures.touch_count(), 2);
+    }
+}
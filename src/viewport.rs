@@ -0,0 +1,390 @@
+use wgpu::util::DeviceExt;
+
+use crate::camera::{Camera, CameraUniform};
+use crate::pipeline::{PipelineBuilder, PipelineLayout};
+
+/// A rectangle of the render target in normalized coordinates, with (0, 0)
+/// at the top left and (1, 1) at the bottom right.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub const FULL: Viewport = Viewport { x: 0.0, y: 0.0, width: 1.0, height: 1.0 };
+
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// The viewport in pixels for a target of `size`, as (x, y, width, height).
+    /// Always at least one pixel wide and high so it can be used as a scissor rect.
+    pub fn to_physical(&self, size: winit::dpi::PhysicalSize<u32>) -> (u32, u32, u32, u32) {
+        let x = ((self.x * size.width as f32).round() as u32).min(size.width.saturating_sub(1));
+        let y = ((self.y * size.height as f32).round() as u32).min(size.height.saturating_sub(1));
+        let width = ((self.width * size.width as f32).round() as u32).min(size.width - x).max(1);
+        let height = ((self.height * size.height as f32).round() as u32).min(size.height - y).max(1);
+        (x, y, width, height)
+    }
+
+    pub fn aspect(&self, size: winit::dpi::PhysicalSize<u32>) -> f32 {
+        let (_, _, width, height) = self.to_physical(size);
+        width as f32 / height as f32
+    }
+
+    /// Converts a pixel position on the target into this viewport's
+    /// normalized device coordinates (-1..1, y up), at the pixel centre.
+    pub fn pixel_to_ndc(
+        &self,
+        size: winit::dpi::PhysicalSize<u32>,
+        position: winit::dpi::PhysicalPosition<f64>,
+    ) -> cgmath::Vector2<f32> {
+        let (x, y, width, height) = self.to_physical(size);
+        cgmath::Vector2::new(
+            (position.x.floor() as f32 + 0.5 - x as f32) / width as f32 * 2.0 - 1.0,
+            1.0 - (position.y.floor() as f32 + 0.5 - y as f32) / height as f32 * 2.0,
+        )
+    }
+
+    /// Whether `position` (in pixels) falls inside this viewport.
+    pub fn contains(&self, size: winit::dpi::PhysicalSize<u32>, position: winit::dpi::PhysicalPosition<f64>) -> bool {
+        let (x, y, width, height) = self.to_physical(size);
+        position.x >= x as f64
+            && position.y >= y as f64
+            && position.x < (x + width) as f64
+            && position.y < (y + height) as f64
+    }
+}
+
+/// What happens to a viewport's rectangle before its camera draws into it.
+/// The whole frame is cleared to `State::clear_color` and far depth first,
+/// so the first full screen view usually wants `Keep`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClearMode {
+    /// Draw over whatever is already there.
+    Keep,
+    /// Reset depth inside the viewport so it isn't occluded by earlier views.
+    Depth,
+    /// Fill the viewport with a color and reset depth.
+    ColorAndDepth(wgpu::Color),
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClearUniform {
+    color: [f32; 4],
+    depth: f32,
+    _padding: [f32; 3],
+}
+
+impl ClearUniform {
+    fn new(clear: ClearMode, camera: &Camera) -> Self {
+        let color = match clear {
+            ClearMode::ColorAndDepth(color) => [color.r as f32, color.g as f32, color.b as f32, color.a as f32],
+            _ => [0.0; 4],
+        };
+        Self {
+            color,
+            depth: camera.projection.depth_clear_value(),
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// Pipelines for clearing a single viewport. Render pass load ops always
+/// clear the whole attachment, so per viewport clears draw a full screen
+/// triangle clipped to the viewport instead.
+pub struct ViewClearer {
+    bind_group_layout: wgpu::BindGroupLayout,
+    color_and_depth_pipeline: wgpu::RenderPipeline,
+    depth_pipeline: wgpu::RenderPipeline,
+}
+
+impl ViewClearer {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> anyhow::Result<Self> {
+        let entries = [wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("clear_bind_group_layout"),
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("clear.wgsl"));
+        let layout = PipelineLayout::new(device, "Clear Pipeline Layout", &[(&bind_group_layout, &entries)]);
+        let create_pipeline = |label, write_mask| {
+            PipelineBuilder::from_module(label, &layout, &shader, format)
+                .write_mask(write_mask)
+                .cull_mode(None)
+                .depth_compare(wgpu::CompareFunction::Always)
+                .build(device)
+        };
+        Ok(Self {
+            color_and_depth_pipeline: create_pipeline("Clear Color Pipeline", wgpu::ColorWrites::ALL)?,
+            depth_pipeline: create_pipeline("Clear Depth Pipeline", wgpu::ColorWrites::empty())?,
+            bind_group_layout,
+        })
+    }
+}
+
+/// A camera together with where it draws on screen and the GPU resources
+/// needed to draw with it.
+pub struct View {
+    pub camera: Camera,
+    pub viewport: Viewport,
+    pub clear: ClearMode,
+    camera_uniform: CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    clear_buffer: wgpu::Buffer,
+    clear_bind_group: wgpu::BindGroup,
+}
+
+impl View {
+    /// `globals_buffer` is shared by every view and bound next to the camera.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        globals_buffer: &wgpu::Buffer,
+        clearer: &ViewClearer,
+        camera: Camera,
+        viewport: Viewport,
+        clear: ClearMode,
+    ) -> Self {
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera);
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: camera_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: globals_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("camera_bind_group"),
+        });
+        let clear_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Clear Buffer"),
+            contents: bytemuck::cast_slice(&[ClearUniform::new(clear, &camera)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let clear_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &clearer.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: clear_buffer.as_entire_binding(),
+            }],
+            label: Some("clear_bind_group"),
+        });
+        Self {
+            camera,
+            viewport,
+            clear,
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group,
+            clear_buffer,
+            clear_bind_group,
+        }
+    }
+
+    pub fn camera_bind_group(&self) -> &wgpu::BindGroup {
+        &self.camera_bind_group
+    }
+
+    /// Matches the camera's aspect ratio to the viewport's size on screen.
+    pub fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        self.camera.aspect = self.viewport.aspect(size);
+    }
+
+    /// Uploads the camera and clear settings; call once per frame before drawing.
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        self.camera_uniform.update_view_proj(&self.camera);
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+        queue.write_buffer(&self.clear_buffer, 0, bytemuck::cast_slice(&[ClearUniform::new(self.clear, &self.camera)]));
+    }
+
+    /// Restricts drawing to the viewport and applies its clear mode. Leaves
+    /// the clear pipeline bound, so set the scene pipeline afterwards.
+    ///
+    /// `frame_depth` is what the depth buffer was cleared to for the frame.
+    /// Depth written with the other convention means nothing to this
+    /// view's camera, so a `Keep` view whose camera uses the other one
+    /// resets depth in its viewport anyway.
+    pub fn begin<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        clearer: &'a ViewClearer,
+        size: winit::dpi::PhysicalSize<u32>,
+        frame_depth: f32,
+    ) {
+        let (x, y, width, height) = self.viewport.to_physical(size);
+        render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+        render_pass.set_scissor_rect(x, y, width, height);
+        let clear = match self.clear {
+            ClearMode::Keep if self.camera.projection.depth_clear_value() != frame_depth => ClearMode::Depth,
+            clear => clear,
+        };
+        let pipeline = match clear {
+            ClearMode::Keep => return,
+            ClearMode::Depth => &clearer.depth_pipeline,
+            ClearMode::ColorAndDepth(_) => &clearer.color_and_depth_pipeline,
+        };
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &self.clear_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Preset arrangements of views around the main camera.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ViewLayout {
+    Single,
+    /// Two cameras side by side, the second looking from the opposite side.
+    SplitScreen,
+    /// A top down map in the corner of the main view.
+    Minimap,
+    /// Editor style top, front and side orthographic views next to the main camera.
+    Quad,
+}
+
+impl ViewLayout {
+    pub fn next(self) -> Self {
+        match self {
+            ViewLayout::Single => ViewLayout::SplitScreen,
+            ViewLayout::SplitScreen => ViewLayout::Minimap,
+            ViewLayout::Minimap => ViewLayout::Quad,
+            ViewLayout::Quad => ViewLayout::Single,
+        }
+    }
+
+    /// The viewport of the main camera plus the cameras, viewports and clear
+    /// modes of every additional view in this layout.
+    pub fn arrange(self, main: &Camera) -> (Viewport, Vec<(Camera, Viewport, ClearMode)>) {
+        let ortho = |eye: cgmath::Point3<f32>, up: cgmath::Vector3<f32>| Camera {
+            eye,
+            target: main.target,
+            up,
+            aspect: main.aspect,
+            projection: crate::camera::Projection::Orthographic { height: 12.0 },
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let above = main.target + cgmath::Vector3::new(0.0, 20.0, 0.0);
+        match self {
+            ViewLayout::Single => (Viewport::FULL, Vec::new()),
+            ViewLayout::SplitScreen => {
+                let mirrored = Camera {
+                    eye: main.target - (main.eye - main.target),
+                    target: main.target,
+                    up: main.up,
+                    aspect: main.aspect,
+                    projection: main.projection,
+                    znear: main.znear,
+                    zfar: main.zfar,
+                };
+                (
+                    Viewport::new(0.0, 0.0, 0.5, 1.0),
+                    vec![(mirrored, Viewport::new(0.5, 0.0, 0.5, 1.0), ClearMode::Depth)],
+                )
+            }
+            ViewLayout::Minimap => (
+                Viewport::FULL,
+                vec![(
+                    ortho(above, -cgmath::Vector3::unit_z()),
+                    Viewport::new(0.7, 0.05, 0.25, 0.25),
+                    ClearMode::ColorAndDepth(wgpu::Color { r: 0.05, g: 0.05, b: 0.05, a: 1.0 }),
+                )],
+            ),
+            ViewLayout::Quad => {
+                let clear = ClearMode::ColorAndDepth(wgpu::Color { r: 0.15, g: 0.15, b: 0.15, a: 1.0 });
+                let y = cgmath::Vector3::unit_y();
+                (
+                    Viewport::new(0.5, 0.5, 0.5, 0.5),
+                    vec![
+                        (ortho(above, -cgmath::Vector3::unit_z()), Viewport::new(0.0, 0.0, 0.5, 0.5), clear),
+                        (ortho(main.target + cgmath::Vector3::new(0.0, 0.0, 20.0), y), Viewport::new(0.5, 0.0, 0.5, 0.5), clear),
+                        (ortho(main.target + cgmath::Vector3::new(20.0, 0.0, 0.0), y), Viewport::new(0.0, 0.5, 0.5, 0.5), clear),
+                    ],
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+    #[test]
+    fn split_viewports_tile_the_target() {
+        let size = PhysicalSize::new(801, 600);
+        let left = Viewport::new(0.0, 0.0, 0.5, 1.0);
+        let right = Viewport::new(0.5, 0.0, 0.5, 1.0);
+        assert_eq!(left.to_physical(size), (0, 0, 401, 600));
+        // Rounding the right half's width would run past the edge
+        assert_eq!(right.to_physical(size), (401, 0, 400, 600));
+
+        let quad = Viewport::new(0.5, 0.5, 0.5, 0.5);
+        assert_eq!(quad.to_physical(PhysicalSize::new(800, 600)), (400, 300, 400, 300));
+        assert_eq!(Viewport::FULL.aspect(PhysicalSize::new(800, 600)), 800.0 / 600.0);
+    }
+
+    #[test]
+    fn empty_targets_still_get_a_pixel() {
+        for size in [PhysicalSize::new(0, 0), PhysicalSize::new(0, 600), PhysicalSize::new(800, 0)] {
+            let (_, _, width, height) = Viewport::new(0.5, 0.5, 0.5, 0.5).to_physical(size);
+            assert!(width >= 1 && height >= 1);
+            Viewport::FULL.to_physical(size);
+        }
+        // A viewport squeezed to nothing is a pixel too
+        assert_eq!(Viewport::new(1.0, 0.0, 0.0, 1.0).to_physical(PhysicalSize::new(800, 600)), (799, 0, 1, 600));
+    }
+
+    #[test]
+    fn contains_includes_the_first_pixel_but_not_the_next_viewport() {
+        let size = PhysicalSize::new(800, 600);
+        let left = Viewport::new(0.0, 0.0, 0.5, 1.0);
+        let right = Viewport::new(0.5, 0.0, 0.5, 1.0);
+        let inside = |viewport: &Viewport, x, y| viewport.contains(size, PhysicalPosition::new(x, y));
+        assert!(inside(&left, 0.0, 0.0));
+        assert!(inside(&left, 399.9, 599.9));
+        assert!(!inside(&left, 400.0, 300.0));
+        assert!(inside(&right, 400.0, 300.0));
+        assert!(inside(&right, 799.5, 0.0));
+        assert!(!inside(&right, 800.0, 0.0));
+        assert!(!inside(&right, 500.0, 600.0));
+        assert!(!inside(&left, -0.5, 10.0));
+    }
+
+    #[test]
+    fn pixel_centres_map_into_ndc() {
+        let size = PhysicalSize::new(800, 600);
+        let right = Viewport::new(0.5, 0.0, 0.5, 1.0);
+        let corner = right.pixel_to_ndc(size, PhysicalPosition::new(400.0, 0.0));
+        assert!((corner.x - (-1.0 + 1.0 / 400.0)).abs() < 1e-6);
+        assert!((corner.y - (1.0 - 1.0 / 600.0)).abs() < 1e-6);
+        let last = right.pixel_to_ndc(size, PhysicalPosition::new(799.7, 599.2));
+        assert!((last.x - (1.0 - 1.0 / 400.0)).abs() < 1e-6);
+        assert!((last.y - (-1.0 + 1.0 / 600.0)).abs() < 1e-6);
+    }
+}
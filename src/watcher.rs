@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Notices files changing on disk by checking their modification times every
+/// `interval`, which is plenty for assets edited by hand and needs no
+/// platform specific notification API.
+///
+/// Files that don't exist yet are watched too and count as changed once
+/// they appear, as do files that get deleted.
+pub struct FileWatcher {
+    pub interval: Duration,
+    files: HashMap<PathBuf, Option<SystemTime>>,
+    next_check: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            files: HashMap::new(),
+            next_check: Instant::now() + interval,
+        }
+    }
+
+    /// Starts watching `path`. Watching it again does nothing.
+    pub fn watch(&mut self, path: &Path) {
+        if !self.files.contains_key(path) {
+            self.files.insert(path.to_path_buf(), modified(path));
+        }
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// The files that changed since the last check, if it's time to check
+    /// again. Call once a frame.
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        if now < self.next_check {
+            return Vec::new();
+        }
+        self.next_check = now + self.interval;
+        let mut changed = Vec::new();
+        for (path, last_modified) in self.files.iter_mut() {
+            let modified = modified(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
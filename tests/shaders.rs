@@ -0,0 +1,477 @@
+//! Validates every WGSL shader with naga, without a GPU.
+//!
+//! Shader mistakes otherwise only show up when the app runs and wgpu
+//! panics in `create_shader_module`, or worse, only on the backend someone
+//! else uses. Each shader is validated against the capabilities and limits
+//! of WebGL2 and of full WebGPU, its `vs_main` and `fs_main` entry points
+//! are checked to fit together, and it's cross-compiled to GLSL ES (what
+//! WebGL2 runs) and SPIR-V (what Vulkan runs).
+
+use std::path::{Path, PathBuf};
+
+use naga::valid::{Capabilities, ModuleInfo, ValidationFlags, Validator};
+use naga::{AddressSpace, Binding, BuiltIn, ShaderStage, TypeInner};
+use webgpu_starter::preprocessor::{ShaderDefs, ShaderLibrary};
+
+// Variants of the runtime shaders in assets/, with the definitions they're
+// made with and whether the app uses them on WebGL2. Files that aren't
+// listed are checked with no definitions.
+const VARIANTS: &[(&str, &[&str], bool)] = &[
+    ("shader.wgsl", &[], true),
+    // Only made when storage buffers are available, so never on WebGL2
+    ("shader.wgsl", &["SKINNED"], false),
+    // The debug views, see `DebugView::defs`
+    ("shader.wgsl", &["DEBUG_VIEW", "DEBUG_WIREFRAME"], false),
+    ("shader.wgsl", &["DEBUG_VIEW", "DEBUG_WIREFRAME", "BARYCENTRIC_WIREFRAME"], true),
+    ("shader.wgsl", &["DEBUG_VIEW", "DEBUG_NORMALS"], true),
+    ("shader.wgsl", &["DEBUG_VIEW", "DEBUG_UV_CHECKER"], true),
+    ("shader.wgsl", &["DEBUG_VIEW", "DEBUG_DEPTH"], true),
+    ("shader.wgsl", &["DEBUG_VIEW", "DEBUG_OVERDRAW"], true),
+    ("shader.wgsl", &["DEBUG_VIEW", "DEBUG_INSTANCE_ID"], true),
+    ("shader.wgsl", &["SKINNED", "DEBUG_VIEW", "DEBUG_WIREFRAME"], false),
+    ("shader.wgsl", &["SKINNED", "DEBUG_VIEW", "DEBUG_WIREFRAME", "BARYCENTRIC_WIREFRAME"], false),
+    ("shader.wgsl", &["SKINNED", "DEBUG_VIEW", "DEBUG_NORMALS"], false),
+    ("shader.wgsl", &["SKINNED", "DEBUG_VIEW", "DEBUG_UV_CHECKER"], false),
+    ("shader.wgsl", &["SKINNED", "DEBUG_VIEW", "DEBUG_DEPTH"], false),
+    ("shader.wgsl", &["SKINNED", "DEBUG_VIEW", "DEBUG_OVERDRAW"], false),
+    ("shader.wgsl", &["SKINNED", "DEBUG_VIEW", "DEBUG_INSTANCE_ID"], false),
+];
+
+// The shaders compiled into the app with `include_wgsl!` have no variants.
+const SHADER_DIRS: &[&str] = &["src", "assets"];
+
+struct Shader {
+    name: String,
+    source: String,
+    webgl2: bool,
+}
+
+struct Target {
+    name: &'static str,
+    capabilities: Capabilities,
+    limits: wgpu::Limits,
+}
+
+// What wgpu asks naga to validate against on each. WebGL2 has neither
+// cube array textures nor per-sample shading.
+fn targets() -> [Target; 2] {
+    [
+        Target {
+            name: "WebGL2",
+            capabilities: Capabilities::empty(),
+            limits: wgpu::Limits::downlevel_webgl2_defaults(),
+        },
+        Target {
+            name: "WebGPU",
+            capabilities: Capabilities::CUBE_ARRAY_TEXTURES | Capabilities::MULTISAMPLED_SHADING,
+            limits: wgpu::Limits::default(),
+        },
+    ]
+}
+
+fn wgsl_files(dir: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+    let mut files = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("Couldn't list {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "wgsl"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+// Every shader and shader variant in the repository, preprocessed.
+fn shaders() -> Vec<Shader> {
+    let mut library = ShaderLibrary::new();
+    let mut shaders = Vec::new();
+    for dir in SHADER_DIRS {
+        for path in wgsl_files(dir) {
+            let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let variants = VARIANTS
+                .iter()
+                .filter(|(name, ..)| *name == file_name && *dir == "assets")
+                .map(|(_, defs, webgl2)| (*defs, *webgl2))
+                .collect::<Vec<_>>();
+            let variants = if variants.is_empty() { vec![(&[][..], true)] } else { variants };
+            pollster::block_on(library.load(&path)).unwrap_or_else(|e| panic!("{:?}", e));
+            for (names, webgl2) in variants {
+                let defs = names.iter().fold(ShaderDefs::new(), |defs, name| defs.define(*name));
+                let preprocessed = library.preprocess(&path, &defs).unwrap_or_else(|e| panic!("{:?}", e));
+                shaders.push(Shader {
+                    name: format!("{}/{} {}", dir, file_name, defs.key()).trim_end().to_string(),
+                    source: preprocessed.source,
+                    webgl2,
+                });
+            }
+        }
+    }
+    shaders
+}
+
+fn parse(shader: &Shader) -> naga::Module {
+    naga::front::wgsl::parse_str(&shader.source)
+        .unwrap_or_else(|e| panic!("{}", e.emit_to_string_with_path(&shader.source, &shader.name)))
+}
+
+fn validate(shader: &Shader, module: &naga::Module, capabilities: Capabilities) -> Result<ModuleInfo, String> {
+    Validator::new(ValidationFlags::all(), capabilities)
+        .validate(module)
+        .map_err(|e| e.emit_to_string_with_path(&shader.source, &shader.name))
+}
+
+// Runs `check` on every shader, failing with everything that went wrong
+// rather than only the first problem.
+fn check_all(check: impl Fn(&Shader, &naga::Module, &mut Vec<String>)) {
+    let mut problems = Vec::new();
+    for shader in shaders() {
+        let module = parse(&shader);
+        check(&shader, &module, &mut problems);
+    }
+    assert!(problems.is_empty(), "\n{}", problems.join("\n"));
+}
+
+fn is_scene_shader(shader: &Shader) -> bool {
+    shader.name.starts_with("assets/")
+}
+
+#[test]
+fn every_shader_is_checked() {
+    let shaders = shaders();
+    for dir in SHADER_DIRS {
+        for path in wgsl_files(dir) {
+            let name = format!("{}/{}", dir, path.file_name().unwrap().to_string_lossy());
+            assert!(shaders.iter().any(|shader| shader.name.starts_with(&name)), "{} isn't checked", name);
+        }
+    }
+    assert!(shaders.iter().any(|shader| shader.name == "assets/shader.wgsl SKINNED=1"));
+}
+
+#[test]
+fn shaders_validate_on_webgl2_and_webgpu() {
+    check_all(|shader, module, problems| {
+        for target in targets() {
+            if target.name == "WebGL2" && !shader.webgl2 {
+                continue;
+            }
+            if let Err(e) = validate(shader, module, target.capabilities) {
+                problems.push(format!("{} on {}:\n{}", shader.name, target.name, e));
+            }
+        }
+    });
+}
+
+// The number of bindings of each kind an entry point uses.
+#[derive(Default)]
+struct BindingCounts {
+    uniform_buffers: u32,
+    storage_buffers: u32,
+    sampled_textures: u32,
+    storage_textures: u32,
+    samplers: u32,
+}
+
+fn binding_counts(module: &naga::Module, info: &ModuleInfo, entry_point: usize) -> BindingCounts {
+    let mut counts = BindingCounts::default();
+    for (handle, global) in module.global_variables.iter() {
+        if info.get_entry_point(entry_point)[handle].is_empty() {
+            continue;
+        }
+        match (global.space, &module.types[global.ty].inner) {
+            (AddressSpace::Uniform, _) => counts.uniform_buffers += 1,
+            (AddressSpace::Storage { .. }, _) => counts.storage_buffers += 1,
+            (AddressSpace::Handle, TypeInner::Sampler { .. }) => counts.samplers += 1,
+            (AddressSpace::Handle, TypeInner::Image { class: naga::ImageClass::Storage { .. }, .. }) => {
+                counts.storage_textures += 1
+            }
+            (AddressSpace::Handle, TypeInner::Image { .. }) => counts.sampled_textures += 1,
+            _ => {}
+        }
+    }
+    counts
+}
+
+// The user defined varyings of an entry point's inputs or outputs, as
+// (location, (scalar kind, components), interpolation) plus the built-ins.
+#[derive(Default)]
+struct Interface {
+    locations: Vec<(u32, (naga::ScalarKind, u32), Option<naga::Interpolation>)>,
+    built_ins: Vec<BuiltIn>,
+}
+
+impl Interface {
+    fn add(&mut self, module: &naga::Module, binding: Option<&Binding>, ty: naga::Handle<naga::Type>) {
+        match (binding, &module.types[ty].inner) {
+            (Some(Binding::BuiltIn(built_in)), _) => self.built_ins.push(*built_in),
+            (Some(Binding::Location { location, interpolation, .. }), inner) => {
+                let ty = match *inner {
+                    TypeInner::Scalar { kind, .. } => (kind, 1),
+                    TypeInner::Vector { size, kind, .. } => (kind, size as u32),
+                    // Validation only allows numbers and vectors of them
+                    _ => return,
+                };
+                self.locations.push((*location, ty, *interpolation))
+            }
+            (None, TypeInner::Struct { members, .. }) => {
+                for member in members {
+                    self.add(module, member.binding.as_ref(), member.ty);
+                }
+            }
+            (None, _) => {}
+        }
+    }
+
+    fn inputs(module: &naga::Module, entry_point: &naga::EntryPoint) -> Self {
+        let mut interface = Self::default();
+        for argument in entry_point.function.arguments.iter() {
+            interface.add(module, argument.binding.as_ref(), argument.ty);
+        }
+        interface
+    }
+
+    fn outputs(module: &naga::Module, entry_point: &naga::EntryPoint) -> Self {
+        let mut interface = Self::default();
+        if let Some(result) = entry_point.function.result.as_ref() {
+            interface.add(module, result.binding.as_ref(), result.ty);
+        }
+        interface
+    }
+
+    fn components(&self) -> u32 {
+        self.locations.iter().map(|(_, (_, components), _)| components).sum()
+    }
+}
+
+// Checks the varyings passed between the stages against
+// `max_inter_stage_shader_components`, counting them like wgpu does: every
+// scalar component of a vertex output or fragment input, built-ins aside.
+fn check_inter_stage_components(
+    module: &naga::Module,
+    entry_point: &naga::EntryPoint,
+    limits: &wgpu::Limits,
+) -> Result<(), String> {
+    let (interface, verb) = match entry_point.stage {
+        ShaderStage::Vertex => (Interface::outputs(module, entry_point), "outputs"),
+        ShaderStage::Fragment => (Interface::inputs(module, entry_point), "reads"),
+        ShaderStage::Compute => return Ok(()),
+    };
+    let components = interface.components();
+    if components > limits.max_inter_stage_shader_components {
+        return Err(format!(
+            "{} {} {} components, only {} are allowed",
+            entry_point.name, verb, components, limits.max_inter_stage_shader_components
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn shaders_fit_in_the_limits_of_webgl2_and_webgpu() {
+    check_all(|shader, module, problems| {
+        for target in targets() {
+            if target.name == "WebGL2" && !shader.webgl2 {
+                continue;
+            }
+            let Ok(info) = validate(shader, module, target.capabilities) else {
+                // Reported by shaders_validate_on_webgl2_and_webgpu
+                continue;
+            };
+            let limits = &target.limits;
+            let mut problem = |message: String| problems.push(format!("{} on {}: {}", shader.name, target.name, message));
+            for (_, global) in module.global_variables.iter() {
+                if let Some(binding) = global.binding.as_ref().filter(|binding| binding.group >= limits.max_bind_groups) {
+                    problem(format!("uses bind group {}, past the {} available", binding.group, limits.max_bind_groups));
+                }
+            }
+            for (index, entry_point) in module.entry_points.iter().enumerate() {
+                let counts = binding_counts(module, &info, index);
+                let stage_limits = [
+                    ("uniform buffers", counts.uniform_buffers, limits.max_uniform_buffers_per_shader_stage),
+                    ("storage buffers", counts.storage_buffers, limits.max_storage_buffers_per_shader_stage),
+                    ("sampled textures", counts.sampled_textures, limits.max_sampled_textures_per_shader_stage),
+                    ("storage textures", counts.storage_textures, limits.max_storage_textures_per_shader_stage),
+                    ("samplers", counts.samplers, limits.max_samplers_per_shader_stage),
+                ];
+                for (kind, count, limit) in stage_limits {
+                    if count > limit {
+                        problem(format!("{} uses {} {}, only {} are allowed", entry_point.name, count, kind, limit));
+                    }
+                }
+                if let Err(e) = check_inter_stage_components(module, entry_point, limits) {
+                    problem(e);
+                }
+                match entry_point.stage {
+                    ShaderStage::Vertex => {
+                        let inputs = Interface::inputs(module, entry_point);
+                        for (location, ..) in inputs.locations.iter() {
+                            if *location >= limits.max_vertex_attributes {
+                                problem(format!(
+                                    "{} reads vertex attribute {}, past the {} available",
+                                    entry_point.name, location, limits.max_vertex_attributes
+                                ));
+                            }
+                        }
+                    }
+                    ShaderStage::Fragment => {}
+                    ShaderStage::Compute => {
+                        if limits.max_compute_invocations_per_workgroup == 0 {
+                            problem(format!("{} is a compute shader, which isn't available", entry_point.name));
+                        }
+                    }
+                }
+            }
+        }
+    });
+}
+
+// A vertex shader passing seven vec4s and `last` on to the fragment shader.
+fn varyings_shader(last: &str) -> naga::Module {
+    let source = format!(
+        "struct VertexOutput {{
+            @builtin(position) position: vec4<f32>,
+            @location(0) a: vec4<f32>,
+            @location(1) b: vec4<f32>,
+            @location(2) c: vec4<f32>,
+            @location(3) d: vec4<f32>,
+            @location(4) e: vec4<f32>,
+            @location(5) f: vec4<f32>,
+            @location(6) g: vec4<f32>,
+            @location(7) last: {},
+        }};
+        @vertex
+        fn vs_main() -> VertexOutput {{
+            var out: VertexOutput;
+            return out;
+        }}",
+        last
+    );
+    naga::front::wgsl::parse_str(&source).unwrap()
+}
+
+#[test]
+fn inter_stage_components_are_counted_like_wgpu() {
+    let webgl2 = wgpu::Limits::downlevel_webgl2_defaults();
+    assert_eq!(webgl2.max_inter_stage_shader_components, 31);
+    // 28 + 3 fits exactly, the position doesn't count
+    let module = varyings_shader("vec3<f32>");
+    assert_eq!(Interface::outputs(&module, &module.entry_points[0]).components(), 31);
+    assert!(check_inter_stage_components(&module, &module.entry_points[0], &webgl2).is_ok());
+    // One more is too many
+    let module = varyings_shader("vec4<f32>");
+    let error = check_inter_stage_components(&module, &module.entry_points[0], &webgl2).unwrap_err();
+    assert!(error.contains("outputs 32 components, only 31"), "{}", error);
+    assert!(check_inter_stage_components(&module, &module.entry_points[0], &wgpu::Limits::default()).is_ok());
+}
+
+#[test]
+fn entry_points_have_the_expected_interfaces() {
+    check_all(|shader, module, problems| {
+        // Files only included by others have no entry points of their own
+        if module.entry_points.is_empty() {
+            return;
+        }
+        let mut problem = |message: String| problems.push(format!("{}: {}", shader.name, message));
+        let find = |name: &str, stage: ShaderStage| {
+            module.entry_points.iter().find(|entry_point| entry_point.name == name && entry_point.stage == stage)
+        };
+        let (Some(vertex), Some(fragment)) = (find("vs_main", ShaderStage::Vertex), find("fs_main", ShaderStage::Fragment))
+        else {
+            problem("needs a @vertex fn vs_main and a @fragment fn fs_main".to_string());
+            return;
+        };
+        let vertex_outputs = Interface::outputs(module, vertex);
+        if !vertex_outputs.built_ins.iter().any(|built_in| matches!(built_in, BuiltIn::Position { .. })) {
+            problem("vs_main doesn't output @builtin(position)".to_string());
+        }
+        if is_scene_shader(shader) {
+            // Scene shaders are drawn with Vertex or SkinnedVertex plus
+            // InstanceRaw, see Vertex::desc and InstanceRaw::desc
+            let inputs = Interface::inputs(module, vertex);
+            for location in [0, 1, 5, 6, 7, 8] {
+                if !inputs.locations.iter().any(|(input, ..)| *input == location) {
+                    problem(format!("vs_main doesn't read vertex attribute {}", location));
+                }
+            }
+        }
+        let fragment_outputs = Interface::outputs(module, fragment);
+        if !fragment_outputs.locations.iter().any(|(location, ..)| *location == 0) {
+            problem("fs_main doesn't output @location(0)".to_string());
+        }
+        for (location, ty, interpolation) in Interface::inputs(module, fragment).locations {
+            match vertex_outputs.locations.iter().find(|(output, ..)| *output == location) {
+                None => problem(format!("fs_main reads @location({}), which vs_main doesn't output", location)),
+                Some((_, output_ty, output_interpolation)) => {
+                    if *output_ty != ty {
+                        problem(format!(
+                            "@location({}) is a {:?} out of vs_main but a {:?} into fs_main",
+                            location, output_ty, ty
+                        ));
+                    }
+                    if *output_interpolation != interpolation {
+                        problem(format!(
+                            "@location({}) is interpolated {:?} out of vs_main but {:?} into fs_main",
+                            location, output_interpolation, interpolation
+                        ));
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn shaders_compile_to_glsl_es_for_webgl2() {
+    use naga::back::glsl;
+
+    check_all(|shader, module, problems| {
+        if !shader.webgl2 {
+            return;
+        }
+        let Ok(info) = validate(shader, module, Capabilities::empty()) else {
+            return;
+        };
+        // As wgpu's GL backend sets them up
+        let options = glsl::Options {
+            version: glsl::Version::Embedded {
+                version: 300,
+                is_webgl: true,
+            },
+            writer_flags: glsl::WriterFlags::ADJUST_COORDINATE_SPACE,
+            ..Default::default()
+        };
+        for entry_point in module.entry_points.iter() {
+            let pipeline_options = glsl::PipelineOptions {
+                shader_stage: entry_point.stage,
+                entry_point: entry_point.name.clone(),
+                multiview: None,
+            };
+            let mut glsl = String::new();
+            let result = glsl::Writer::new(
+                &mut glsl,
+                module,
+                &info,
+                &options,
+                &pipeline_options,
+                naga::proc::BoundsCheckPolicies::default(),
+            )
+            .and_then(|mut writer| writer.write());
+            if let Err(e) = result {
+                problems.push(format!("{} {} to GLSL ES: {}", shader.name, entry_point.name, e));
+            }
+        }
+    });
+}
+
+#[test]
+fn shaders_compile_to_spirv() {
+    use naga::back::spv;
+
+    check_all(|shader, module, problems| {
+        let capabilities = targets()[1].capabilities;
+        let Ok(info) = validate(shader, module, capabilities) else {
+            return;
+        };
+        if let Err(e) = spv::write_vec(module, &info, &spv::Options::default(), None) {
+            problems.push(format!("{} to SPIR-V: {}", shader.name, e));
+        }
+    });
+}